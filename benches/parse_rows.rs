@@ -0,0 +1,77 @@
+// Benchmark of reading transaction rows, comparing deserialising a newly allocated record per row against
+// parsing fields by hand from one record reused across rows (`fast_parse`). The allocations made by a run
+// under each are counted and printed before timing, as the fast path is there to avoid them.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use transaction_engine::client::ClientDb;
+use transaction_engine::config::EngineConfig;
+use transaction_engine::transaction::{apply_transactions, TransactionDb};
+
+// Allocator counting every allocation made, so the allocations of a run can be compared.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Deposits spread over a few clients, as read from a file.
+fn transaction_rows() -> String {
+    let mut data = String::from("type,client,tx,amount\n");
+    for row in 1..=10_000 {
+        data.push_str(&format!(
+            "deposit,{},{},{}.{:04}\n",
+            row % 100,
+            row,
+            row,
+            row % 10_000
+        ));
+    }
+    data
+}
+
+fn run(data: &str, config: &EngineConfig) {
+    let rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    let mut transaction_db = TransactionDb::init();
+    let mut client_db = ClientDb::init();
+    let stats = apply_transactions(vec![rdr], &mut transaction_db, &mut client_db, config, None);
+    black_box(stats.unwrap());
+}
+
+fn bench_parse_rows(c: &mut Criterion) {
+    let data = transaction_rows();
+    let mut group = c.benchmark_group("parse_rows");
+    for (name, fast_parse) in [("deserialise", false), ("fast_parse", true)] {
+        let config = EngineConfig {
+            fast_parse,
+            ..EngineConfig::default()
+        };
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        run(&data, &config);
+        println!(
+            "parse_rows/{}: {} allocations for 10000 rows",
+            name,
+            ALLOCATIONS.load(Ordering::Relaxed) - before
+        );
+        group.bench_function(name, |b| b.iter(|| run(&data, &config)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse_rows);
+criterion_main!(benches);
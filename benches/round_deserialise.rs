@@ -0,0 +1,69 @@
+// Benchmark of reading transaction amounts, comparing the original f64 deserialise-and-discard-error
+// implementation of `round_deserialise` against the current one, for populated amounts and for the empty
+// amounts of dispute/resolve/chargeback rows.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Deserializer};
+use transaction_engine::amount;
+
+// The original implementation, kept here as the baseline.
+fn legacy_round_deserialise<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let x: Result<f64, _> = Deserialize::deserialize(deserializer);
+    match x {
+        Ok(value) => Ok(Some((value * 10_000.0).round() / 10_000.0)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+struct Current {
+    #[serde(deserialize_with = "amount::round_deserialise")]
+    amount: Option<amount::Amount>,
+}
+
+#[derive(Deserialize)]
+struct Legacy {
+    #[serde(deserialize_with = "legacy_round_deserialise")]
+    amount: Option<f64>,
+}
+
+// Amount records as read from a file, either all populated or all empty as for dispute rows.
+fn amount_records(populated: bool) -> Vec<csv::StringRecord> {
+    (0..10_000)
+        .map(|row| match populated {
+            true => csv::StringRecord::from(vec![format!("{}.{:04}", row, row % 10_000)]),
+            false => csv::StringRecord::from(vec![""]),
+        })
+        .collect()
+}
+
+fn bench_round_deserialise(c: &mut Criterion) {
+    let headers = csv::StringRecord::from(vec!["amount"]);
+    for (name, populated) in [("populated", true), ("empty", false)] {
+        let records = amount_records(populated);
+        let mut group = c.benchmark_group(format!("round_deserialise/{}", name));
+        group.bench_function("legacy", |b| {
+            b.iter(|| {
+                for record in &records {
+                    let row: Legacy = record.deserialize(Some(&headers)).unwrap();
+                    black_box(row.amount);
+                }
+            })
+        });
+        group.bench_function("current", |b| {
+            b.iter(|| {
+                for record in &records {
+                    let row: Current = record.deserialize(Some(&headers)).unwrap();
+                    black_box(row.amount);
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_round_deserialise);
+criterion_main!(benches);
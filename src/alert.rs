@@ -0,0 +1,122 @@
+use crate::transaction::TransactionType;
+use std::fmt;
+use std::time::Duration;
+
+// ------------------------------------------------------------------------------------------------
+// ----------------------------------------- ALERT STRUCT -----------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// A risk event raised while applying transactions, with the client and row of the csv it concerns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub client_id: u16,
+    pub line: u64,
+    pub kind: AlertKind,
+}
+
+// The condition an alert was raised for, with the details of what triggered it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertKind {
+    // The client made more timestamped transactions within the burst window than the threshold allows.
+    Burst {
+        transactions: usize,
+        window: Duration,
+        threshold: usize,
+    },
+    // A deposit/withdrawal was applied to an account that was locked by a chargeback and later unlocked.
+    Reactivated {
+        transaction_type: TransactionType,
+    },
+}
+
+impl AlertKind {
+    // Lowercase name of the condition, e.g. for routing alerts by type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::Burst { .. } => "burst",
+            AlertKind::Reactivated { .. } => "reactivated",
+        }
+    }
+}
+
+// Alerts are written as they have always been printed to stderr.
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            AlertKind::Burst {
+                transactions,
+                window,
+                threshold,
+            } => write!(
+                f,
+                "alert: line {}: client {} made {} transactions within {:?}, over the burst threshold of {}",
+                self.line, self.client_id, transactions, window, threshold
+            ),
+            AlertKind::Reactivated { transaction_type } => write!(
+                f,
+                "warning: line {}: {} applied to client {}, which was previously locked",
+                self.line,
+                transaction_type.as_str(),
+                self.client_id
+            ),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------------------ ALERT SINKS -----------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Destination for the alerts of a run, implemented by library consumers to route alerts to their own
+// systems, e.g. a webhook, a log or a file.
+pub trait AlertSink {
+    fn alert(&mut self, alert: &Alert);
+}
+
+// Default sink, printing every alert to stderr.
+pub struct StderrAlertSink;
+
+impl AlertSink for StderrAlertSink {
+    fn alert(&mut self, alert: &Alert) {
+        eprintln!("{}", alert);
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_display_as_printed() {
+        // Make sure structured alerts are written exactly as the plain text alerts they replaced.
+        let burst = Alert {
+            client_id: 1,
+            line: 5,
+            kind: AlertKind::Burst {
+                transactions: 4,
+                window: Duration::from_secs(60),
+                threshold: 3,
+            },
+        };
+        let reactivated = Alert {
+            client_id: 2,
+            line: 8,
+            kind: AlertKind::Reactivated {
+                transaction_type: TransactionType::Withdrawal,
+            },
+        };
+        assert_eq!(
+            burst.to_string(),
+            "alert: line 5: client 1 made 4 transactions within 60s, over the burst threshold of 3"
+        );
+        assert_eq!(
+            reactivated.to_string(),
+            "warning: line 8: withdrawal applied to client 2, which was previously locked"
+        );
+        assert_eq!(burst.kind.as_str(), "burst");
+    }
+}
@@ -0,0 +1,555 @@
+use crate::config::{RoundingMode, MAX_PRECISION};
+use rust_decimal::Decimal;
+use serde::de::{self, Visitor};
+use serde::Deserializer;
+use std::fmt;
+use std::str::FromStr;
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------------- AMOUNT STRUCT --------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Amounts are read with 4 decimal place precision, unless another precision is configured.
+pub const AMOUNT_DECIMAL_PLACES: u32 = 4;
+
+// Round an amount to 4.d.p, with halves rounded away from zero.
+pub fn round_amount(value: Decimal) -> Decimal {
+    round_amount_with(value, RoundingMode::HalfUp)
+}
+
+// Round an amount to 4.d.p with the given rounding mode.
+pub fn round_amount_with(value: Decimal, rounding: RoundingMode) -> Decimal {
+    rounding.round(value, AMOUNT_DECIMAL_PLACES)
+}
+
+// Parse a decimal amount exactly, e.g. 10.5 or 1e3. Returns None for malformed, non-finite or out of
+// range amounts.
+fn parse_decimal(field: &str) -> Option<Decimal> {
+    if !is_decimal_literal(field) {
+        return None;
+    }
+    Decimal::from_str(field)
+        .or_else(|_| Decimal::from_scientific(field))
+        .ok()
+}
+
+// True if the field is written as a plain decimal: an optional sign, digits with an optional decimal point
+// (either side of which may be empty, but not both), and optionally an exponent, e.g. 10, -0.5, .5, 5. or
+// 1.5e3. Exponents are accepted deliberately, as they always have been, so 1e2 is an amount of 100. The
+// decimal parser is more lenient (accepting e.g. `1_000`), so anything else is checked for first and
+// treated as malformed.
+fn is_decimal_literal(field: &str) -> bool {
+    // Length of the run of ascii digits at the start of the text.
+    fn digits(text: &str) -> usize {
+        text.bytes().take_while(u8::is_ascii_digit).count()
+    }
+    let unsigned = field.strip_prefix(['+', '-']).unwrap_or(field);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let is_digits = |text: &str| !text.is_empty() && digits(text) == text.len();
+    let is_optional_digits = |text: &str| digits(text) == text.len();
+    let has_digits = !whole.is_empty() || fraction.is_some_and(|fraction| !fraction.is_empty());
+    has_digits
+        && is_optional_digits(whole)
+        && fraction.is_none_or(is_optional_digits)
+        && exponent
+            .is_none_or(|exponent| is_digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)))
+}
+
+// True if the raw amount field is a non-zero amount too small to survive rounding to the precision, e.g.
+// 0.00001 at 4.d.p.
+pub fn is_subprecision(field: &str, rounding: RoundingMode, decimal_places: u32) -> bool {
+    parse_decimal(field)
+        .is_some_and(|value| !value.is_zero() && rounding.round(value, decimal_places).is_zero())
+}
+
+// Difference between a raw amount field and the amount it rounds to at the precision, e.g. 0.00004 for
+// 1.00004 at 4.d.p. Zero for an empty or malformed field.
+pub fn rounding_residue(field: &str, rounding: RoundingMode, decimal_places: u32) -> Decimal {
+    parse_decimal(field).map_or(Decimal::ZERO, |value| {
+        value - rounding.round(value, decimal_places)
+    })
+}
+
+// Significant digits of a raw amount field, ignoring leading zeros and trailing zeros after the decimal
+// point, e.g. 18 for 12345678901234.5678 and 1 for 0.0001. None for an empty or malformed field.
+pub fn significant_digits(field: &str) -> Option<u32> {
+    parse_decimal(field).map(|value| {
+        let mantissa = value.normalize().mantissa().unsigned_abs();
+        mantissa.checked_ilog10().map_or(0, |digits| digits + 1)
+    })
+}
+
+// Reason to reject a raw amount field with more significant digits than the maximum, however it rounds.
+pub fn excess_digits(field: &str, max_digits: u32) -> Option<AmountError> {
+    significant_digits(field)
+        .filter(|digits| *digits > max_digits)
+        .map(|_| AmountError::TooManyDigits(field.to_string(), max_digits))
+}
+
+// Monetary amount of a transaction. Always non-negative and held to at most 8.d.p, the finest precision
+// amounts can be read with, so every amount applied to a client record has already been validated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Amount(Decimal);
+
+// Reasons a value cannot be used as an amount.
+#[derive(Debug, PartialEq)]
+pub enum AmountError {
+    Malformed(String),
+    Negative(Decimal),
+    TooPrecise(Decimal),
+    TooManyDigits(String, u32),
+}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(Decimal::ZERO);
+
+    // Validate a decimal as an amount. Negative zero is accepted as zero.
+    pub fn new(value: Decimal) -> Result<Self, AmountError> {
+        if value.is_zero() {
+            Ok(Amount::ZERO)
+        } else if value.is_sign_negative() {
+            Err(AmountError::Negative(value))
+        } else if value.normalize().scale() > MAX_PRECISION {
+            Err(AmountError::TooPrecise(value))
+        } else {
+            Ok(Amount(value))
+        }
+    }
+
+    // Parse an amount field, e.g. 10.5 or 1e3, rounding it to 4.d.p.
+    pub fn parse(field: &str) -> Result<Self, AmountError> {
+        Amount::parse_with(field, RoundingMode::HalfUp)
+    }
+
+    // Parse an amount field, rounding it to 4.d.p with the given rounding mode.
+    pub fn parse_with(field: &str, rounding: RoundingMode) -> Result<Self, AmountError> {
+        Amount::parse_with_precision(field, rounding, AMOUNT_DECIMAL_PLACES)
+    }
+
+    // Parse an amount field, rounding it to the given number of decimal places (at most 8) with the given
+    // rounding mode.
+    pub fn parse_with_precision(
+        field: &str,
+        rounding: RoundingMode,
+        decimal_places: u32,
+    ) -> Result<Self, AmountError> {
+        let value =
+            parse_decimal(field).ok_or_else(|| AmountError::Malformed(field.to_string()))?;
+        Amount::new(rounding.round(value, decimal_places.min(MAX_PRECISION)))
+    }
+
+    pub fn value(self) -> Decimal {
+        self.0
+    }
+
+    // Sum of two amounts, or None if it overflows.
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    // Difference of two amounts, or None if it would be negative.
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        (self.0 >= other.0).then(|| Amount(self.0 - other.0))
+    }
+}
+
+// Amounts are displayed without trailing zeros, e.g. 12 rather than 12.0000.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.normalize())
+    }
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::Malformed(field) => write!(f, "{:?} is not a decimal amount", field),
+            AmountError::Negative(value) => write!(f, "amount {} is negative", value.normalize()),
+            AmountError::TooPrecise(value) => write!(
+                f,
+                "amount {} has more than {} decimal places",
+                value.normalize(),
+                MAX_PRECISION
+            ),
+            AmountError::TooManyDigits(field, max_digits) => write!(
+                f,
+                "amount {} has more than {} significant digits",
+                field, max_digits
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+// ------------------------------------------------------------------------------------------------
+// -------------------------------- AMOUNT DESERIALISER -------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Custom Deserialiser for the optional amount field, parsed with `Amount::parse` so it is rounded to 4.d.p.
+// An empty (or otherwise invalid, e.g. negative) field has no amount, as for dispute/resolve/chargeback rows.
+// An invalid non-empty field is kept apart from an empty one when the row is parsed, so the row can be rejected.
+// The raw field is visited and parsed directly as a decimal, so the amount is exactly the one written in
+// the file rather than its nearest f64.
+pub fn round_deserialise<'de, D>(deserializer: D) -> Result<Option<Amount>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(deserializer.deserialize_str(AmountVisitor).unwrap_or(None))
+}
+
+// Custom Deserialiser for a balance column of a client snapshot, parsed exactly as a decimal and left for
+// the snapshot loader to round to the configured precision.
+// Unlike transaction amounts, a balance may be negative and a malformed balance is an error.
+pub fn balance_deserialise<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_str(BalanceVisitor)
+}
+
+// Custom Deserialiser for a non-negative running total of a client snapshot, e.g. total_deposited.
+// A malformed or negative total is an error.
+pub fn amount_deserialise<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = deserializer.deserialize_str(BalanceVisitor)?;
+    Amount::new(value).map_err(de::Error::custom)
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Option<Amount>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal amount or an empty field")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(Amount::parse(value).ok())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        let value = Decimal::try_from(value).ok();
+        Ok(value.and_then(|value| Amount::new(round_amount(value)).ok()))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(Amount::new(Decimal::from(value)).ok())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(Amount::new(Decimal::from(value)).ok())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+}
+
+struct BalanceVisitor;
+
+impl<'de> Visitor<'de> for BalanceVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal balance")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        parse_decimal(value).ok_or_else(|| E::custom("expected a decimal balance"))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        Decimal::try_from(value).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(Decimal::from(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(Decimal::from(value))
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde::Deserialize;
+
+    // The original implementation, deserialising an f64 and treating any error as no amount.
+    fn legacy_round_deserialise<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let x: Result<f64, _> = Deserialize::deserialize(deserializer);
+        match x {
+            Ok(value) => Ok(Some((value * 10_000.0).round() / 10_000.0)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Amounts {
+        #[serde(deserialize_with = "round_deserialise")]
+        new: Option<Amount>,
+        #[serde(deserialize_with = "legacy_round_deserialise")]
+        legacy: Option<f64>,
+    }
+
+    // Read each field as an amount with both implementations.
+    fn read_amounts(fields: &[String]) -> Result<Vec<Amounts>, csv::Error> {
+        let rows: String = fields
+            .iter()
+            .map(|field| format!("{},{}\n", field, field))
+            .collect();
+        let data = format!("new,legacy\n{}", rows);
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes())
+            .deserialize()
+            .collect()
+    }
+
+    #[test]
+    fn significant_digits_counted() {
+        // Leading zeros and trailing zeros after the decimal point are not significant, while zeros before
+        // the decimal point are. Only fields over the maximum have excess digits.
+        assert_eq!(significant_digits("12345678901234.5678"), Some(18));
+        assert_eq!(significant_digits("0.0001"), Some(1));
+        assert_eq!(significant_digits("100.50"), Some(4));
+        assert_eq!(significant_digits("1e3"), Some(4));
+        assert_eq!(significant_digits("abc"), None);
+        assert_eq!(excess_digits("1234.5678", 8), None);
+        assert_eq!(
+            excess_digits("1234.56789", 8),
+            Some(AmountError::TooManyDigits("1234.56789".to_string(), 8))
+        );
+    }
+
+    #[test]
+    fn rounding_modes_applied() -> Result<(), AmountError> {
+        // A half is rounded up under HalfUp, to the even neighbour under HalfEven, and dropped under Truncate.
+        assert_eq!(
+            round_amount_with(dec!(1.23455), RoundingMode::HalfUp),
+            dec!(1.2346)
+        );
+        assert_eq!(
+            round_amount_with(dec!(1.23455), RoundingMode::Truncate),
+            dec!(1.2345)
+        );
+        assert_eq!(
+            round_amount_with(dec!(1.23465), RoundingMode::HalfEven),
+            dec!(1.2346)
+        );
+        assert_eq!(
+            round_amount_with(dec!(1.23455), RoundingMode::HalfEven),
+            dec!(1.2346)
+        );
+        assert_eq!(
+            Amount::parse_with("1.23455", RoundingMode::Truncate)?,
+            Amount::new(dec!(1.2345))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subprecision_amounts_detected() {
+        // Only non-zero amounts that round to zero are sub-precision.
+        assert!(is_subprecision(
+            "0.00001",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        assert!(is_subprecision(
+            "-0.00004",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        assert!(!is_subprecision(
+            "0.00005",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        assert!(!is_subprecision(
+            "0",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        assert!(!is_subprecision(
+            "0.0000",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        assert!(!is_subprecision(
+            "12.5",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        assert!(!is_subprecision(
+            "",
+            RoundingMode::HalfUp,
+            AMOUNT_DECIMAL_PLACES
+        ));
+        // An amount too small for 4.d.p survives rounding to 8.d.p.
+        assert!(!is_subprecision("0.00001", RoundingMode::HalfUp, 8));
+    }
+
+    #[test]
+    fn rounding_matches_legacy_implementation() -> Result<(), Box<dyn std::error::Error>> {
+        // Read a wide range of amounts with both implementations and make sure they agree to within the
+        // last decimal place (the legacy f64 arithmetic could round a half the wrong way), that malformed
+        // fields have no amount in either, and that negative amounts (once accepted) no longer have one.
+        let mut fields: Vec<String> = ["", "abc", "1.2.3", "-", "1e3", "-0"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        for step in -20_000..20_000_i64 {
+            fields.push(format!("{}", step as f64 * 0.000_137));
+            fields.push(format!("{:.5}", step as f64 / 3.0));
+            fields.push(format!("{}", step * 1_000_003));
+        }
+
+        let amounts = read_amounts(&fields)?;
+        assert_eq!(amounts.len(), fields.len());
+        for amount in amounts {
+            match (amount.new, amount.legacy) {
+                (Some(new), Some(legacy)) => {
+                    assert!((new.value() - Decimal::try_from(legacy)?).abs() <= dec!(0.0001));
+                }
+                (new, legacy) => {
+                    assert_eq!(new.is_some(), legacy.is_some_and(|legacy| legacy >= 0.0))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_rounded_exactly() -> Result<(), Box<dyn std::error::Error>> {
+        // Amounts are rounded on their decimal digits with halves away from zero, where the legacy f64
+        // rounding took 0.00015 down as its nearest f64 is just below the half.
+        let fields: Vec<String> = ["0.00015", "2.00005", "0.1", "100.12344"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let amounts: Vec<Option<Decimal>> = read_amounts(&fields)?
+            .into_iter()
+            .map(|amount| amount.new.map(Amount::value))
+            .collect();
+        assert_eq!(
+            amounts,
+            [
+                Some(dec!(0.0002)),
+                Some(dec!(2.0001)),
+                Some(dec!(0.1)),
+                Some(dec!(100.1234))
+            ]
+        );
+        assert_eq!(read_amounts(&fields)?[0].legacy, Some(0.0001));
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_validated_on_construction() {
+        // Amounts must be non-negative and within 8.d.p; parsing rounds to 4.d.p before validating.
+        assert_eq!(Amount::new(dec!(12.5)).map(Amount::value), Ok(dec!(12.5)));
+        assert_eq!(Amount::new(dec!(-0)), Ok(Amount::ZERO));
+        assert_eq!(
+            Amount::new(dec!(-1.5)),
+            Err(AmountError::Negative(dec!(-1.5)))
+        );
+        assert_eq!(
+            Amount::new(dec!(1.000000001)),
+            Err(AmountError::TooPrecise(dec!(1.000000001)))
+        );
+        assert_eq!(Amount::new(dec!(1.50000)).map(Amount::value), Ok(dec!(1.5)));
+        assert_eq!(
+            Amount::parse("1.00005").map(Amount::value),
+            Ok(dec!(1.0001))
+        );
+        assert_eq!(Amount::parse("1e3").map(Amount::value), Ok(dec!(1000)));
+        assert_eq!(
+            Amount::parse("-0.5"),
+            Err(AmountError::Negative(dec!(-0.5)))
+        );
+        for field in ["ten", ".", "-", "+"] {
+            assert_eq!(
+                Amount::parse(field),
+                Err(AmountError::Malformed(field.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn only_plain_decimals_parsed() {
+        // Amounts written as plain decimals, with or without a sign, fraction or exponent, and with digits on
+        // either side of the decimal point are parsed, while digit separators, stray characters and
+        // incomplete parts that the decimal parser would let through are malformed.
+        for (field, value) in [
+            ("10", dec!(10)),
+            ("+10.5", dec!(10.5)),
+            ("0.0001", dec!(0.0001)),
+            ("1.5e3", dec!(1500)),
+            ("15E-1", dec!(1.5)),
+            (".5", dec!(0.5)),
+            ("5.", dec!(5)),
+            ("+.5", dec!(0.5)),
+            ("1e2", dec!(100)),
+        ] {
+            assert_eq!(Amount::parse(field).map(Amount::value), Ok(value));
+        }
+        for field in [
+            "1_000", "1,000", "1 000", "0x10", ".", "-.", ".e3", "1..0", "1.2.3", "1e", "e3",
+            "1.5e3.0", "++1", "1_e3",
+        ] {
+            assert_eq!(
+                Amount::parse(field),
+                Err(AmountError::Malformed(field.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn amount_arithmetic_keeps_invariants() -> Result<(), AmountError> {
+        // Sums of amounts stay exact, and a sum too large to represent is refused rather than wrapping.
+        let sum = Amount::new(dec!(0.1))?.checked_add(Amount::new(dec!(0.2))?);
+        assert_eq!(sum.map(Amount::value), Some(dec!(0.3)));
+        assert_eq!(sum.map(|sum| sum.to_string()), Some("0.3".to_string()));
+        let max = Amount::new(Decimal::MAX)?;
+        assert_eq!(max.checked_add(Amount::new(dec!(1))?), None);
+        assert_eq!(max.checked_add(Amount::ZERO), Some(max));
+        Ok(())
+    }
+
+    #[test]
+    fn non_finite_amounts_rejected() {
+        // NaN and infinite amounts, whether written out in the csv or deserialised from a float, are never
+        // valid amounts.
+        for field in ["NaN", "nan", "inf", "-inf", "Infinity"] {
+            assert_eq!(
+                Amount::parse(field),
+                Err(AmountError::Malformed(field.to_string()))
+            );
+        }
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let deserializer = de::value::F64Deserializer::<de::value::Error>::new(value);
+            assert_eq!(round_deserialise(deserializer), Ok(None));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::EngineConfig;
+use crate::transaction;
+use csv::{Reader, WriterBuilder};
+use std::error::Error;
+use std::io;
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------- CANONICALIZE FUNCTION ------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Rewrites a transaction file in canonical form, returning the number of rows written:
+// - types are written in lower case, without padding around any field,
+// - amounts are rounded to 4.d.p and written without trailing zeros (an invalid amount is left empty),
+// - the optional `seq`, `timestamp` and `reason` columns are only written when some row has a value,
+// - when every row has a timestamp, rows are ordered by timestamp (ties keep their file order),
+//   otherwise the file order is preserved.
+// Canonical files are stable inputs for golden tests, and duplicate rows in them are identical lines.
+pub fn canonicalize<R: io::Read, W: io::Write>(
+    rdr: Reader<R>,
+    out: W,
+) -> Result<usize, Box<dyn Error>> {
+    let mut transactions = transaction::read_transactions(rdr, &EngineConfig::default())?;
+    if transactions
+        .iter()
+        .all(|transaction| transaction.timestamp.is_some())
+    {
+        transactions.sort_by_key(|transaction| transaction.timestamp);
+    }
+
+    let has_seq = transactions
+        .iter()
+        .any(|transaction| transaction.seq.is_some());
+    let has_timestamp = transactions
+        .iter()
+        .any(|transaction| transaction.timestamp.is_some());
+    let has_reason = transactions
+        .iter()
+        .any(|transaction| transaction.reason.is_some());
+
+    let mut writer = WriterBuilder::new().from_writer(out);
+    let mut header = vec!["type", "client", "tx", "amount"];
+    header.extend(has_seq.then_some("seq"));
+    header.extend(has_timestamp.then_some("timestamp"));
+    header.extend(has_reason.then_some("reason"));
+    writer.write_record(&header)?;
+    for transaction in &transactions {
+        let mut record = vec![
+            transaction.transaction_type.as_str().to_string(),
+            transaction.client_id.to_string(),
+            transaction.transaction_id.to_string(),
+            optional_field(transaction.amount),
+        ];
+        record.extend(has_seq.then(|| optional_field(transaction.seq)));
+        record.extend(has_timestamp.then(|| optional_field(transaction.timestamp)));
+        record.extend(has_reason.then(|| optional_field(transaction.reason.as_deref())));
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(transactions.len())
+}
+
+// Text of an optional column, empty when the row has no value.
+fn optional_field<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::{ReaderBuilder, Trim};
+
+    // Canonicalize csv data held in memory, returning the canonical csv.
+    fn canonicalize_str(data: &str) -> Result<String, Box<dyn Error>> {
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut out = Vec::new();
+        canonicalize(rdr, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn canonical_form_normalises_rows() -> Result<(), Box<dyn Error>> {
+        // Types are lower cased, amounts rounded and trimmed, and rows ordered by timestamp.
+        let data = "type, client, tx, amount, timestamp
+                    Deposit, 1, 1, 10.50000, 300
+                    withdrawal, 1, 2, 1e1, 100
+                    DISPUTE, 1, 1, , 200
+                    deposit, 2, 3, 0.123456, 100";
+        assert_eq!(
+            canonicalize_str(data)?,
+            "type,client,tx,amount,timestamp\n\
+             withdrawal,1,2,10,100\n\
+             deposit,2,3,0.1235,100\n\
+             dispute,1,1,,200\n\
+             deposit,1,1,10.5,300\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn canonicalize_is_idempotent() -> Result<(), Box<dyn Error>> {
+        // Canonicalizing the canonical output again leaves it unchanged.
+        let data = "type, client, tx, amount, seq, reason
+                    deposit, 1, 1, 10.0000, 1,
+                    Withdrawal, 1, 2, 2.50, 2,
+                    dispute, 1, 1, , 3, fraud
+                    deposit, 2, 3, 7.123449, 4, ";
+        let first = canonicalize_str(data)?;
+        let second = canonicalize_str(&first)?;
+        assert_eq!(second, first);
+        Ok(())
+    }
+}
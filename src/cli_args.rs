@@ -1,44 +1,641 @@
-use clap::Parser;
+use crate::amount::AMOUNT_DECIMAL_PLACES;
+use crate::config::{
+    AmountFormat, BurstLimit, ChargebackLocks, EngineConfig, FieldPrecision, Locale, LockedAllows,
+    NegativeAvailablePolicy, OutputConfig, OutputFormat, ResolveAfterChargeback, ResolveTo,
+    RoundingMode, SortBy, MAX_PRECISION,
+};
+#[cfg(feature = "http")]
+use crate::remote;
+use crate::scenario::Scenario;
+use crate::timeout::TimeoutReader;
+use crate::transaction::TransactionType;
+use clap::{Parser, Subcommand};
 use csv::{Reader, ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+use std::error::Error;
 use std::fs::File;
+use std::io::{self, BufWriter, Read};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Csv reader over a transaction input, either a local file or a fetched URL.
+pub type TxReader = Reader<Box<dyn Read + Send>>;
+
+// Transaction inputs opened for a run, either streamed straight from their source or read into memory up
+// front so that they can be read more than once.
+pub enum TxInputs {
+    Streamed(Vec<TxReader>),
+    Buffered(Vec<Vec<u8>>),
+}
+
+impl TxInputs {
+    // Csv readers over the buffered inputs, leaving them to be read again when applied. Streamed inputs can
+    // only be read once, so cannot be checked first.
+    pub fn readers_to_check(&self) -> Result<Vec<Reader<&[u8]>>, Box<dyn Error>> {
+        match self {
+            TxInputs::Buffered(inputs) => Ok(inputs
+                .iter()
+                .map(|input| transaction_reader_builder().from_reader(input.as_slice()))
+                .collect()),
+            TxInputs::Streamed(_) => {
+                Err("streamed transaction inputs can only be read once".into())
+            }
+        }
+    }
+
+    // Csv readers applying the inputs.
+    pub fn into_readers(self) -> Vec<TxReader> {
+        match self {
+            TxInputs::Streamed(readers) => readers,
+            TxInputs::Buffered(inputs) => inputs
+                .into_iter()
+                .map(|input| {
+                    let input: Box<dyn Read + Send> = Box::new(io::Cursor::new(input));
+                    transaction_reader_builder().from_reader(input)
+                })
+                .collect(),
+        }
+    }
+}
 
 /// Program to read transactions from a csv file and apply valid transactions to client database.
 #[derive(Parser, Debug)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CliArgs {
-    /// Relative path to transaction csv file.
-    #[clap(value_parser)]
-    transaction_file_path: String,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Relative paths to transaction csv files, applied in the order given, or `-` to read from stdin. With
+    /// the `http` feature these may also be http(s):// or s3:// URLs.
+    #[clap(value_parser, required = true)]
+    pub transaction_file_paths: Vec<String>,
+
+    /// Apply the files concurrently, sharding clients across this many worker threads (at least 1). Each
+    /// client's transactions are still applied in file order, then row order.
+    #[clap(
+        long,
+        alias = "threads",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = &["explain", "require-contiguous-seq", "max-runtime", "warn-reactivated", "defer-future-dated", "burst-threshold", "ledger", "suspense-account"]
+    )]
+    pub shards: Option<usize>,
+
+    /// Replay the transactions under a named set of extra options, given as NAME=OPTIONS (e.g.
+    /// "floor=--minimum-balance 5"), writing each scenario's clients to stdout after a `# scenario: NAME` line.
+    /// Repeat to compare several policies; each starts from empty databases. Options for a single run's files
+    /// and reports cannot be combined with it.
+    #[clap(
+        long = "scenario",
+        value_parser = Scenario::from_str,
+        conflicts_with_all = &["output", "shard-output", "shards", "snapshot", "check-references", "dry-run", "rejects", "ledger", "meta", "lineage", "audit", "client-summary", "dispute-report"]
+    )]
+    pub scenarios: Vec<Scenario>,
+
+    /// Print an explanation of every rejected transaction to stderr.
+    #[clap(long, value_parser)]
+    pub explain: bool,
+
+    /// Write every rejected transaction (line, type, client, tx and reason) to this csv file.
+    #[clap(long, value_parser)]
+    rejects: Option<String>,
+
+    /// Write a journal of every applied transaction, in the order applied, with the client's resulting
+    /// balances to this csv file.
+    #[clap(long, value_parser)]
+    ledger: Option<String>,
+
+    /// Client id of a system suspense account credited with the residues lost rounding deposit and
+    /// withdrawal amounts to 4 decimal places, so the grand total reconciles with the unrounded amounts.
+    #[clap(long, value_parser)]
+    suspense_account: Option<u16>,
+
+    /// Require the `seq` column to be present, contiguous and increasing, aborting on any gap.
+    #[clap(long, value_parser)]
+    require_contiguous_seq: bool,
+
+    /// Print the lineage (original transaction and its disputes/resolves/chargebacks) of a tx id to stderr.
+    #[clap(long, value_parser)]
+    pub lineage: Option<u32>,
+
+    /// Write run provenance metadata (inputs, row count, timestamp, version, output checksum, chained hash) as JSON
+    /// to this path.
+    #[clap(long, value_parser)]
+    pub meta: Option<String>,
+
+    /// Chained hash from the previous run's metadata, chaining this run's output to it so the sequence of
+    /// runs is tamper-evident.
+    #[clap(long, value_parser = sha256_hex, requires = "meta")]
+    pub prev_hash: Option<String>,
+
+    /// How a dispute behaves when holding the disputed amount would make available funds negative.
+    #[clap(long, value_enum, default_value_t = NegativeAvailablePolicy::Allow)]
+    negative_available: NegativeAvailablePolicy,
+
+    /// Daily interest rate (e.g. 0.0001) credited on held funds, accrued from the `timestamp` column.
+    #[clap(long, value_parser = non_negative_decimal)]
+    held_interest_rate: Option<Decimal>,
+
+    /// Track at most this many deposits/withdrawals for disputes, forgetting the oldest not under dispute so
+    /// memory stays bounded on huge inputs. Forgotten transactions can no longer be disputed.
+    #[clap(long, value_parser)]
+    pub max_tracked_transactions: Option<usize>,
+
+    /// Path to a client csv from a previous run to load as the starting state.
+    #[clap(long, value_parser)]
+    snapshot: Option<String>,
+
+    /// Only output clients that had a transaction applied during this run.
+    #[clap(long, value_parser)]
+    changed_only: bool,
+
+    /// Before applying, check every dispute/resolve/chargeback references a tx in the file, aborting if not.
+    #[clap(long, value_parser)]
+    pub check_references: bool,
+
+    /// Decimal places amounts are read with and balances written with, e.g. 8 for crypto currencies or 2 for
+    /// cents. Defaults to 4.
+    #[clap(long, value_parser = precision_in_range)]
+    precision: Option<u32>,
+
+    /// Decimal places the available column is written with, overriding --precision.
+    #[clap(long, value_parser = precision_in_range)]
+    available_precision: Option<u32>,
+
+    /// Decimal places the held column is written with, overriding --precision.
+    #[clap(long, value_parser = precision_in_range)]
+    held_precision: Option<u32>,
+
+    /// Decimal places the total column is written with, overriding --precision.
+    #[clap(long, value_parser = precision_in_range)]
+    total_precision: Option<u32>,
+
+    /// Zero-pad the client column of the output to this many digits, e.g. 00042 for a width of 5. Fails if
+    /// any client id written has more digits.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    client_id_width: Option<u32>,
+
+    /// Also output each client's cumulative deposited and withdrawn totals.
+    #[clap(long, value_parser)]
+    cumulative_totals: bool,
+
+    /// Currency code written in a trailing currency column of the output, e.g. USD.
+    #[clap(long, value_parser)]
+    currency: Option<String>,
+
+    /// Write the clients as csv or as a JSON array of objects. Sharded output is always csv.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+
+    /// Order the clients in the output by this column, e.g. total for a top balances report. Clients with
+    /// equal balances are ordered by client id.
+    #[clap(long, value_enum, default_value_t = SortBy::Client)]
+    sort_by: SortBy,
+
+    /// Order the clients in the output from the largest value of the sort column to the smallest.
+    #[clap(long, value_parser)]
+    desc: bool,
+
+    /// Write balances in a canonical format: every decimal place (fixed) or no trailing zeros (trimmed).
+    #[clap(long, value_enum)]
+    canonical_amount_format: Option<AmountFormat>,
+
+    /// Write balances with the thousands separator and decimal point of a locale (en, de or fr).
+    #[clap(long, value_enum)]
+    locale: Option<Locale>,
+
+    /// Write a `#` comment line listing the output columns and precision before the header. Off by default as
+    /// not every csv parser skips comment lines.
+    #[clap(long, value_parser)]
+    schema_comment: bool,
+
+    /// Write the clients to this csv file instead of stdout.
+    #[clap(long, value_parser)]
+    output: Option<String>,
+
+    /// Write the clients to this many csv files (at least 1) instead of stdout, sharded by client id modulo the
+    /// count and each sorted by client id.
+    #[clap(
+        long,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = &["meta", "output"]
+    )]
+    pub shard_output: Option<usize>,
+
+    /// Path prefix of the sharded output files, written as `<prefix>_<shard>.csv`.
+    #[clap(
+        long,
+        value_parser,
+        default_value = "clients",
+        requires = "shard-output"
+    )]
+    shard_output_prefix: String,
+
+    /// Abort with an error if a streamed input (stdin or a URL) sends no data for this many seconds.
+    #[clap(long, value_parser = seconds_to_duration)]
+    pub read_timeout: Option<Duration>,
+
+    /// Stop processing after this many seconds, outputting the balances computed so far with a warning.
+    #[clap(long, value_parser = seconds_to_duration)]
+    max_runtime: Option<Duration>,
+
+    /// Minimum available balance a client must keep; withdrawals cannot take available funds below it.
+    #[clap(long, default_value_t = Decimal::ZERO, value_parser = non_negative_decimal)]
+    minimum_balance: Decimal,
+
+    /// Reject deposits and withdrawals for more than this amount, e.g. fat-finger errors. An amount equal to
+    /// the maximum is applied.
+    #[clap(long, value_parser = non_negative_decimal)]
+    max_amount: Option<Decimal>,
+
+    /// Percentage (0 to 100) of available funds reserved as non-withdrawable. The reported available
+    /// balance is unchanged.
+    #[clap(long, default_value_t = Decimal::ZERO, value_parser = percentage_in_range)]
+    reserve_pct: Decimal,
+
+    /// Ignore trailing columns beyond the header instead of rejecting the row.
+    #[clap(long, value_parser)]
+    ignore_extra_fields: bool,
+
+    /// Validate every row without writing any balances or outputs, printing only the malformed rows and
+    /// rejected transactions found to stderr. Exits with code 2 if any were found.
+    #[clap(long, value_parser, conflicts_with = "explain")]
+    pub dry_run: bool,
+
+    /// Abort on the first row that cannot be parsed instead of skipping it.
+    #[clap(long, value_parser)]
+    strict: bool,
+
+    /// Only apply transactions of these types (comma separated, e.g. dispute,resolve), skipping all others.
+    /// Skipped deposits and withdrawals are not recorded, so later rows cannot reference them.
+    #[clap(long, value_parser = TransactionType::from_str, use_value_delimiter = true)]
+    only_types: Vec<TransactionType>,
+
+    /// Reject transactions with a non-zero amount below the amount precision (e.g. 0.00001 at 4 decimal
+    /// places), rather than applying them as a zero amount.
+    #[clap(long, value_parser)]
+    reject_subprecision: bool,
+
+    /// Reject transactions whose amount has more than this many significant digits (e.g. 12345678901234.5678
+    /// has 18), however it would round.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_significant_digits: Option<u32>,
+
+    /// Parse rows by hand from one record buffer reused across rows, rather than deserialising a newly
+    /// allocated record per row. Transactions read, and malformed rows reported, are identical.
+    #[clap(long, value_parser)]
+    fast_parse: bool,
+
+    /// How amounts are rounded to their precision as they are read, and balances to their precision as they
+    /// are written.
+    #[clap(long, value_enum, default_value_t = RoundingMode::HalfUp)]
+    rounding: RoundingMode,
+
+    /// Transactions still applied to an account once it has been locked by a chargeback.
+    #[clap(long, value_enum, default_value_t = LockedAllows::Nothing)]
+    locked_allows: LockedAllows,
+
+    /// Balance a resolve returns disputed funds to. Pending funds are made available by a later release row,
+    /// and are written in an extra pending column.
+    #[clap(long, value_enum, default_value_t = ResolveTo::Available)]
+    resolve_to: ResolveTo,
+
+    /// Chargebacks that lock the account: any chargeback, or only one completing the reversal of the whole
+    /// transaction when disputes cover part of it.
+    #[clap(long, value_enum, default_value_t = ChargebackLocks::Any)]
+    chargeback_locks: ChargebackLocks,
+
+    /// Handling of a resolve for a transaction that has already been charged back: reject it, reported like
+    /// any other rejection, or ignore it as a silent no-op. Either way the balances are unchanged.
+    #[clap(long, value_enum, default_value_t = ResolveAfterChargeback::Reject)]
+    resolve_after_chargeback: ResolveAfterChargeback,
+
+    /// Only allow a withdrawal to be disputed within this many seconds of its timestamp, rejecting later
+    /// disputes. Disputes of untimestamped withdrawals, or without a timestamp themselves, are always allowed.
+    #[clap(long, value_parser = seconds_to_duration)]
+    withdrawal_dispute_window: Option<Duration>,
+
+    /// Warn on stderr about deposits/withdrawals on accounts that were locked and have since been unlocked.
+    #[clap(long, value_parser)]
+    warn_reactivated: bool,
+
+    /// Hold transactions timestamped after the processing clock until a clock row advances the clock to them.
+    #[clap(long, value_parser)]
+    defer_future_dated: bool,
+
+    /// Starting processing clock in seconds since the unix epoch, instead of the current time.
+    #[clap(long, value_parser, requires = "defer-future-dated")]
+    processing_clock: Option<u64>,
+
+    /// Alert on stderr when a client makes more than this many timestamped transactions within the burst window.
+    #[clap(long, value_parser, requires = "burst-window")]
+    burst_threshold: Option<usize>,
+
+    /// Length in seconds of the sliding window used by --burst-threshold.
+    #[clap(long, value_parser = seconds_to_duration, requires = "burst-threshold")]
+    burst_window: Option<Duration>,
+
+    /// Print counts of total, locked, held and zero-balance clients to stderr after processing.
+    #[clap(long, value_parser)]
+    pub client_summary: bool,
+
+    /// Print every dispute still open after processing, with its reason code, to stderr.
+    #[clap(long, value_parser)]
+    pub dispute_report: bool,
+
+    /// Check every client's balances against its history after processing, printing any that do not add up
+    /// to stderr.
+    #[clap(long, value_parser)]
+    pub audit: bool,
+
+    /// Path to a SQLite database to load client/transaction state from and save it back to.
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_parser, conflicts_with = "scenarios")]
+    pub db: Option<String>,
 }
 
-// Build the csv reader from the path supplied to the binary.
-// Panics if specified filename is invalid.
+// Tools run instead of applying transactions.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Rewrite a transaction file in canonical form: lower case types, amounts rounded to 4 decimal places
+    /// without trailing zeros, and rows ordered by timestamp when every row has one.
+    Canonicalize {
+        /// Path to the transaction csv file to rewrite.
+        #[clap(value_parser)]
+        input: String,
+
+        /// Write the canonical file to this path instead of stdout.
+        #[clap(long, value_parser)]
+        output: Option<String>,
+    },
+}
+
+// Build a csv reader for each of the paths supplied to the binary.
+// The readers are flexible so that rows with the wrong number of fields are reported by the engine.
+// With the `http` feature, paths that are URLs are fetched and streamed into the reader.
+// Fails if any specified file cannot be opened.
 impl CliArgs {
-    pub fn create_tx_reader(self) -> Reader<File> {
-        ReaderBuilder::new()
-            .trim(Trim::All)
-            .from_path(self.transaction_file_path)
-            .expect("Failed to initalise CSV reader. Please ensure specified path is correct")
+    pub fn create_tx_readers(&self) -> Result<Vec<TxReader>, Box<dyn Error>> {
+        self.transaction_file_paths
+            .iter()
+            .map(|path| create_tx_reader(path, self.read_timeout))
+            .collect()
+    }
+
+    // Open every transaction input for a run. With --check-references the inputs are read twice, so they are
+    // read into memory once instead: stdin can only be read once, and a URL may return different transactions
+    // if fetched again.
+    pub fn open_tx_inputs(&self) -> Result<TxInputs, Box<dyn Error>> {
+        match self.check_references {
+            true => self.read_tx_inputs().map(TxInputs::Buffered),
+            false => self.create_tx_readers().map(TxInputs::Streamed),
+        }
+    }
+
+    // Read every transaction input into memory, in the order given.
+    pub fn read_tx_inputs(&self) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        self.transaction_file_paths
+            .iter()
+            .map(|path| read_tx_input(path, self.read_timeout))
+            .collect()
+    }
+
+    // Build a csv reader for the snapshot, if one was supplied.
+    pub fn create_snapshot_reader(&self) -> Option<csv::Result<Reader<File>>> {
+        self.snapshot
+            .as_ref()
+            .map(|path| ReaderBuilder::new().trim(Trim::All).from_path(path))
+    }
+
+    // Create the output file, if one was supplied.
+    pub fn create_output_file(&self) -> Option<io::Result<BufWriter<File>>> {
+        self.output
+            .as_ref()
+            .map(|path| File::create(path).map(BufWriter::new))
+    }
+
+    // Create the rejected transactions file, if one was supplied.
+    pub fn create_rejects_file(&self) -> Option<io::Result<BufWriter<File>>> {
+        self.rejects
+            .as_ref()
+            .map(|path| File::create(path).map(BufWriter::new))
+    }
+
+    // Create the ledger file, if one was supplied.
+    pub fn create_ledger_file(&self) -> Option<io::Result<BufWriter<File>>> {
+        self.ledger
+            .as_ref()
+            .map(|path| File::create(path).map(BufWriter::new))
+    }
+
+    // Create the files of a sharded output, numbered from 0.
+    // At least one file is created.
+    pub fn create_output_shards(&self, shards: usize) -> io::Result<Vec<BufWriter<File>>> {
+        (0..shards.max(1))
+            .map(|shard| {
+                File::create(format!("{}_{}.csv", self.shard_output_prefix, shard))
+                    .map(BufWriter::new)
+            })
+            .collect()
+    }
+
+    // Decimal places of each balance column: the column's own precision if given, else --precision, else 4.
+    fn field_precision(&self) -> FieldPrecision {
+        let column_precision =
+            |column: Option<u32>| column.or(self.precision).unwrap_or(AMOUNT_DECIMAL_PLACES);
+        FieldPrecision {
+            available: column_precision(self.available_precision),
+            held: column_precision(self.held_precision),
+            total: column_precision(self.total_precision),
+        }
+    }
+
+    // Build the output configuration from the supplied options.
+    pub fn output_config(&self) -> OutputConfig {
+        OutputConfig {
+            changed_only: self.changed_only,
+            precision: self.field_precision(),
+            rounding: self.rounding,
+            pending_column: self.resolve_to == ResolveTo::Pending,
+            cumulative_totals: self.cumulative_totals,
+            currency: self.currency.clone(),
+            amount_format: self.canonical_amount_format,
+            locale: self.locale,
+            format: self.format,
+            sort_by: self.sort_by,
+            descending: self.desc,
+            client_id_width: self.client_id_width,
+            schema_comment: self.schema_comment,
+        }
+    }
+
+    // Build the engine configuration from the supplied options.
+    pub fn engine_config(&self) -> EngineConfig {
+        EngineConfig {
+            require_contiguous_seq: self.require_contiguous_seq,
+            negative_available: self.negative_available,
+            held_interest_rate: self.held_interest_rate,
+            rounding: self.rounding,
+            max_runtime: self.max_runtime,
+            minimum_balance: self.minimum_balance,
+            max_amount: self.max_amount,
+            reserve_pct: self.reserve_pct,
+            ignore_extra_fields: self.ignore_extra_fields,
+            strict: self.strict,
+            locked_allows: self.locked_allows,
+            resolve_to: self.resolve_to,
+            chargeback_locks: self.chargeback_locks,
+            resolve_after_chargeback: self.resolve_after_chargeback,
+            withdrawal_dispute_window: self.withdrawal_dispute_window,
+            warn_reactivated: self.warn_reactivated,
+            defer_future_dated: self.defer_future_dated,
+            processing_clock: self.processing_clock,
+            precision: self.precision,
+            reject_subprecision: self.reject_subprecision,
+            max_significant_digits: self.max_significant_digits,
+            burst_limit: self
+                .burst_threshold
+                .zip(self.burst_window)
+                .map(|(threshold, window)| BurstLimit { threshold, window }),
+            only_types: self.only_types.clone(),
+            record_ledger: self.ledger.is_some(),
+            suspense_account: self.suspense_account,
+            fast_parse: self.fast_parse,
+        }
     }
 }
 
+// Build a csv reader for a single transaction input. Streamed inputs are read with the timeout, if any.
+pub fn create_tx_reader(
+    path: &str,
+    read_timeout: Option<Duration>,
+) -> Result<TxReader, Box<dyn Error>> {
+    Ok(transaction_reader_builder().from_reader(open_tx_input(path, read_timeout)?))
+}
+
+// Read the whole of a transaction input into memory, e.g. to replay it more than once.
+pub fn read_tx_input(
+    path: &str,
+    read_timeout: Option<Duration>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data = Vec::new();
+    open_tx_input(path, read_timeout)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// Csv reader settings for transaction inputs: fields are trimmed, and rows may have any number of fields so
+// that those with too many can be reported clearly.
+pub fn transaction_reader_builder() -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder.trim(Trim::All).flexible(true);
+    builder
+}
+
+// Open a single transaction input. Streamed inputs are read with the timeout, if any.
+fn open_tx_input(
+    path: &str,
+    read_timeout: Option<Duration>,
+) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    match read_timeout {
+        Some(timeout) if is_streamed(path) => {
+            let source = if path == STDIN_PATH { "stdin" } else { path };
+            Ok(Box::new(TimeoutReader::new(
+                open_input(path)?,
+                timeout,
+                source,
+            )))
+        }
+        _ => open_input(path),
+    }
+}
+
+// Path of the transaction input read from stdin.
+const STDIN_PATH: &str = "-";
+
+// True if the input is streamed from stdin or a URL, rather than read from a local file.
+fn is_streamed(path: &str) -> bool {
+    #[cfg(feature = "http")]
+    if remote::is_url(path) {
+        return true;
+    }
+    path == STDIN_PATH
+}
+
+// Open a transaction input, reading stdin for `-` and fetching it if it is a URL.
+fn open_input(path: &str) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    #[cfg(feature = "http")]
+    if remote::is_url(path) {
+        return remote::open_url(path);
+    }
+    if path == STDIN_PATH {
+        return Ok(Box::new(io::stdin()));
+    }
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(Box::new(file))
+}
+
+// Validate a precision option is a whole number of decimal places no greater than MAX_PRECISION.
+fn precision_in_range(value: &str) -> Result<u32, String> {
+    let precision: u32 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a whole number of decimal places", value))?;
+    if precision > MAX_PRECISION {
+        return Err(format!(
+            "precision must be at most {} decimal places",
+            MAX_PRECISION
+        ));
+    }
+    Ok(precision)
+}
+
+// Validate a hash option is a hex encoded SHA-256, normalised to lowercase.
+fn sha256_hex(value: &str) -> Result<String, String> {
+    if value.len() != 64 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("`{}` is not a hex encoded SHA-256 hash", value));
+    }
+    Ok(value.to_ascii_lowercase())
+}
+
+// Validate a percentage option is a decimal between 0 and 100.
+fn percentage_in_range(value: &str) -> Result<Decimal, String> {
+    value
+        .parse::<Decimal>()
+        .ok()
+        .filter(|percentage| (Decimal::ZERO..=Decimal::ONE_HUNDRED).contains(percentage))
+        .ok_or_else(|| format!("`{}` is not a percentage between 0 and 100", value))
+}
+
+// Validate an amount or rate option is a non-negative decimal.
+fn non_negative_decimal(value: &str) -> Result<Decimal, String> {
+    value
+        .parse::<Decimal>()
+        .ok()
+        .filter(|value| !value.is_sign_negative())
+        .ok_or_else(|| format!("`{}` is not a non-negative decimal", value))
+}
+
+// Parse a non-negative number of (possibly fractional) seconds into a Duration.
+fn seconds_to_duration(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("`{}` is not a non-negative number of seconds", value))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // Create reader from path
-    fn create_tx_reader(path: String) -> Reader<File> {
-        ReaderBuilder::new()
-            .trim(Trim::All)
-            .from_path(path)
-            .expect("Failed to initalise CSV reader. Please ensure specified path is correct")
-    }
-
     #[test]
-    #[should_panic]
-    fn invalid_path_panics() {
-        // Make sure that an invalid path causes the csv reader to panic
-        let path = "not_a_valid_path.csv".to_string();
-        let _ = create_tx_reader(path);
+    fn invalid_path_errors() {
+        // Make sure that an invalid path is reported as an error naming the path, rather than a panic
+        let err = create_tx_reader("not_a_valid_path.csv", None)
+            .err()
+            .expect("opening a nonexistent file should fail");
+        assert_eq!(
+            err.to_string(),
+            "not_a_valid_path.csv: No such file or directory (os error 2)"
+        );
     }
 
     #[test]
@@ -47,7 +644,150 @@ mod tests {
         let dir = tempfile::tempdir()?;
         let file_path = dir.path().join("temp_csv_file.csv");
         let _ = File::create(&file_path)?;
-        let _ = create_tx_reader(file_path.as_path().display().to_string());
+        create_tx_reader(&file_path.as_path().display().to_string(), None)?;
         Ok(())
     }
+
+    #[test]
+    fn canonicalize_subcommand_parsed() {
+        // The subcommand is parsed in place of transaction files, and transaction files still parse without it.
+        let args =
+            CliArgs::try_parse_from(["engine", "canonicalize", "in.csv", "--output", "out.csv"])
+                .expect("canonicalize arguments should parse");
+        match args.command {
+            Some(Command::Canonicalize { input, output }) => {
+                assert_eq!(input, "in.csv");
+                assert_eq!(output.as_deref(), Some("out.csv"));
+            }
+            None => panic!("expected the canonicalize subcommand"),
+        }
+        let args = CliArgs::try_parse_from(["engine", "in.csv"])
+            .expect("transaction file arguments should parse");
+        assert!(args.command.is_none());
+        assert_eq!(args.transaction_file_paths, ["in.csv"]);
+    }
+
+    #[test]
+    fn per_run_options_conflict_with_scenarios() {
+        // Scenario mode does not use a single run's files or reports, so asking for them with it is an error
+        // rather than silently ignored, while engine options are still accepted.
+        for option in [
+            &["--output", "out.csv"][..],
+            &["--shards", "2"],
+            &["--snapshot", "clients.csv"],
+            &["--check-references"],
+            &["--dry-run"],
+            &["--rejects", "rejects.csv"],
+            &["--ledger", "ledger.csv"],
+            &["--meta", "meta.json"],
+            #[cfg(feature = "sqlite")]
+            &["--db", "engine.sqlite"],
+        ] {
+            let args = [
+                "engine",
+                "in.csv",
+                "--scenario",
+                "floor=--minimum-balance 5",
+            ];
+            assert!(CliArgs::try_parse_from(args.iter().chain(option)).is_err());
+        }
+        let args = [
+            "engine",
+            "in.csv",
+            "--scenario",
+            "floor=--minimum-balance 5",
+            "--explain",
+        ];
+        assert!(CliArgs::try_parse_from(args).is_ok());
+    }
+
+    #[test]
+    fn zero_shards_rejected() {
+        // Make sure at least one shard is required, under either name of the option.
+        let args = CliArgs::try_parse_from(["engine", "in.csv", "--shards", "2"])
+            .expect("shard arguments should parse");
+        assert_eq!(args.shards, Some(2));
+        assert!(CliArgs::try_parse_from(["engine", "in.csv", "--shards", "0"]).is_err());
+        assert!(CliArgs::try_parse_from(["engine", "in.csv", "--threads", "0"]).is_err());
+    }
+
+    #[test]
+    fn zero_shard_outputs_rejected() {
+        // Make sure at least one output file is required.
+        let args = CliArgs::try_parse_from(["engine", "in.csv", "--shard-output", "3"])
+            .expect("shard output arguments should parse");
+        assert_eq!(args.shard_output, Some(3));
+        assert!(CliArgs::try_parse_from(["engine", "in.csv", "--shard-output", "0"]).is_err());
+    }
+
+    #[test]
+    fn precision_validated() {
+        // Make sure precision options accept 0 to MAX_PRECISION and reject anything else.
+        assert_eq!(precision_in_range("0"), Ok(0));
+        assert_eq!(precision_in_range("8"), Ok(8));
+        assert!(precision_in_range("9").is_err());
+        assert!(precision_in_range("-1").is_err());
+        assert!(precision_in_range("two").is_err());
+    }
+
+    #[test]
+    fn precision_sets_input_and_column_precision() {
+        // --precision applies to amounts read and to every balance column, unless a column overrides it,
+        // and both default to 4.d.p.
+        let args = CliArgs::try_parse_from([
+            "engine",
+            "in.csv",
+            "--precision",
+            "8",
+            "--held-precision",
+            "2",
+        ])
+        .expect("precision arguments should parse");
+        assert_eq!(args.engine_config().amount_precision(), 8);
+        let precision = args.output_config().precision;
+        assert_eq!(
+            (precision.available, precision.held, precision.total),
+            (8, 2, 8)
+        );
+        let args = CliArgs::try_parse_from(["engine", "in.csv"])
+            .expect("transaction file arguments should parse");
+        assert_eq!(args.engine_config().amount_precision(), 4);
+        let precision = args.output_config().precision;
+        assert_eq!(
+            (precision.available, precision.held, precision.total),
+            (4, 4, 4)
+        );
+    }
+
+    #[test]
+    fn reserve_percentage_validated() {
+        // Make sure the reserve percentage accepts 0 to 100 and rejects anything else.
+        assert_eq!(percentage_in_range("0"), Ok(Decimal::ZERO));
+        assert_eq!(percentage_in_range("12.5"), Ok(Decimal::new(125, 1)));
+        assert_eq!(percentage_in_range("100"), Ok(Decimal::ONE_HUNDRED));
+        assert!(percentage_in_range("100.01").is_err());
+        assert!(percentage_in_range("-1").is_err());
+        assert!(percentage_in_range("half").is_err());
+    }
+
+    #[test]
+    fn negative_amounts_and_rates_rejected() {
+        // Make sure the minimum balance, maximum amount and held interest rate accept zero and positive
+        // decimals and reject negative ones at parse time.
+        for option in ["--minimum-balance", "--max-amount", "--held-interest-rate"] {
+            let parse = |value: &str| {
+                CliArgs::try_parse_from(["engine", "in.csv", &format!("{}={}", option, value)])
+            };
+            for value in ["0", "0.0001", "500"] {
+                assert!(parse(value).is_ok());
+            }
+            for value in ["-1", "-0.0001", "lots"] {
+                assert!(parse(value).is_err());
+            }
+        }
+        assert_eq!(
+            non_negative_decimal("-1"),
+            Err("`-1` is not a non-negative decimal".to_string())
+        );
+    }
 }
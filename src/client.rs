@@ -1,40 +1,367 @@
-use crate::transaction::{Transaction, TransactionDb, TransactionType};
-use csv::WriterBuilder;
-use serde::{Serialize, Serializer};
-use std::collections::HashMap;
+use crate::amount::{amount_deserialise, balance_deserialise, Amount};
+use crate::collections::{Map, Set};
+use crate::config::{
+    AmountFormat, ChargebackLocks, EngineConfig, Locale, LockedAllows, NegativeAvailablePolicy,
+    OutputConfig, OutputFormat, ResolveTo, SortBy,
+};
+use crate::error::TransactionError;
+use crate::metadata::ChecksumWriter;
+use crate::storage::Storage;
+use crate::transaction::{DisputeState, ReferencedTransaction, Transaction, TransactionType};
+use csv::{Reader, WriterBuilder};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
 use std::error::Error;
-use std::io::Write;
+use std::fmt;
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::thread;
+use std::time::Duration;
 
 // ------------------------------------------------------------------------------------------------
 // -------------------------------- CLIENT DB STRUCT ----------------------------------------------
 // ------------------------------------------------------------------------------------------------
 
-// Wrapper struct for client database (hashmap) to avoid exposure to internal hashmap api.
-pub struct ClientDb {
-    db: HashMap<u16, Client>,
+// Length of an interest accrual period on held funds (one day).
+const INTEREST_ACCRUAL_PERIOD_SECS: u64 = 86_400;
+
+// Number of client records written between flushes of the output writer.
+const FLUSH_EVERY_N_CLIENTS: usize = 1_000;
+
+// Number of times a write to the output that failed with a transient error is retried, and the delay
+// before the first retry (doubled for each subsequent retry).
+const WRITE_RETRIES: u32 = 5;
+const WRITE_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+// Wrapper struct for client database (hashmap by default, or any `Storage`) to avoid exposure to internal
+// storage api. Also tracks which clients have had a transaction applied during the current run.
+pub struct ClientDb<S = Map<u16, Client>> {
+    db: S,
+    modified: Set<u16>,
 }
 
-// Client struct with renamed fields for clarity. All f64 fields custom serialised to ensure 4.d.p precision.
-#[derive(Serialize, Debug)]
+// Client struct with renamed fields for clarity. Serialised through `ClientRecord` (below) so the output
+// configuration can be applied. Deserialisable so that the output of a previous run can be loaded back in as a snapshot.
+#[derive(Deserialize, Debug)]
 pub struct Client {
     #[serde(rename = "client")]
     pub client_id: u16,
-    #[serde(serialize_with = "round_serialize")]
-    available: f64,
-    #[serde(serialize_with = "round_serialize")]
-    held: f64,
-    #[serde(serialize_with = "round_serialize")]
-    total: f64,
-    locked: bool,
+    #[serde(deserialize_with = "balance_deserialise")]
+    pub(crate) available: Decimal,
+    #[serde(deserialize_with = "balance_deserialise")]
+    pub(crate) held: Decimal,
+    #[serde(deserialize_with = "balance_deserialise")]
+    pub(crate) total: Decimal,
+    pub(crate) locked: bool,
+    // True once the account has been locked, even if it has since been unlocked.
+    #[serde(default)]
+    pub(crate) was_ever_locked: bool,
+    // Resolved funds awaiting release to available, when resolves are configured to go to pending.
+    #[serde(default, deserialize_with = "balance_deserialise")]
+    pub(crate) pending: Decimal,
+    // Running sums of every deposit and withdrawal applied to the account, unaffected by disputes.
+    #[serde(default, deserialize_with = "amount_deserialise")]
+    pub(crate) total_deposited: Amount,
+    #[serde(default, deserialize_with = "amount_deserialise")]
+    pub(crate) total_withdrawn: Amount,
+    // Timestamp up to which interest on held funds has been credited.
+    #[serde(skip)]
+    pub(crate) interest_accrued_to: Option<u64>,
+    // Total carried in from a snapshot or database, less the deposits and withdrawals it already includes.
+    #[serde(skip)]
+    pub(crate) opening_total: Decimal,
+    // Running sums of the interest credited and the funds charged back, for auditing the total.
+    #[serde(skip)]
+    pub(crate) interest_credited: Decimal,
+    #[serde(skip)]
+    pub(crate) charged_back: Decimal,
+    // Rounding residues credited to a suspense account, for auditing the total.
+    #[serde(skip)]
+    pub(crate) rounding_residues: Decimal,
+    // Withdrawn funds credited back by disputes of withdrawals, less those whose dispute was resolved,
+    // for auditing the total.
+    #[serde(skip)]
+    pub(crate) reclaimed_withdrawals: Decimal,
+}
+
+// A client record as written to the output, with each balance rounded to its configured precision.
+pub struct ClientRecord<'a> {
+    client: &'a Client,
+    output: &'a OutputConfig,
+}
+
+// Custom Serialiser for the output record with renamed fields. Runs on point of serialisation.
+impl Serialize for ClientRecord<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let precision = &self.output.precision;
+        let output = self.output;
+        let mut record = serializer.serialize_struct("Client", output.columns().len())?;
+        record.serialize_field(
+            "client",
+            &ClientId(self.client.client_id, output.client_id_width),
+        )?;
+        record.serialize_field(
+            "available",
+            &Rounded(self.client.available, precision.available, output),
+        )?;
+        record.serialize_field("held", &Rounded(self.client.held, precision.held, output))?;
+        record.serialize_field(
+            "total",
+            &Rounded(self.client.total, precision.total, output),
+        )?;
+        if self.output.pending_column {
+            record.serialize_field(
+                "pending",
+                &Rounded(self.client.pending, precision.held, output),
+            )?;
+        }
+        record.serialize_field("locked", &self.client.locked)?;
+        if self.output.cumulative_totals {
+            record.serialize_field(
+                "total_deposited",
+                &Rounded(self.client.total_deposited.value(), precision.total, output),
+            )?;
+            record.serialize_field(
+                "total_withdrawn",
+                &Rounded(self.client.total_withdrawn.value(), precision.total, output),
+            )?;
+        }
+        if let Some(currency) = &self.output.currency {
+            record.serialize_field("currency", currency)?;
+        }
+        record.end()
+    }
+}
+
+// Client id paired with the width it is zero-padded to, if any, when serialised.
+struct ClientId(u16, Option<u32>);
+
+// Custom Serialiser to zero-pad a client id to a fixed width, e.g. 00042, for downstream systems expecting
+// fixed-width ids. A padded id is written as text, so it stays padded in JSON output too.
+impl Serialize for ClientId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            ClientId(client_id, Some(width)) => {
+                serializer.serialize_str(&format!("{:0width$}", client_id, width = width as usize))
+            }
+            ClientId(client_id, None) => serializer.serialize_u16(client_id),
+        }
+    }
+}
+
+// Balance paired with the number of decimal places it is rounded to, and the output configuration
+// controlling the format (if any) and locale it is written in, when serialised.
+struct Rounded<'a>(Decimal, u32, &'a OutputConfig);
+
+// Custom Serialiser to round a balance to its precision. Runs on point of serialisation.
+impl Serialize for Rounded<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let Rounded(value, precision, output) = *self;
+        let rounded = output.rounding.round(value, precision);
+        // Avoid writing negative zero, e.g. -0.0000, in the fixed width formats.
+        let rounded = if rounded.is_zero() {
+            Decimal::ZERO
+        } else {
+            rounded
+        };
+        // JSON balances are numbers, so the text format and locale do not apply.
+        if output.format == OutputFormat::Json {
+            return serializer.serialize_f64(rounded.to_f64().unwrap_or_default());
+        }
+        let fixed = fixed_places(rounded, precision);
+        let text = match output.amount_format {
+            None => shortest(rounded),
+            Some(AmountFormat::Fixed) => fixed,
+            Some(AmountFormat::Trimmed) if fixed.contains('.') => fixed
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string(),
+            Some(AmountFormat::Trimmed) => fixed,
+        };
+        match output.locale {
+            Some(locale) => serializer.serialize_str(&localise(&text, locale)),
+            None => serializer.serialize_str(&text),
+        }
+    }
+}
+
+// Shortest text of a balance that still shows it is a decimal, e.g. 100.5, 100.0 or 0.0.
+// Pad an already rounded value with trailing zeros to exactly `precision` decimal places. Decimal's own
+// `{:.*}` formatting panics for values with 29 whole digits, so the zeros are added here instead.
+fn fixed_places(value: Decimal, precision: u32) -> String {
+    let text = value.to_string();
+    let places = text
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len());
+    let padding = "0".repeat((precision as usize).saturating_sub(places));
+    match (places, precision) {
+        (_, 0) => text,
+        (0, _) => format!("{}.{}", text, padding),
+        _ => format!("{}{}", text, padding),
+    }
+}
+
+fn shortest(value: Decimal) -> String {
+    let text = value.normalize().to_string();
+    match text.contains('.') {
+        true => text,
+        false => format!("{}.0", text),
+    }
+}
+
+// Rewrite a plain decimal amount (e.g. -1234567.5) with the locale's thousands separator and decimal
+// point (e.g. -1.234.567,5). The csv writer quotes the field if a separator is the delimiter.
+fn localise(amount: &str, locale: Locale) -> String {
+    let (thousands, decimal_point) = locale.separators();
+    let (sign, digits) = match amount.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", amount),
+    };
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let mut localised = sign.to_string();
+    for (position, digit) in integer.chars().enumerate() {
+        if position > 0 && (integer.len() - position) % 3 == 0 {
+            localised.push(thousands);
+        }
+        localised.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        localised.push(decimal_point);
+        localised.push_str(fraction);
+    }
+    localised
+}
+
+// Counts of clients by account state, printed as a quick sanity check after processing.
+#[derive(Debug, Default, PartialEq)]
+pub struct ClientSummary {
+    pub clients: usize,
+    pub locked: usize,
+    pub held: usize,
+    pub zero_balance: usize,
+}
+
+impl fmt::Display for ClientSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} clients, {} locked, {} with held funds, {} zero-balance",
+            self.clients, self.locked, self.held, self.zero_balance
+        )
+    }
+}
+
+// A client whose balances do not add up, found by `ClientDb::audit`.
+#[derive(Debug, PartialEq)]
+pub enum Discrepancy {
+    // The total does not match the total replayed from the client's deposits, withdrawals, chargebacks and interest.
+    History {
+        client_id: u16,
+        total: Decimal,
+        expected: Decimal,
+    },
+    // The total does not equal the client's available, held and pending funds combined.
+    Balances {
+        client_id: u16,
+        total: Decimal,
+        expected: Decimal,
+    },
+}
+
+impl Discrepancy {
+    // Client whose balances do not add up.
+    pub fn client_id(&self) -> u16 {
+        match self {
+            Discrepancy::History { client_id, .. } | Discrepancy::Balances { client_id, .. } => {
+                *client_id
+            }
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::History {
+                client_id,
+                total,
+                expected,
+            } => write!(
+                f,
+                "client {}: total {} does not match {} replayed from its history",
+                client_id,
+                total.normalize(),
+                expected.normalize()
+            ),
+            Discrepancy::Balances {
+                client_id,
+                total,
+                expected,
+            } => write!(
+                f,
+                "client {}: total {} does not equal its available, held and pending funds of {}",
+                client_id,
+                total.normalize(),
+                expected.normalize()
+            ),
+        }
+    }
+}
+
+// Writer retrying transient errors from the underlying output with exponential backoff.
+// A failed write accepts no bytes, so retrying the same buffer never duplicates or drops part of a row,
+// and partial writes are continued from where they stopped by the `write_all` of the csv writer.
+// Other errors, including BrokenPipe, are returned immediately.
+struct RetryWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> RetryWriter<W> {
+    fn retry<T>(&mut self, mut operation: impl FnMut(&mut W) -> io::Result<T>) -> io::Result<T> {
+        let mut backoff = WRITE_RETRY_BACKOFF;
+        let mut retries = 0;
+        loop {
+            match operation(&mut self.inner) {
+                Err(err) if retries < WRITE_RETRIES && is_transient(&err) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for RetryWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.retry(|inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.retry(|inner| inner.flush())
+    }
 }
 
-// Custom Serialiser to round transaction amount to 4.d.p. Runs on point of serialisation.
-fn round_serialize<S>(x: &f64, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let rounded_to_precision = (x * 10_000.0).round() / 10_000.0;
-    s.serialize_f64(rounded_to_precision)
+// Errors that may succeed if the write is attempted again.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    )
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -46,7 +373,65 @@ impl ClientDb {
     // database would exist in real-life scenario and would init associated function
     // would create database connection.
     pub fn init() -> Self {
-        ClientDb { db: HashMap::new() }
+        ClientDb::with_storage(Map::new())
+    }
+
+    // Split the database into `shards` databases by client id, keeping which clients were modified.
+    pub fn into_shards(self, shards: usize) -> Vec<ClientDb> {
+        let mut shard_dbs: Vec<ClientDb> = (0..shards).map(|_| ClientDb::init()).collect();
+        for (client_id, client) in self.db {
+            let shard = &mut shard_dbs[usize::from(client_id) % shards];
+            if self.modified.contains(&client_id) {
+                shard.mark_modified(client_id);
+            }
+            shard.insert_client_record(client);
+        }
+        shard_dbs
+    }
+
+    // Move every client record of another database into this one.
+    pub fn merge(&mut self, other: ClientDb) {
+        self.db.extend(other.db);
+        self.modified.extend(other.modified);
+    }
+}
+
+impl<S: Storage<u16, Client>> ClientDb<S> {
+    // Database keeping its client records in the given storage, e.g. an integrator's persistent store.
+    pub fn with_storage(db: S) -> Self {
+        ClientDb {
+            db,
+            modified: Set::new(),
+        }
+    }
+
+    // Load client records from a csv snapshot in the output format of a previous run, with each balance rounded
+    // to the precision amounts are read with. Loaded records are not considered modified by the current run.
+    pub fn load_snapshot<R: Read>(
+        &mut self,
+        mut rdr: Reader<R>,
+        config: &EngineConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let round = |value| config.rounding.round(value, config.amount_precision());
+        for row in rdr.deserialize() {
+            let mut client: Client = row?;
+            client.available = round(client.available);
+            client.held = round(client.held);
+            client.total = round(client.total);
+            client.pending = round(client.pending);
+            client.total_deposited = Amount::new(round(client.total_deposited.value()))?;
+            client.total_withdrawn = Amount::new(round(client.total_withdrawn.value()))?;
+            client.was_ever_locked |= client.locked;
+            client.opening_total =
+                client.total - client.total_deposited.value() + client.total_withdrawn.value();
+            self.insert_client_record(client);
+        }
+        Ok(())
+    }
+
+    // Record that a transaction changed the client's balances during this run.
+    pub fn mark_modified(&mut self, client_id: u16) {
+        self.modified.insert(client_id);
     }
 
     // Insert a Client record into the db with id as key
@@ -59,16 +444,246 @@ impl ClientDb {
         self.db.get_mut(client_id)
     }
 
-    // Write client database as csv to stdout with headers
-    pub fn to_csv_stdout(&self) -> Result<(), Box<dyn Error>> {
-        let mut writer = WriterBuilder::new().has_headers(true).from_writer(vec![]);
-        for client in self.db.values() {
-            writer.serialize(client)?;
+    // Get a mutable reference to a client record given an id, first inserting a new client if there is none.
+    pub fn get_or_create_client_record(&mut self, client_id: u16) -> &mut Client {
+        self.db
+            .get_or_insert_with(client_id, || Client::new(client_id))
+    }
+
+    // Iterate over immutable references to every client record in the db.
+    pub fn client_records(&self) -> impl Iterator<Item = &Client> {
+        self.db.iter().map(|(_, client)| client)
+    }
+
+    // Credit rounding residues to the suspense account, creating it if needed, so it is always written out.
+    pub fn post_to_suspense(&mut self, client_id: u16, residue: Decimal) {
+        let suspense = self.get_or_create_client_record(client_id);
+        suspense.available += residue;
+        suspense.total += residue;
+        suspense.rounding_residues += residue;
+        self.mark_modified(client_id);
+    }
+
+    // Sum of available funds across all clients, or None if it is too large to represent.
+    pub fn total_available(&self) -> Option<Decimal> {
+        checked_sum(self.client_records().map(|client| client.available))
+    }
+
+    // Sum of held funds across all clients, or None if it is too large to represent.
+    pub fn total_held(&self) -> Option<Decimal> {
+        checked_sum(self.client_records().map(|client| client.held))
+    }
+
+    // Check every client's balances against its history, returning any that do not add up ordered by client id.
+    // A client's total must equal its opening total plus deposits, less withdrawals and chargebacks, plus any
+    // interest, rounding residues or disputed withdrawals credited, and must equal its available, held and
+    // pending funds combined.
+    pub fn audit(&self) -> Vec<Discrepancy> {
+        let mut discrepancies: Vec<Discrepancy> = self
+            .client_records()
+            .flat_map(|client| {
+                let replayed = client.opening_total + client.total_deposited.value()
+                    - client.total_withdrawn.value()
+                    - client.charged_back
+                    + client.interest_credited
+                    + client.rounding_residues
+                    + client.reclaimed_withdrawals;
+                let combined = client.available + client.held + client.pending;
+                [
+                    (replayed != client.total).then_some(Discrepancy::History {
+                        client_id: client.client_id,
+                        total: client.total,
+                        expected: replayed,
+                    }),
+                    (combined != client.total).then_some(Discrepancy::Balances {
+                        client_id: client.client_id,
+                        total: client.total,
+                        expected: combined,
+                    }),
+                ]
+            })
+            .flatten()
+            .collect();
+        discrepancies.sort_by_key(Discrepancy::client_id);
+        discrepancies
+    }
+
+    // Count locked clients, clients with held funds and clients with a zero total balance.
+    pub fn summary(&self) -> ClientSummary {
+        self.client_records()
+            .fold(ClientSummary::default(), |mut summary, client| {
+                summary.clients += 1;
+                summary.locked += usize::from(client.locked);
+                summary.held += usize::from(!client.held.is_zero());
+                summary.zero_balance += usize::from(client.total.is_zero());
+                summary
+            })
+    }
+
+    // Write client database as csv to a buffered stdout with headers.
+    // Returns the checksum of the written output for the run metadata.
+    pub fn to_csv_stdout(&self, output: &OutputConfig) -> Result<String, Box<dyn Error>> {
+        let stdout = std::io::stdout();
+        self.to_csv_writer(BufWriter::new(stdout.lock()), output)
+    }
+
+    // Write client database as csv with headers to the writer, e.g. an output file.
+    // Returns the checksum of the written output for the run metadata.
+    pub fn to_csv_writer<W: Write>(
+        &self,
+        writer: W,
+        output: &OutputConfig,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut writer = ChecksumWriter::new(writer);
+        self.write_csv(&mut writer, output)?;
+        Ok(writer.hex_digest())
+    }
+
+    // Write client database to the writer in the configured format, returning the checksum of the written
+    // output for the run metadata.
+    pub fn write_output<W: Write>(
+        &self,
+        writer: W,
+        output: &OutputConfig,
+    ) -> Result<String, Box<dyn Error>> {
+        match output.format {
+            OutputFormat::Csv => self.to_csv_writer(writer, output),
+            OutputFormat::Json => self.to_json_writer(writer, output),
+        }
+    }
+
+    // Write client database as a JSON array of client objects to the writer, in the configured order, with the
+    // same fields and rounding as the csv columns and balances written as numbers.
+    // Returns the checksum of the written output for the run metadata.
+    pub fn to_json_writer<W: Write>(
+        &self,
+        writer: W,
+        output: &OutputConfig,
+    ) -> Result<String, Box<dyn Error>> {
+        self.check_client_id_width(output)?;
+        let mut clients: Vec<&Client> = self.output_records(output).collect();
+        sort_clients(&mut clients, output);
+        let records: Vec<ClientRecord> = clients
+            .into_iter()
+            .map(|client| ClientRecord { client, output })
+            .collect();
+        let mut writer = ChecksumWriter::new(RetryWriter { inner: writer });
+        serde_json::to_writer(&mut writer, &records)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(writer.hex_digest())
+    }
+
+    // Write client database as csv with headers to any writer, in client id order (or the configured order)
+    // so the output is stable between runs. Clients are serialised one at a time straight to the writer, flushing
+    // periodically, so the output is never held in memory as a whole. Optionally only clients modified by
+    // this run are written.
+    pub fn write_csv<W: Write>(&self, out: W, output: &OutputConfig) -> Result<(), Box<dyn Error>> {
+        self.check_client_id_width(output)?;
+        let mut clients: Vec<&Client> = self.output_records(output).collect();
+        sort_clients(&mut clients, output);
+        write_clients(out, clients.into_iter(), output)
+    }
+
+    // Write client database as csv split across the writers, each client going to shard
+    // `client_id % shards` in client id order (or the configured order), so downstream consumers can read the shards in parallel.
+    // Every shard is written with a header, even if no clients land in it.
+    pub fn write_csv_shards<W: Write>(
+        &self,
+        outs: Vec<W>,
+        output: &OutputConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_client_id_width(output)?;
+        let mut shards: Vec<Vec<&Client>> = vec![Vec::new(); outs.len()];
+        for client in self.output_records(output) {
+            let shard = usize::from(client.client_id) % outs.len();
+            shards[shard].push(client);
+        }
+        for (out, mut clients) in outs.into_iter().zip(shards) {
+            sort_clients(&mut clients, output);
+            write_clients(out, clients.into_iter(), output)?;
         }
-        let buf = writer.into_inner()?;
-        std::io::stdout().write_all(&buf)?;
         Ok(())
     }
+
+    // Check every client id to be written fits in the configured client id width, before anything is written,
+    // so a padded column never silently holds wider ids.
+    fn check_client_id_width(&self, output: &OutputConfig) -> Result<(), Box<dyn Error>> {
+        let width = match output.client_id_width {
+            Some(width) => width,
+            None => return Ok(()),
+        };
+        match self
+            .output_records(output)
+            .map(|client| client.client_id)
+            .max()
+            .filter(|client_id| client_id.to_string().len() > width as usize)
+        {
+            Some(client_id) => Err(format!(
+                "client id {} is wider than the client id width of {}",
+                client_id, width
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // Client records to write, optionally only those modified by this run.
+    fn output_records<'a>(
+        &'a self,
+        output: &'a OutputConfig,
+    ) -> impl Iterator<Item = &'a Client> + 'a {
+        self.client_records()
+            .filter(|client| !output.changed_only || self.modified.contains(&client.client_id))
+    }
+}
+
+// Order clients for output by the configured column, breaking ties by client id so the order is stable.
+fn sort_clients(clients: &mut [&Client], output: &OutputConfig) {
+    clients.sort_by(|a, b| {
+        let ordering = match output.sort_by {
+            SortBy::Client => a.client_id.cmp(&b.client_id),
+            SortBy::Available => a.available.cmp(&b.available),
+            SortBy::Held => a.held.cmp(&b.held),
+            SortBy::Total => a.total.cmp(&b.total),
+        };
+        let ordering = match output.descending {
+            true => ordering.reverse(),
+            false => ordering,
+        };
+        ordering.then(a.client_id.cmp(&b.client_id))
+    });
+}
+
+// Write client records as csv to any writer, flushing periodically.
+// Transient write errors are retried rather than failing the run.
+// When configured, a schema comment line is written ahead of the header. The header is always written,
+// even when there are no clients.
+fn write_clients<'a, W: Write>(
+    out: W,
+    clients: impl Iterator<Item = &'a Client>,
+    output: &OutputConfig,
+) -> Result<(), Box<dyn Error>> {
+    let mut out = RetryWriter { inner: out };
+    if output.schema_comment {
+        writeln!(out, "{}", output.schema_comment_line())?;
+    }
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(out);
+    writer.write_record(output.columns())?;
+    for (written, client) in clients.enumerate() {
+        writer.serialize(ClientRecord { client, output })?;
+        if (written + 1) % FLUSH_EVERY_N_CLIENTS == 0 {
+            writer.flush()?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Exact sum of balances, or None if it overflows. Every balance fits on its own, but a sum across many
+// clients near the maximum need not.
+fn checked_sum(mut values: impl Iterator<Item = Decimal>) -> Option<Decimal> {
+    values.try_fold(Decimal::ZERO, Decimal::checked_add)
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -80,105 +695,567 @@ impl Client {
     pub fn new(client_id: u16) -> Self {
         Client {
             client_id,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            total: Decimal::ZERO,
             locked: false,
+            was_ever_locked: false,
+            pending: Decimal::ZERO,
+            total_deposited: Amount::ZERO,
+            total_withdrawn: Amount::ZERO,
+            interest_accrued_to: None,
+            opening_total: Decimal::ZERO,
+            interest_credited: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            rounding_residues: Decimal::ZERO,
+            reclaimed_withdrawals: Decimal::ZERO,
         }
     }
 
+    // Funds the client can use.
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    // Funds held by open disputes.
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    // All of the client's funds, available, held and pending release.
+    pub fn total(&self) -> Decimal {
+        self.total
+    }
+
+    // True if the account has been locked by a chargeback.
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
     // Handler function for type of transaction. Performs respective associated function on the client record.
-    // If account is locked then early return as no mutations to the client record should take place.
+    // If account is locked then early return as no mutations to the client record should take place,
+    // unless the configuration allows disputes (including further chargebacks) on locked accounts.
+    // Claims are applied using the referenced transaction, as looked up from the transaction db beforehand.
+    // Returns the reason the transaction was rejected if it could not be applied. Interest on held funds is
+    // accrued before the transaction is applied, and only kept if it is.
     pub fn apply_transaction_to_client(
         &mut self,
         transaction: &Transaction,
-        transaction_db: &TransactionDb,
-    ) {
-        if self.locked {
-            return;
+        referenced: Option<&ReferencedTransaction>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        if self.locked && !self.locked_allows(transaction.transaction_type, config) {
+            return Err(TransactionError::AccountLocked {
+                transaction_type: transaction.transaction_type,
+                client_id: self.client_id,
+            });
+        }
+
+        let before_interest = (
+            self.available,
+            self.total,
+            self.interest_credited,
+            self.interest_accrued_to,
+        );
+        if let (Some(rate), Some(timestamp)) = (config.held_interest_rate, transaction.timestamp) {
+            self.accrue_held_interest(rate, timestamp, transaction.transaction_type, config)?;
         }
 
-        match transaction.transaction_type {
+        let result = match transaction.transaction_type {
             TransactionType::Deposit => self.deposit(transaction.amount),
-            TransactionType::Withdrawal => self.withdrawal(transaction.amount),
-            TransactionType::Dispute => self.dispute(transaction.transaction_id, transaction_db),
-            TransactionType::Resolve => self.resolve(transaction.transaction_id, transaction_db),
+            TransactionType::Withdrawal => self.withdrawal(transaction.amount, config),
+            TransactionType::Dispute => self.dispute(transaction, referenced, config),
+            TransactionType::Resolve => {
+                self.resolve(transaction.transaction_id, referenced, config)
+            }
             TransactionType::Chargeback => {
-                self.chargeback(transaction.transaction_id, transaction_db)
+                self.chargeback(transaction.transaction_id, referenced, config)
             }
+            TransactionType::Release => {
+                self.release(transaction.transaction_id, referenced, config)
+            }
+            TransactionType::Unlock => self.unlock(),
+            TransactionType::Correction => self.correct(transaction, referenced),
+            // Clock rows are consumed by `apply_transactions` and have no effect on a client.
+            TransactionType::Clock => Ok(()),
+        };
+        // A rejected transaction leaves the account untouched, including the interest accrued up to it.
+        if result.is_err() {
+            (
+                self.available,
+                self.total,
+                self.interest_credited,
+                self.interest_accrued_to,
+            ) = before_interest;
         }
+        result
     }
 
-    // Updates client account following deposit.
-    // If deposit amount is missing, ignore as a bad transaction and do nothing to client account.
-    fn deposit(&mut self, deposit_amount: Option<f64>) {
-        if let Some(amount) = deposit_amount {
-            self.total += amount;
-            self.available += amount;
+    // True if the transaction type may still be applied to a locked account.
+    // An unlock is always allowed, as it only applies to locked accounts.
+    fn locked_allows(&self, transaction_type: TransactionType, config: &EngineConfig) -> bool {
+        if transaction_type == TransactionType::Unlock {
+            return true;
+        }
+        match config.locked_allows {
+            LockedAllows::Nothing => false,
+            LockedAllows::Disputes => !matches!(
+                transaction_type,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Correction
+            ),
+        }
+    }
+
+    // Credits simple interest on held funds for every whole accrual period elapsed since interest was
+    // last accrued, rounded to the amount precision with the configured mode. The interest itself is not disputed so is
+    // credited as available. The first timestamped transaction for a client starts the accrual clock.
+    // Rejects the transaction if the interest would overflow the balances, leaving the interest unaccrued.
+    fn accrue_held_interest(
+        &mut self,
+        rate: Decimal,
+        timestamp: u64,
+        transaction_type: TransactionType,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let accrued_to = *self.interest_accrued_to.get_or_insert(timestamp);
+        let periods = timestamp.saturating_sub(accrued_to) / INTEREST_ACCRUAL_PERIOD_SECS;
+        if periods == 0 {
+            return Ok(());
         }
+        let interest = self
+            .held
+            .checked_mul(rate)
+            .and_then(|interest| interest.checked_mul(Decimal::from(periods)))
+            .ok_or(TransactionError::AmountOverflow {
+                transaction_type,
+                client_id: self.client_id,
+            })?;
+        let interest = config.rounding.round(interest, config.amount_precision());
+        let available = self.adjusted(self.available, interest, transaction_type)?;
+        let total = self.adjusted(self.total, interest, transaction_type)?;
+        let interest_credited =
+            self.adjusted(self.interest_credited, interest, transaction_type)?;
+        self.available = available;
+        self.total = total;
+        self.interest_credited = interest_credited;
+        self.interest_accrued_to = Some(accrued_to + periods * INTEREST_ACCRUAL_PERIOD_SECS);
+        Ok(())
+    }
+
+    // Balance after adding a (possibly negative) change to it, rejecting the transaction if it would
+    // overflow. Every balance a transaction changes is adjusted before any is updated, so a rejected
+    // transaction leaves the account untouched.
+    fn adjusted(
+        &self,
+        balance: Decimal,
+        change: Decimal,
+        transaction_type: TransactionType,
+    ) -> Result<Decimal, TransactionError> {
+        balance
+            .checked_add(change)
+            .ok_or(TransactionError::AmountOverflow {
+                transaction_type,
+                client_id: self.client_id,
+            })
+    }
+
+    // Updates client account following deposit.
+    // If deposit amount is missing, reject as a bad transaction and do nothing to client account.
+    fn deposit(&mut self, deposit_amount: Option<Amount>) -> Result<(), TransactionError> {
+        let amount = deposit_amount.ok_or(TransactionError::MissingAmount {
+            transaction_type: TransactionType::Deposit,
+            client_id: self.client_id,
+        })?;
+        let total_deposited =
+            self.cumulative_sum(self.total_deposited, amount, TransactionType::Deposit)?;
+        let total = self.adjusted(self.total, amount.value(), TransactionType::Deposit)?;
+        let available = self.adjusted(self.available, amount.value(), TransactionType::Deposit)?;
+        self.total_deposited = total_deposited;
+        self.total = total;
+        self.available = available;
+        Ok(())
+    }
+
+    // Adds an applied amount to one of the cumulative totals, rejecting the transaction if the total
+    // would overflow.
+    fn cumulative_sum(
+        &self,
+        cumulative: Amount,
+        amount: Amount,
+        transaction_type: TransactionType,
+    ) -> Result<Amount, TransactionError> {
+        cumulative
+            .checked_add(amount)
+            .ok_or(TransactionError::AmountOverflow {
+                transaction_type,
+                client_id: self.client_id,
+            })
+    }
+
+    // Amount the client can currently withdraw: available funds less any configured minimum balance, and
+    // no more than the share of available funds outside any configured reserve.
+    // Available is divided before it is multiplied so that a balance near the maximum cannot overflow.
+    pub fn withdrawable(&self, config: &EngineConfig) -> Decimal {
+        let unreserved =
+            self.available / Decimal::ONE_HUNDRED * (Decimal::ONE_HUNDRED - config.reserve_pct);
+        self.available
+            .saturating_sub(config.minimum_balance)
+            .min(unreserved)
+            .max(Decimal::ZERO)
     }
 
     // Updates Client account following withdrawal
-    // If withdrawal amount is missing, ignore as a bad transaction and do nothing to client account.
-    fn withdrawal(&mut self, withdrawal_amount: Option<f64>) {
-        if let Some(amount) = withdrawal_amount {
-            match amount < self.available {
-                true => {
-                    self.available -= amount;
-                    self.total -= amount;
-                }
-                false => {}
-            }
+    // If withdrawal amount is missing or exceeds withdrawable funds, reject and do nothing to client account.
+    fn withdrawal(
+        &mut self,
+        withdrawal_amount: Option<Amount>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let amount = withdrawal_amount.ok_or(TransactionError::MissingAmount {
+            transaction_type: TransactionType::Withdrawal,
+            client_id: self.client_id,
+        })?;
+        let withdrawable = self.withdrawable(config);
+        if amount.value() <= withdrawable {
+            let total_withdrawn =
+                self.cumulative_sum(self.total_withdrawn, amount, TransactionType::Withdrawal)?;
+            let available =
+                self.adjusted(self.available, -amount.value(), TransactionType::Withdrawal)?;
+            let total = self.adjusted(self.total, -amount.value(), TransactionType::Withdrawal)?;
+            self.total_withdrawn = total_withdrawn;
+            self.available = available;
+            self.total = total;
+            Ok(())
+        } else {
+            Err(TransactionError::InsufficientFunds {
+                client_id: self.client_id,
+                amount: amount.value(),
+                available: withdrawable,
+            })
         }
     }
 
-    // Retrieves original transaction data following a dispute claim.
-    // If original transaction data doesn't exist or
-    // there is no corresponding amount for the specified transaction then the dispute is ignored.
-    fn dispute(&mut self, transaction_id: u32, transaction_db: &TransactionDb) {
-        let transaction_data = transaction_db.retrieve_transaction_data(&transaction_id);
-        if let Some(tx) = transaction_data {
-            match tx.amount {
-                Some(value) => {
-                    self.available -= value;
-                    self.held += value;
-                }
-                None => {}
-            }
+    // Retrieves the amount of the original transaction referenced by a dispute/resolve/chargeback claim,
+    // as corrected if it has since been corrected. Rejects the claim if the original transaction data doesn't exist,
+    // it belongs to a different client, or there is no corresponding amount for the specified transaction.
+    fn referenced_amount(
+        &self,
+        transaction_type: TransactionType,
+        transaction_id: u32,
+        referenced: Option<&ReferencedTransaction>,
+    ) -> Result<Decimal, TransactionError> {
+        let tx = referenced.ok_or(TransactionError::UnknownTransaction {
+            transaction_type,
+            transaction_id,
+        })?;
+        if tx.client_id != self.client_id {
+            return Err(TransactionError::ClientMismatch {
+                transaction_type,
+                transaction_id,
+                client_id: self.client_id,
+                owner_id: tx.client_id,
+            });
+        }
+        tx.amount
+            .map(Amount::value)
+            .ok_or(TransactionError::NoDisputableAmount {
+                transaction_type,
+                transaction_id,
+            })
+    }
+
+    // Rejects a resolve/chargeback claim unless the referenced transaction is currently under dispute,
+    // so funds that were never held (or have already been released) cannot be moved.
+    fn require_disputed(
+        transaction_type: TransactionType,
+        transaction_id: u32,
+        referenced: Option<&ReferencedTransaction>,
+    ) -> Result<(), TransactionError> {
+        match referenced.and_then(|tx| tx.dispute_state) {
+            Some(DisputeState::Disputed) => Ok(()),
+            _ => Err(TransactionError::NotDisputed {
+                transaction_type,
+                transaction_id,
+            }),
         }
     }
 
-    // Retrieves original transaction data following a resolve claim.
-    // If original transaction data doesn't exist or
-    // there is no corresponding amount for the specified transaction then the resolve is ignored.
-    fn resolve(&mut self, transaction_id: u32, transaction_db: &TransactionDb) {
-        let transaction_data = transaction_db.retrieve_transaction_data(&transaction_id);
-        if let Some(tx) = transaction_data {
-            match tx.amount {
-                Some(value) => {
-                    self.available += value;
-                    self.held -= value;
+    // True if the transaction referenced by a claim is a withdrawal, whose funds have already left the
+    // account, rather than a deposit.
+    fn references_withdrawal(referenced: Option<&ReferencedTransaction>) -> bool {
+        referenced.is_some_and(|tx| tx.transaction_type == TransactionType::Withdrawal)
+    }
+
+    // Amount covered by the referenced transaction's current dispute, which is all of the transaction's
+    // amount unless the dispute was partial.
+    fn disputed_amount(
+        &self,
+        transaction_type: TransactionType,
+        transaction_id: u32,
+        referenced: Option<&ReferencedTransaction>,
+    ) -> Result<Decimal, TransactionError> {
+        let amount = self.referenced_amount(transaction_type, transaction_id, referenced)?;
+        Ok(referenced
+            .and_then(|tx| tx.disputed)
+            .map_or(amount, Amount::value))
+    }
+
+    // Holds the original transaction's amount following a dispute claim, or only the dispute's own amount
+    // for a partial dispute. Anything already charged back cannot be disputed again.
+    // Rejects the claim if the transaction is already under dispute, so its amount is never held twice.
+    // If the amount exceeds available funds the configured policy decides whether available may go
+    // negative, the dispute is rejected, or only the available funds are held.
+    // A disputed withdrawal has already left the account, so available funds are untouched and the amount
+    // is instead credited back into held funds (increasing the total) until the dispute is settled.
+    fn dispute(
+        &mut self,
+        dispute: &Transaction,
+        referenced: Option<&ReferencedTransaction>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let transaction_id = dispute.transaction_id;
+        self.referenced_amount(TransactionType::Dispute, transaction_id, referenced)?;
+        if referenced.and_then(|tx| tx.dispute_state) == Some(DisputeState::Disputed) {
+            return Err(TransactionError::AlreadyDisputed { transaction_id });
+        }
+        let disputable = referenced.and_then(|tx| tx.disputable).unwrap_or_default();
+        if disputable == Amount::ZERO {
+            return Err(TransactionError::FullyChargedBack { transaction_id });
+        }
+        let mut value = match dispute.amount {
+            Some(amount) if amount.value() > disputable.value() => {
+                return Err(TransactionError::DisputeExceedsDisputable {
+                    transaction_id,
+                    amount: amount.value(),
+                    disputable: disputable.value(),
+                })
+            }
+            Some(amount) => amount.value(),
+            None => disputable.value(),
+        };
+        if Self::references_withdrawal(referenced) {
+            let held = self.adjusted(self.held, value, TransactionType::Dispute)?;
+            let total = self.adjusted(self.total, value, TransactionType::Dispute)?;
+            let reclaimed_withdrawals =
+                self.adjusted(self.reclaimed_withdrawals, value, TransactionType::Dispute)?;
+            self.held = held;
+            self.total = total;
+            self.reclaimed_withdrawals = reclaimed_withdrawals;
+            return Ok(());
+        }
+        if value > self.available {
+            match config.negative_available {
+                NegativeAvailablePolicy::Allow => {}
+                // Only the available funds are held, and recorded as what the dispute covers.
+                NegativeAvailablePolicy::Clamp if self.available > Decimal::ZERO => {
+                    value = self.available
+                }
+                // A dispute that would hold nothing is rejected rather than recorded.
+                NegativeAvailablePolicy::Reject | NegativeAvailablePolicy::Clamp => {
+                    return Err(TransactionError::InsufficientFundsForDispute {
+                        client_id: self.client_id,
+                        transaction_id,
+                        amount: value,
+                        available: self.available,
+                    })
                 }
-                None => {}
             }
         }
+        let available = self.adjusted(self.available, -value, TransactionType::Dispute)?;
+        let held = self.adjusted(self.held, value, TransactionType::Dispute)?;
+        self.available = available;
+        self.held = held;
+        Ok(())
     }
 
-    // Retrieves original transaction data following a chargeback claim.
-    // If original transaction data doesn't exist or
-    // there is no corresponding amount for the specified transaction then the chargeback is ignored.
-    fn chargeback(&mut self, transaction_id: u32, transaction_db: &TransactionDb) {
-        let transaction_data = transaction_db.retrieve_transaction_data(&transaction_id);
-        if let Some(tx) = transaction_data {
-            match tx.amount {
-                Some(value) => {
-                    self.held -= value;
-                    self.total -= value;
-                    self.locked = true
-                }
-                None => {}
+    // Releases the original transaction's held amount following a resolve claim, either straight to
+    // available funds or to funds pending release depending on the configuration.
+    // Rejects the resolve of a charged back transaction, whose funds are gone, however it was disputed.
+    // Resolving a disputed withdrawal upholds it, so the held amount leaves the account again.
+    fn resolve(
+        &mut self,
+        transaction_id: u32,
+        referenced: Option<&ReferencedTransaction>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let value = self.disputed_amount(TransactionType::Resolve, transaction_id, referenced)?;
+        // A resolve after a chargeback must not return funds that have already left the account.
+        if referenced.and_then(|tx| tx.dispute_state) == Some(DisputeState::ChargedBack) {
+            return Err(TransactionError::ResolveAfterChargeback { transaction_id });
+        }
+        Self::require_disputed(TransactionType::Resolve, transaction_id, referenced)?;
+        if Self::references_withdrawal(referenced) {
+            let held = self.adjusted(self.held, -value, TransactionType::Resolve)?;
+            let total = self.adjusted(self.total, -value, TransactionType::Resolve)?;
+            let reclaimed_withdrawals =
+                self.adjusted(self.reclaimed_withdrawals, -value, TransactionType::Resolve)?;
+            self.held = held;
+            self.total = total;
+            self.reclaimed_withdrawals = reclaimed_withdrawals;
+            return Ok(());
+        }
+        let held = self.adjusted(self.held, -value, TransactionType::Resolve)?;
+        match config.resolve_to {
+            ResolveTo::Available => {
+                self.available = self.adjusted(self.available, value, TransactionType::Resolve)?
+            }
+            ResolveTo::Pending => {
+                self.pending = self.adjusted(self.pending, value, TransactionType::Resolve)?
             }
         }
+        self.held = held;
+        Ok(())
+    }
+
+    // Moves a resolved transaction's amount from pending to available funds following a release claim.
+    // Rejects the release unless the transaction's dispute was resolved into pending funds.
+    fn release(
+        &mut self,
+        transaction_id: u32,
+        referenced: Option<&ReferencedTransaction>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let value = self.disputed_amount(TransactionType::Release, transaction_id, referenced)?;
+        let resolved = referenced.and_then(|tx| tx.dispute_state) == Some(DisputeState::Resolved);
+        // A resolved withdrawal dispute is never moved to pending funds.
+        if config.resolve_to != ResolveTo::Pending
+            || !resolved
+            || Self::references_withdrawal(referenced)
+        {
+            return Err(TransactionError::NothingPending {
+                client_id: self.client_id,
+                transaction_id,
+            });
+        }
+        let value = value.min(self.pending);
+        self.available = self.adjusted(self.available, value, TransactionType::Release)?;
+        self.pending -= value;
+        Ok(())
+    }
+
+    // Withdraws the original transaction's held amount following a chargeback claim, only reversing the
+    // disputed part of the transaction for a partial dispute. The account is locked by any chargeback, or
+    // only once the whole transaction has been charged back, depending on the configuration.
+    // Charging back a disputed withdrawal reverses it, crediting the held amount back to available funds.
+    fn chargeback(
+        &mut self,
+        transaction_id: u32,
+        referenced: Option<&ReferencedTransaction>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let amount =
+            self.referenced_amount(TransactionType::Chargeback, transaction_id, referenced)?;
+        let disputed =
+            self.disputed_amount(TransactionType::Chargeback, transaction_id, referenced)?;
+        Self::require_disputed(TransactionType::Chargeback, transaction_id, referenced)?;
+        let held = self.adjusted(self.held, -disputed, TransactionType::Chargeback)?;
+        if Self::references_withdrawal(referenced) {
+            self.available =
+                self.adjusted(self.available, disputed, TransactionType::Chargeback)?;
+        } else {
+            let total = self.adjusted(self.total, -disputed, TransactionType::Chargeback)?;
+            let charged_back =
+                self.adjusted(self.charged_back, disputed, TransactionType::Chargeback)?;
+            self.total = total;
+            self.charged_back = charged_back;
+        }
+        self.held = held;
+        let charged_back = referenced.map_or(Amount::ZERO, |tx| tx.dispute_amounts.charged_back);
+        let locks = match config.chargeback_locks {
+            ChargebackLocks::Any => true,
+            ChargebackLocks::Full => charged_back
+                .value()
+                .checked_add(disputed)
+                .is_none_or(|charged_back| charged_back >= amount),
+        };
+        if locks {
+            self.locked = true;
+            self.was_ever_locked = true;
+        }
+        Ok(())
+    }
+
+    // Adjusts the balances by the difference between a deposit/withdrawal's corrected amount and the amount
+    // it currently stands at, fixing a known error while the original stays on record. A reason is required
+    // for the audit trail. Corrections are rejected while the transaction is under dispute, or once any of
+    // it has been charged back, as those funds were held or reversed at the uncorrected amount.
+    // A correction taking funds out of the account (a smaller deposit or larger withdrawal) is rejected if
+    // it exceeds the available funds.
+    fn correct(
+        &mut self,
+        correction: &Transaction,
+        referenced: Option<&ReferencedTransaction>,
+    ) -> Result<(), TransactionError> {
+        let transaction_id = correction.transaction_id;
+        self.referenced_amount(TransactionType::Correction, transaction_id, referenced)?;
+        let current = referenced.and_then(|tx| tx.amount).unwrap_or_default();
+        let corrected = correction.amount.ok_or(TransactionError::MissingAmount {
+            transaction_type: TransactionType::Correction,
+            client_id: self.client_id,
+        })?;
+        let has_reason = correction
+            .reason
+            .as_deref()
+            .is_some_and(|reason| !reason.trim().is_empty());
+        if !has_reason {
+            return Err(TransactionError::MissingCorrectionReason { transaction_id });
+        }
+        let disputed = referenced.is_some_and(|tx| {
+            tx.dispute_state == Some(DisputeState::Disputed)
+                || tx.dispute_amounts.charged_back != Amount::ZERO
+        });
+        if disputed {
+            return Err(TransactionError::CorrectionOfDisputed { transaction_id });
+        }
+        let withdrawal = Self::references_withdrawal(referenced);
+        // Change in the funds the transaction brought into the account.
+        let change = match withdrawal {
+            true => current.value() - corrected.value(),
+            false => corrected.value() - current.value(),
+        };
+        if -change > self.available {
+            return Err(TransactionError::InsufficientFundsForCorrection {
+                client_id: self.client_id,
+                transaction_id,
+                amount: -change,
+                available: self.available,
+            });
+        }
+        // The cumulative total counts the corrected amount in place of the amount it stood at.
+        let cumulative = match withdrawal {
+            true => self.total_withdrawn,
+            false => self.total_deposited,
+        };
+        let cumulative = cumulative
+            .checked_sub(current)
+            .and_then(|cumulative| cumulative.checked_add(corrected))
+            .ok_or(TransactionError::AmountOverflow {
+                transaction_type: TransactionType::Correction,
+                client_id: self.client_id,
+            })?;
+        let available = self.adjusted(self.available, change, TransactionType::Correction)?;
+        let total = self.adjusted(self.total, change, TransactionType::Correction)?;
+        match withdrawal {
+            true => self.total_withdrawn = cumulative,
+            false => self.total_deposited = cumulative,
+        }
+        self.available = available;
+        self.total = total;
+        Ok(())
+    }
+
+    // Unfreezes a locked account, e.g. once a chargeback has been investigated. The account remains
+    // marked as having been locked.
+    fn unlock(&mut self) -> Result<(), TransactionError> {
+        if !self.locked {
+            return Err(TransactionError::AccountNotLocked {
+                client_id: self.client_id,
+            });
+        }
+        self.locked = false;
+        Ok(())
     }
 }
 
@@ -189,7 +1266,9 @@ impl Client {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::transaction;
+    use crate::config::{FieldPrecision, RoundingMode};
+    use crate::transaction::{self, TransactionDb};
+    use rust_decimal_macros::dec;
 
     // Helper function to create client and transction databases in test suite.
     fn create_client_transaction_dbs() -> (ClientDb, TransactionDb) {
@@ -201,74 +1280,243 @@ mod tests {
     #[test]
     fn deposit_correctly_credits_account() {
         // Ensure that when a despoist takes place that the correct mutations take place to both available and total funds.
-        let (mut client_db, transaction_db) = create_client_transaction_dbs();
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
         let client_id = 1u16;
         let client = Client::new(client_id);
         client_db.insert_client_record(client);
 
-        let deposit_amount = 100_f64;
-        let test_desposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: 1,
-            transaction_id: 1,
-            amount: Some(deposit_amount),
-        };
+        let deposit_amount = dec!(100);
+        let test_desposit = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(deposit_amount).ok(),
+        );
 
-        test_desposit.handle_transaction(&transaction_db, &mut client_db);
+        test_desposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record = client_db.get_client_record(&client_id).unwrap();
         assert_eq!(client_record.available, deposit_amount);
         assert_eq!(client_record.total, deposit_amount);
     }
 
+    #[test]
+    fn balance_overflow_rejected_without_change() {
+        // A deposit onto a balance at the maximum, or a dispute taking available past the minimum, would
+        // overflow. Each is rejected as an overflow rather than panicking, and leaves the balances untouched.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let mut client = Client::new(1);
+        client.available = Decimal::MAX;
+        client.total = Decimal::MAX;
+        client_db.insert_client_record(client);
+        let result = Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(1)).ok())
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            );
+        assert_eq!(
+            result,
+            Err(TransactionError::AmountOverflow {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, Decimal::MAX);
+        assert_eq!(client_record.total, Decimal::MAX);
+        assert_eq!(client_record.total_deposited, Amount::ZERO);
+
+        let config = EngineConfig {
+            negative_available: NegativeAvailablePolicy::Allow,
+            ..EngineConfig::default()
+        };
+        let deposit = Transaction::new(TransactionType::Deposit, 2, 2, Amount::new(dec!(10)).ok());
+        deposit
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        transaction_db.insert_transaction(deposit);
+        // Unwrap used here as we can say for certainty that the client record with id=2_u16 exists
+        client_db.get_client_record(&2).unwrap().available = Decimal::MIN;
+        let result = Transaction::new(TransactionType::Dispute, 2, 2, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::AmountOverflow {
+                transaction_type: TransactionType::Dispute,
+                client_id: 2,
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=2_u16 exists
+        let client_record = client_db.get_client_record(&2).unwrap();
+        assert_eq!(client_record.available, Decimal::MIN);
+        assert_eq!(client_record.held, Decimal::ZERO);
+    }
+
     #[test]
     fn withdraw_correctly_removes_balance() {
         // Checks whether after a withdrawal the correct mutations take place to both available and total funds.
-        let (mut client_db, transaction_db) = create_client_transaction_dbs();
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
         let client_id = 1u16;
-        let (deposit_amount, withdrawal_amount) = (500_f64, 100_f64);
+        let (deposit_amount, withdrawal_amount) = (dec!(500), dec!(100));
 
-        let test_deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: Some(deposit_amount),
-        };
-        let test_withdrawal = Transaction {
-            transaction_type: TransactionType::Withdrawal,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: Some(withdrawal_amount),
-        };
-        test_deposit.handle_transaction(&transaction_db, &mut client_db);
-        test_withdrawal.handle_transaction(&transaction_db, &mut client_db);
+        let test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            client_id,
+            1,
+            Amount::new(deposit_amount).ok(),
+        );
+        let test_withdrawal = Transaction::new(
+            TransactionType::Withdrawal,
+            client_id,
+            1,
+            Amount::new(withdrawal_amount).ok(),
+        );
+        test_deposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        test_withdrawal
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record = client_db.get_client_record(&client_id).unwrap();
         assert_eq!(client_record.total, deposit_amount - withdrawal_amount);
         assert_eq!(client_record.available, deposit_amount - withdrawal_amount)
     }
 
+    #[test]
+    fn withdraw_exact_balance_succeeds() {
+        // A withdrawal of the full available balance empties the account.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        )
+        .handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        )
+        .unwrap();
+        Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Amount::new(dec!(100.0)).ok(),
+        )
+        .handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        )
+        .unwrap();
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.total, dec!(0.0));
+    }
+
+    #[test]
+    fn withdraw_just_over_balance_rejected() {
+        // A withdrawal of the smallest amount over the available balance is still rejected.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        )
+        .handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        )
+        .unwrap();
+        let result = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Amount::new(dec!(100.0001)).ok(),
+        )
+        .handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::InsufficientFunds {
+                client_id: 1,
+                amount: dec!(100.0001),
+                available: dec!(100.0),
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(
+            client_db.get_client_record(&1).unwrap().available,
+            dec!(100.0)
+        );
+    }
+
     #[test]
     fn withdraw_does_nothing_if_not_enough_available() {
         // Tests that client total does not change if a withdrawal is greater than the avaialbe funds.
-        let (mut client_db, transaction_db) = create_client_transaction_dbs();
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
         let client_id = 1u16;
-        let (deposit_amount, withdrawal_amount) = (100_f64, 500_f64);
+        let (deposit_amount, withdrawal_amount) = (dec!(100), dec!(500));
 
-        let test_deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: Some(deposit_amount),
-        };
-        let test_withdrawal = Transaction {
-            transaction_type: TransactionType::Withdrawal,
-            client_id: client_id,
-            transaction_id: 2,
-            amount: Some(withdrawal_amount),
-        };
-        test_deposit.handle_transaction(&transaction_db, &mut client_db);
-        test_withdrawal.handle_transaction(&transaction_db, &mut client_db);
+        let test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            client_id,
+            1,
+            Amount::new(deposit_amount).ok(),
+        );
+        let test_withdrawal = Transaction::new(
+            TransactionType::Withdrawal,
+            client_id,
+            2,
+            Amount::new(withdrawal_amount).ok(),
+        );
+        test_deposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        let result = test_withdrawal.handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::InsufficientFunds {
+                client_id,
+                amount: withdrawal_amount,
+                available: deposit_amount,
+            })
+        );
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record_after_withdrawal = client_db.get_client_record(&client_id).unwrap();
         assert_eq!(client_record_after_withdrawal.total, deposit_amount);
@@ -279,28 +1527,35 @@ mod tests {
         // Tests whether a dispute correctly mutates the held and available balance of a client.
         let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
         let client_id = 1u16;
-        let deposit_and_disputed_amount = 100_f64;
+        let deposit_and_disputed_amount = dec!(100);
 
-        let test_deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: Some(deposit_and_disputed_amount),
-        };
-        let test_dispute = Transaction {
-            transaction_type: TransactionType::Dispute,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: None,
-        };
+        let test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            client_id,
+            1,
+            Amount::new(deposit_and_disputed_amount).ok(),
+        );
+        let test_dispute = Transaction::new(TransactionType::Dispute, client_id, 1, None);
 
-        test_deposit.handle_transaction(&transaction_db, &mut client_db);
+        test_deposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         transaction_db.insert_transaction(test_deposit);
-        test_dispute.handle_transaction(&transaction_db, &mut client_db);
+        test_dispute
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record = client_db.get_client_record(&client_id).unwrap();
         assert_eq!(client_record.held, deposit_and_disputed_amount);
-        assert_eq!(client_record.available, 0_f64);
+        assert_eq!(client_record.available, dec!(0));
         assert_eq!(client_record.total, deposit_and_disputed_amount);
     }
 
@@ -308,31 +1563,39 @@ mod tests {
     fn resolve_releases_held_funds() {
         let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
         let client_id = 1u16;
-        let held_amount = 100_f64;
+        let held_amount = dec!(100);
 
-        let test_deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: Some(100_f64),
-        };
-        let test_dispute = Transaction {
-            transaction_type: TransactionType::Dispute,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: None,
-        };
-        let test_resolution = Transaction {
-            transaction_type: TransactionType::Resolve,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: None,
-        };
+        let test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            client_id,
+            1,
+            Amount::new(dec!(100)).ok(),
+        );
+        let test_dispute = Transaction::new(TransactionType::Dispute, client_id, 1, None);
+        let test_resolution = Transaction::new(TransactionType::Resolve, client_id, 1, None);
 
-        test_deposit.handle_transaction(&transaction_db, &mut client_db);
+        test_deposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         transaction_db.insert_transaction(test_deposit);
-        test_dispute.handle_transaction(&transaction_db, &mut client_db);
-        test_resolution.handle_transaction(&transaction_db, &mut client_db);
+        test_dispute
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        test_resolution
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record_after_dispute = client_db.get_client_record(&client_id).unwrap();
         assert_eq!(client_record_after_dispute.available, held_amount);
@@ -343,58 +1606,545 @@ mod tests {
         let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
         let client_id = 1u16;
 
-        let test_deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: Some(100.0),
-        };
-        let test_chargeback = Transaction {
-            transaction_type: TransactionType::Chargeback,
-            client_id: client_id,
-            transaction_id: 1,
-            amount: None,
-        };
+        let test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            client_id,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        );
+        let test_dispute = Transaction::new(TransactionType::Dispute, client_id, 1, None);
+        let test_chargeback = Transaction::new(TransactionType::Chargeback, client_id, 1, None);
 
-        test_deposit.handle_transaction(&transaction_db, &mut client_db);
+        test_deposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         transaction_db.insert_transaction(test_deposit);
-        test_chargeback.handle_transaction(&transaction_db, &mut client_db);
+        test_dispute
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        test_chargeback
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record_after_chargeback = client_db.get_client_record(&client_id).unwrap();
-        assert_eq!(client_record_after_chargeback.locked, true);
+        assert!(client_record_after_chargeback.locked);
+    }
+
+    // Helper applying a deposit of 100 to client 1 and recording it in the transaction database.
+    fn deposit_100() -> (ClientDb, TransactionDb) {
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        );
+        test_deposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        transaction_db.insert_transaction(test_deposit);
+        (client_db, transaction_db)
+    }
+
+    #[test]
+    fn double_dispute_is_ignored() {
+        // A second dispute of a transaction already under dispute is rejected rather than holding its amount again.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        Transaction::new(TransactionType::Dispute, 1, 1, None)
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        let result = Transaction::new(TransactionType::Dispute, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::AlreadyDisputed { transaction_id: 1 })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(100.0));
+        assert_eq!(client_record.available, dec!(0.0));
+    }
+
+    #[test]
+    fn partial_chargeback_locks_by_default() {
+        // Disputing 40 of a deposit of 100 holds only 40, and charging it back reverses only the 40 but
+        // still locks the account by default.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        let config = EngineConfig::default();
+        for transaction in [
+            Transaction::new(TransactionType::Dispute, 1, 1, Amount::new(dec!(40)).ok()),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+        ] {
+            transaction
+                .handle_transaction(&mut transaction_db, &mut client_db, &config)
+                .unwrap();
+        }
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(60.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(60.0));
+        assert!(client_record.locked);
+        assert_eq!(
+            transaction_db.dispute_amounts(1).charged_back.value(),
+            dec!(40)
+        );
+    }
+
+    #[test]
+    fn dispute_over_transaction_amount_rejected() {
+        // A partial dispute cannot cover more than the transaction's amount.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        let result = Transaction::new(TransactionType::Dispute, 1, 1, Amount::new(dec!(150)).ok())
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            );
+        assert_eq!(
+            result,
+            Err(TransactionError::DisputeExceedsDisputable {
+                transaction_id: 1,
+                amount: dec!(150),
+                disputable: dec!(100),
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().held, dec!(0.0));
+    }
+
+    #[test]
+    fn resolve_without_dispute_rejected() {
+        // A resolve of a transaction that was never disputed must not release funds that were never held.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        let result = Transaction::new(TransactionType::Resolve, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::NotDisputed {
+                transaction_type: TransactionType::Resolve,
+                transaction_id: 1,
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(100.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(transaction_db.dispute_state(1), None);
+    }
+
+    #[test]
+    fn chargeback_without_dispute_rejected() {
+        // A chargeback of a transaction that was never disputed leaves the account untouched and unlocked.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        let result = Transaction::new(TransactionType::Chargeback, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::NotDisputed {
+                transaction_type: TransactionType::Chargeback,
+                transaction_id: 1,
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.total, dec!(100.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert!(!client_record.locked);
+    }
+
+    #[test]
+    fn chargeback_after_resolve_does_not_lock() {
+        // A transaction whose dispute was resolved no longer has funds held, so a later chargeback of it
+        // is rejected and must not lock the account.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        for transaction_type in [TransactionType::Dispute, TransactionType::Resolve] {
+            Transaction::new(transaction_type, 1, 1, None)
+                .handle_transaction(
+                    &mut transaction_db,
+                    &mut client_db,
+                    &EngineConfig::default(),
+                )
+                .unwrap();
+        }
+        let result = Transaction::new(TransactionType::Chargeback, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::NotDisputed {
+                transaction_type: TransactionType::Chargeback,
+                transaction_id: 1,
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(100.0));
+        assert_eq!(client_record.total, dec!(100.0));
+        assert!(!client_record.locked);
+    }
+
+    #[test]
+    fn resolve_clears_dispute() {
+        // A resolve ends the dispute, so a second resolve of the same transaction is rejected.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        for transaction_type in [TransactionType::Dispute, TransactionType::Resolve] {
+            Transaction::new(transaction_type, 1, 1, None)
+                .handle_transaction(
+                    &mut transaction_db,
+                    &mut client_db,
+                    &EngineConfig::default(),
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            transaction_db.dispute_state(1),
+            Some(DisputeState::Resolved)
+        );
+
+        let result = Transaction::new(TransactionType::Resolve, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::NotDisputed {
+                transaction_type: TransactionType::Resolve,
+                transaction_id: 1,
+            })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(100.0));
+        assert_eq!(client_record.held, dec!(0.0));
+    }
+
+    // Helper applying a deposit of 100 then a withdrawal (tx 2) of 30 to client 1, and disputing the withdrawal.
+    fn disputed_withdrawal_30() -> (ClientDb, TransactionDb) {
+        let (mut client_db, mut transaction_db) = deposit_100();
+        let test_withdrawal = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Amount::new(dec!(30.0)).ok(),
+        );
+        test_withdrawal
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        transaction_db.insert_transaction(test_withdrawal);
+        Transaction::new(TransactionType::Dispute, 1, 2, None)
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        (client_db, transaction_db)
+    }
+
+    #[test]
+    fn disputed_withdrawal_credits_held_funds() {
+        // The disputed withdrawal's 30 has already left available funds, so available is unchanged (70)
+        // while held and total both increase by 30, to 30 and 100. Unlike a disputed deposit, nothing is
+        // taken from available.
+        let (mut client_db, _) = disputed_withdrawal_30();
+        assert!(client_db.audit().is_empty());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(70.0));
+        assert_eq!(client_record.held, dec!(30.0));
+        assert_eq!(client_record.total, dec!(100.0));
+    }
+
+    #[test]
+    fn resolved_withdrawal_dispute_debits_held_funds() {
+        // Resolving upholds the withdrawal: held and total both decrease by 30, to 0 and 70, and available
+        // stays at 70. Unlike a resolved deposit dispute, nothing is returned to available.
+        let (mut client_db, mut transaction_db) = disputed_withdrawal_30();
+        Transaction::new(TransactionType::Resolve, 1, 2, None)
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        assert!(client_db.audit().is_empty());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(70.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(70.0));
+        assert!(!client_record.locked);
+    }
+
+    #[test]
+    fn charged_back_withdrawal_credits_available_funds() {
+        // Charging back reverses the withdrawal: the held 30 moves to available, which returns to 100, and
+        // the total stays at 100. Unlike a charged back deposit, the total is not reduced. The chargeback
+        // still locks the account.
+        let (mut client_db, mut transaction_db) = disputed_withdrawal_30();
+        Transaction::new(TransactionType::Chargeback, 1, 2, None)
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        assert!(client_db.audit().is_empty());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(100.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(100.0));
+        assert!(client_record.locked);
+    }
+
+    #[test]
+    fn resolve_after_chargeback_changes_nothing() {
+        // Once a deposit has been charged back its funds are gone, so a later resolve must not add phantom
+        // funds, whether it is stopped by the locked account or, when locked accounts still take disputes,
+        // by the transaction having been charged back.
+        for locked_allows in [LockedAllows::Nothing, LockedAllows::Disputes] {
+            let config = EngineConfig {
+                locked_allows,
+                ..EngineConfig::default()
+            };
+            let (mut client_db, mut transaction_db) = deposit_100();
+            for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                Transaction::new(transaction_type, 1, 1, None)
+                    .handle_transaction(&mut transaction_db, &mut client_db, &config)
+                    .unwrap();
+            }
+            let result = Transaction::new(TransactionType::Resolve, 1, 1, None).handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &config,
+            );
+            assert!(result.is_err());
+            if locked_allows == LockedAllows::Disputes {
+                assert_eq!(
+                    result,
+                    Err(TransactionError::ResolveAfterChargeback { transaction_id: 1 })
+                );
+            }
+            // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+            let client_record = client_db.get_client_record(&1).unwrap();
+            assert_eq!(client_record.available, dec!(0.0));
+            assert_eq!(client_record.held, dec!(0.0));
+            assert_eq!(client_record.total, dec!(0.0));
+            assert!(client_record.locked);
+        }
+    }
+
+    #[test]
+    fn resolve_after_withdrawal_chargeback_changes_nothing() {
+        // A charged back withdrawal has already been returned to the client, so a later resolve must not
+        // take it out of the account again.
+        let config = EngineConfig {
+            locked_allows: LockedAllows::Disputes,
+            ..EngineConfig::default()
+        };
+        let (mut client_db, mut transaction_db) = disputed_withdrawal_30();
+        Transaction::new(TransactionType::Chargeback, 1, 2, None)
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        let result = Transaction::new(TransactionType::Resolve, 1, 2, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::ResolveAfterChargeback { transaction_id: 2 })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(100.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(100.0));
+    }
+
+    // Helper correcting tx 1 of client 1 to the given amount, with a reason, recording the correction.
+    fn correct_tx_1(
+        client_db: &mut ClientDb,
+        transaction_db: &mut TransactionDb,
+        amount: Decimal,
+    ) -> Result<(), TransactionError> {
+        let mut correction =
+            Transaction::new(TransactionType::Correction, 1, 1, Amount::new(amount).ok());
+        correction.reason = Some("keying error".to_string());
+        let result =
+            correction.handle_transaction(transaction_db, client_db, &EngineConfig::default());
+        transaction_db.insert_transaction(correction);
+        result
+    }
+
+    #[test]
+    fn correction_adjusts_deposit() {
+        // Correcting the deposit of 100 to 90 takes the difference of 10 from available and total, and the
+        // cumulative deposited total counts the corrected 90. The original deposit stays on record, followed
+        // by the correction.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        correct_tx_1(&mut client_db, &mut transaction_db, dec!(90.0)).unwrap();
+        assert!(client_db.audit().is_empty());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(90.0));
+        assert_eq!(client_record.total, dec!(90.0));
+        assert_eq!(client_record.total_deposited.value(), dec!(90.0));
+        let lineage: Vec<_> = transaction_db
+            .lineage(1)
+            .iter()
+            .map(|transaction| (transaction.transaction_type, transaction.amount))
+            .collect();
+        assert_eq!(
+            lineage,
+            [
+                (TransactionType::Deposit, Amount::new(dec!(100.0)).ok()),
+                (TransactionType::Correction, Amount::new(dec!(90.0)).ok())
+            ]
+        );
+    }
+
+    #[test]
+    fn dispute_after_correction_holds_corrected_amount() {
+        // Once the deposit of 100 is corrected up to 120, a dispute of it holds the corrected 120.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        correct_tx_1(&mut client_db, &mut transaction_db, dec!(120.0)).unwrap();
+        Transaction::new(TransactionType::Dispute, 1, 1, None)
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.held, dec!(120.0));
+        assert_eq!(client_record.total, dec!(120.0));
+    }
+
+    #[test]
+    fn correction_of_disputed_deposit_rejected() {
+        // A deposit under dispute has its funds held at the uncorrected amount, so cannot be corrected
+        // until the dispute is settled.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        Transaction::new(TransactionType::Dispute, 1, 1, None)
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        let result = correct_tx_1(&mut client_db, &mut transaction_db, dec!(90.0));
+        assert_eq!(
+            result,
+            Err(TransactionError::CorrectionOfDisputed { transaction_id: 1 })
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(100.0));
+        assert_eq!(client_record.total, dec!(100.0));
+    }
+
+    #[test]
+    fn correction_without_reason_rejected() {
+        // A correction must give the reason it was made, for the audit trail.
+        let (mut client_db, mut transaction_db) = deposit_100();
+        let result = Transaction::new(
+            TransactionType::Correction,
+            1,
+            1,
+            Amount::new(dec!(90.0)).ok(),
+        )
+        .handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::MissingCorrectionReason { transaction_id: 1 })
+        );
     }
 
     #[test]
     fn locked_account_does_not_apply_transaction() {
         // Tests that a transaction will not alter a locked account.
-        let (mut client_db, transaction_db) = create_client_transaction_dbs();
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
 
         let locked_client = Client {
-            client_id: 1,
-            available: 100.0,
-            held: 0.0,
-            total: 100.0,
+            available: dec!(100.0),
+            total: dec!(100.0),
             locked: true,
+            ..Client::new(1)
         };
         client_db.insert_client_record(locked_client);
 
-        let test_transaction = Transaction {
-            transaction_type: TransactionType::Withdrawal,
-            client_id: 1,
-            transaction_id: 1,
-            amount: Some(100.0),
-        };
+        let test_transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        );
 
         // Duplicated as unnecessary to derive Copy and Clone on client for non test purposes.
         let original_client_record = Client {
-            client_id: 1,
-            available: 100.0,
-            held: 0.0,
-            total: 100.0,
+            available: dec!(100.0),
+            total: dec!(100.0),
             locked: true,
+            ..Client::new(1)
         };
 
-        test_transaction.handle_transaction(&transaction_db, &mut client_db);
+        let result = test_transaction.handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::AccountLocked {
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 1,
+            })
+        );
         // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
         let client_record = client_db
             .get_client_record(&original_client_record.client_id)
@@ -405,15 +2155,1314 @@ mod tests {
     #[test]
     fn unknown_client_creates_new_record() {
         // Tests to ensure that a new client record is created if a transaction references a client id that does not exist
-        let (mut client_db, transaction_db) = create_client_transaction_dbs();
-        let test_desposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: 1,
-            transaction_id: 1,
-            amount: Some(1_f64),
-        };
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let test_desposit =
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(1)).ok());
         assert!(client_db.db.is_empty());
-        test_desposit.handle_transaction(&transaction_db, &mut client_db);
+        test_desposit
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        assert_eq!(client_db.db.len(), 1);
+    }
+
+    #[test]
+    fn get_or_create_returns_same_record() {
+        // The first call for an unknown id stores a new client, and a second call for the same id returns
+        // that stored record rather than creating another.
+        let (mut client_db, _) = create_client_transaction_dbs();
+        client_db.get_or_create_client_record(1).available = dec!(5.0);
+        let client_record = client_db.get_or_create_client_record(1);
+        assert_eq!(client_record.client_id, 1);
+        assert_eq!(client_record.available, dec!(5.0));
         assert_eq!(client_db.db.len(), 1);
     }
+
+    // Helper applying the canonical negative-available sequence: deposit 100, withdraw 80, dispute the deposit.
+    fn dispute_after_withdrawal(config: &EngineConfig) -> (ClientDb, TransactionDb) {
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let test_transactions = vec![
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Amount::new(dec!(80.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        for transaction in test_transactions {
+            let _ = transaction.handle_transaction(&mut transaction_db, &mut client_db, config);
+            transaction_db.insert_transaction(transaction);
+        }
+        (client_db, transaction_db)
+    }
+
+    #[test]
+    fn negative_available_allow_holds_full_amount() {
+        // Default policy holds the whole disputed deposit, taking available negative.
+        let (mut client_db, _) = dispute_after_withdrawal(&EngineConfig::default());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(-80.0));
+        assert_eq!(client_record.held, dec!(100.0));
+        assert_eq!(client_record.total, dec!(20.0));
+    }
+
+    #[test]
+    fn negative_available_reject_ignores_dispute() {
+        // Reject policy leaves the account as it was after the withdrawal.
+        let config = EngineConfig {
+            negative_available: NegativeAvailablePolicy::Reject,
+            ..EngineConfig::default()
+        };
+        let (mut client_db, _) = dispute_after_withdrawal(&config);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(20.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(20.0));
+    }
+
+    #[test]
+    fn negative_available_clamp_holds_only_available() {
+        // Clamp policy holds only the remaining available funds, and a resolve releases no more than was held.
+        let config = EngineConfig {
+            negative_available: NegativeAvailablePolicy::Clamp,
+            ..EngineConfig::default()
+        };
+        let (mut client_db, mut transaction_db) = dispute_after_withdrawal(&config);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.held, dec!(20.0));
+        assert_eq!(client_record.total, dec!(20.0));
+
+        Transaction::new(TransactionType::Resolve, 1, 1, None)
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(20.0));
+        assert_eq!(client_record.held, dec!(0.0));
+    }
+
+    #[test]
+    fn negative_available_clamp_settles_only_what_each_dispute_held() {
+        // With 50 available, disputing the 30 deposit holds all of it, and disputing the 100 deposit is clamped
+        // to the remaining 20. Charging back the 100 deposit removes only the 20 it held, leaving the other
+        // dispute's 30 held until it is resolved (allowed on the now locked account).
+        let config = EngineConfig {
+            negative_available: NegativeAvailablePolicy::Clamp,
+            locked_allows: LockedAllows::Disputes,
+            ..EngineConfig::default()
+        };
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let test_transactions = vec![
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(30.0)).ok()),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Amount::new(dec!(80.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+        ];
+        for transaction in test_transactions {
+            transaction
+                .handle_transaction(&mut transaction_db, &mut client_db, &config)
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        assert_eq!(
+            transaction_db.dispute_amounts(1).charged_back.value(),
+            dec!(20)
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.held, dec!(30.0));
+        assert_eq!(client_record.total, dec!(30.0));
+
+        Transaction::new(TransactionType::Resolve, 1, 2, None)
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(30.0));
+        assert_eq!(client_record.held, dec!(0.0));
+    }
+
+    #[test]
+    fn negative_available_clamp_to_zero_rejected() {
+        // With nothing available a clamped dispute would hold nothing, so it is rejected and not recorded.
+        let config = EngineConfig {
+            negative_available: NegativeAvailablePolicy::Clamp,
+            ..EngineConfig::default()
+        };
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        for transaction in [
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+        ] {
+            transaction
+                .handle_transaction(&mut transaction_db, &mut client_db, &config)
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        assert_eq!(
+            Transaction::new(TransactionType::Dispute, 1, 1, None).handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &config
+            ),
+            Err(TransactionError::InsufficientFundsForDispute {
+                client_id: 1,
+                transaction_id: 1,
+                amount: dec!(100.0),
+                available: dec!(0.0),
+            })
+        );
+        assert_eq!(transaction_db.dispute_state(1), None);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(0.0));
+    }
+
+    #[test]
+    fn streamed_csv_matches_buffered_csv() -> Result<(), Box<dyn Error>> {
+        // Make sure streaming clients straight to the writer produces exactly the bytes of
+        // serialising the whole (id ordered) table into a buffer first.
+        let mut client_db = ClientDb::init();
+        for client_id in 0..2_500 {
+            let mut client = Client::new(client_id);
+            client.available = Decimal::from(client_id) * dec!(1.5);
+            client.total = client.available;
+            client_db.insert_client_record(client);
+        }
+
+        let output = OutputConfig::default();
+        let mut clients: Vec<&Client> = client_db.client_records().collect();
+        clients.sort_by_key(|client| client.client_id);
+        let mut buffered = WriterBuilder::new().has_headers(true).from_writer(vec![]);
+        for client in clients {
+            buffered.serialize(ClientRecord {
+                client,
+                output: &output,
+            })?;
+        }
+        let buffered = buffered.into_inner()?;
+
+        let mut streamed = Vec::new();
+        client_db.write_csv(&mut streamed, &output)?;
+        assert_eq!(streamed, buffered);
+        Ok(())
+    }
+
+    #[test]
+    fn held_funds_accrue_daily_interest() {
+        // Hold 1000 for three and a half days at 0.1% a day and make sure three days of interest are credited.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let config = EngineConfig {
+            held_interest_rate: Some(dec!(0.001)),
+            ..EngineConfig::default()
+        };
+        let start = 1_700_000_000;
+        let mut test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(1000.0)).ok(),
+        );
+        test_deposit.timestamp = Some(start);
+        let mut test_dispute = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        test_dispute.timestamp = Some(start);
+        let mut later_deposit =
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(1.0)).ok());
+        later_deposit.timestamp = Some(start + 3 * 86_400 + 43_200);
+
+        test_deposit
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        transaction_db.insert_transaction(test_deposit);
+        test_dispute
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        later_deposit
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(1000.0));
+        assert_eq!(client_record.available, dec!(3.0) + dec!(1.0));
+        assert_eq!(client_record.total, dec!(1000.0) + dec!(3.0) + dec!(1.0));
+        assert_eq!(client_record.interest_accrued_to, Some(start + 3 * 86_400));
+    }
+
+    #[test]
+    fn rejected_transactions_accrue_no_interest() {
+        // A deposit to a locked account and a withdrawal over the available funds, each days after funds were
+        // held, are rejected without crediting interest or moving the accrual clock on.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let config = EngineConfig {
+            held_interest_rate: Some(dec!(0.001)),
+            ..EngineConfig::default()
+        };
+        let start = 1_700_000_000;
+        let mut test_deposit = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(1000.0)).ok(),
+        );
+        test_deposit.timestamp = Some(start);
+        let mut test_dispute = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        test_dispute.timestamp = Some(start);
+        let mut test_withdrawal = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Amount::new(dec!(50.0)).ok(),
+        );
+        test_withdrawal.timestamp = Some(start + 3 * 86_400);
+        let mut locked_deposit =
+            Transaction::new(TransactionType::Deposit, 1, 3, Amount::new(dec!(1.0)).ok());
+        locked_deposit.timestamp = Some(start + 5 * 86_400);
+
+        test_deposit
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        transaction_db.insert_transaction(test_deposit);
+        test_dispute
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .unwrap();
+        assert!(test_withdrawal
+            .handle_transaction(&mut transaction_db, &mut client_db, &config)
+            .is_err());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        client_db.get_client_record(&1).unwrap().locked = true;
+        assert_eq!(
+            locked_deposit.handle_transaction(&mut transaction_db, &mut client_db, &config),
+            Err(TransactionError::AccountLocked {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+            })
+        );
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.total, dec!(1000.0));
+        assert_eq!(client_record.interest_credited, dec!(0));
+        assert_eq!(client_record.interest_accrued_to, Some(start));
+    }
+
+    #[test]
+    fn snapshot_clients_output_without_transactions() -> Result<(), Box<dyn Error>> {
+        // Load three clients from a snapshot, touch only client 2 and make sure all three are still written,
+        // including the client with zero balances.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let snapshot = "client,available,held,total,locked
+                        1,10.0,0.0,10.0,false
+                        2,20.0,0.0,20.0,false
+                        3,0.0,0.0,0.0,false";
+        client_db.load_snapshot(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(snapshot.as_bytes()),
+            &EngineConfig::default(),
+        )?;
+        Transaction::new(TransactionType::Deposit, 2, 1, Amount::new(dec!(5.0)).ok())
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )?;
+
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &OutputConfig::default())?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n\
+             1,10.0,0.0,10.0,false\n\
+             2,25.0,0.0,25.0,false\n\
+             3,0.0,0.0,0.0,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_balances_read_at_configured_precision() -> Result<(), Box<dyn Error>> {
+        // A snapshot written at 8 decimal places keeps every place when amounts are read to 8, and is rounded
+        // to 4 by default.
+        let snapshot = "client,available,held,total,locked
+                        1,0.12345678,0.00000001,0.12345679,false";
+        let load = |config: &EngineConfig| -> Result<(Decimal, Decimal), Box<dyn Error>> {
+            let mut client_db = ClientDb::init();
+            client_db.load_snapshot(
+                csv::ReaderBuilder::new()
+                    .trim(csv::Trim::All)
+                    .from_reader(snapshot.as_bytes()),
+                config,
+            )?;
+            // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+            let client_record = client_db.get_client_record(&1).unwrap();
+            Ok((client_record.available, client_record.held))
+        };
+        let config = EngineConfig {
+            precision: Some(8),
+            ..EngineConfig::default()
+        };
+        assert_eq!(load(&config)?, (dec!(0.12345678), dec!(0.00000001)));
+        assert_eq!(load(&EngineConfig::default())?, (dec!(0.1235), dec!(0)));
+        Ok(())
+    }
+
+    #[test]
+    fn audit_flags_tampered_balance() -> Result<(), Box<dyn Error>> {
+        // A healthy run over a snapshot client and a new client, with a withdrawal, a chargeback and an open
+        // dispute, reports no discrepancies. Tampering with a total is then flagged against both checks.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let snapshot = "client,available,held,total,locked
+                        2,20.0,0.0,20.0,false";
+        client_db.load_snapshot(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(snapshot.as_bytes()),
+            &EngineConfig::default(),
+        )?;
+        let transactions = vec![
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(50.0)).ok()),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Amount::new(dec!(20.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            Transaction::new(TransactionType::Chargeback, 1, 2, None),
+            Transaction::new(TransactionType::Deposit, 2, 4, Amount::new(dec!(5.0)).ok()),
+            Transaction::new(TransactionType::Dispute, 2, 4, None),
+        ];
+        for transaction in transactions {
+            transaction.handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )?;
+            transaction_db.insert_transaction(transaction);
+        }
+        assert_eq!(client_db.audit(), vec![]);
+
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        client_db.get_client_record(&1).unwrap().total += dec!(1.0);
+        assert_eq!(
+            client_db.audit(),
+            vec![
+                Discrepancy::History {
+                    client_id: 1,
+                    total: dec!(81.0),
+                    expected: dec!(80.0),
+                },
+                Discrepancy::Balances {
+                    client_id: 1,
+                    total: dec!(81.0),
+                    expected: dec!(80.0),
+                },
+            ]
+        );
+        assert_eq!(
+            client_db.audit()[0].to_string(),
+            "client 1: total 81 does not match 80 replayed from its history"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn balances_written_with_rounding_mode() -> Result<(), Box<dyn Error>> {
+        // The same balance is written rounded up under HalfUp and truncated under Truncate.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1.23455),
+            total: dec!(1.23455),
+            ..Client::new(1)
+        });
+        let written = |rounding: RoundingMode| -> Result<String, Box<dyn Error>> {
+            let output = OutputConfig {
+                rounding,
+                ..OutputConfig::default()
+            };
+            let mut written = Vec::new();
+            client_db.write_output(&mut written, &output)?;
+            Ok(String::from_utf8(written)?)
+        };
+        assert_eq!(
+            written(RoundingMode::HalfUp)?,
+            "client,available,held,total,locked\n1,1.2346,0.0,1.2346,false\n"
+        );
+        assert_eq!(
+            written(RoundingMode::Truncate)?,
+            "client,available,held,total,locked\n1,1.2345,0.0,1.2345,false\n"
+        );
+        Ok(())
+    }
+
+    // Helper writing three clients with distinct balances as csv in the given order, returning the client ids
+    // in the order written.
+    fn written_order(sort_by: SortBy, descending: bool) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut client_db = ClientDb::init();
+        for (client_id, available, held) in [
+            (1, dec!(5), dec!(20)),
+            (2, dec!(40), dec!(0)),
+            (3, dec!(1), dec!(10)),
+        ] {
+            client_db.insert_client_record(Client {
+                available,
+                held,
+                total: available + held,
+                ..Client::new(client_id)
+            });
+        }
+        let output = OutputConfig {
+            sort_by,
+            descending,
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_output(&mut written, &output)?;
+        Ok(String::from_utf8(written)?
+            .lines()
+            .skip(1)
+            .filter_map(|row| row.split(',').next())
+            .map(String::from)
+            .collect())
+    }
+
+    #[test]
+    fn clients_sorted_by_total_descending() -> Result<(), Box<dyn Error>> {
+        // Totals are 25, 40 and 11, so the largest total (client 2) is written first.
+        assert_eq!(written_order(SortBy::Total, true)?, ["2", "1", "3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn clients_sorted_by_held_ascending() -> Result<(), Box<dyn Error>> {
+        // Held funds are 20, 0 and 10, so the smallest (client 2) is written first. Sorting by client id
+        // remains the default.
+        assert_eq!(written_order(SortBy::Held, false)?, ["2", "3", "1"]);
+        assert_eq!(written_order(SortBy::Client, false)?, ["1", "2", "3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn clients_written_as_json() -> Result<(), Box<dyn Error>> {
+        // A client written as JSON has the csv column names as fields and its balances, rounded to 4.d.p,
+        // as numbers that read back to the expected values.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(10.12345),
+            held: dec!(2.5),
+            total: dec!(12.62345),
+            locked: true,
+            ..Client::new(7)
+        });
+        let output = OutputConfig {
+            format: OutputFormat::Json,
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_output(&mut written, &output)?;
+
+        let clients: serde_json::Value = serde_json::from_slice(&written)?;
+        assert_eq!(
+            clients,
+            serde_json::json!([{
+                "client": 7,
+                "available": 10.1235,
+                "held": 2.5,
+                "total": 12.6235,
+                "locked": true,
+            }])
+        );
+        assert_eq!(
+            String::from_utf8(written)?,
+            "[{\"client\":7,\"available\":10.1235,\"held\":2.5,\"total\":12.6235,\"locked\":true}]\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn changed_only_outputs_clients_modified_this_run() -> Result<(), Box<dyn Error>> {
+        // Load three clients from a snapshot, touch only client 2 and make sure only it is written.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let snapshot = "client,available,held,total,locked
+                        1,10.0,0.0,10.0,false
+                        2,20.0,0.0,20.0,false
+                        3,30.0,0.0,30.0,false";
+        client_db.load_snapshot(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(snapshot.as_bytes()),
+            &EngineConfig::default(),
+        )?;
+        Transaction::new(TransactionType::Deposit, 2, 1, Amount::new(dec!(5.0)).ok())
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )?;
+
+        let output = OutputConfig {
+            changed_only: true,
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n2,25.0,0.0,25.0,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn balances_rounded_to_per_field_precision() -> Result<(), Box<dyn Error>> {
+        // Make sure each balance column is rounded to its own configured number of decimal places.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1.23456),
+            held: dec!(2.34567),
+            total: dec!(3.58023),
+            ..Client::new(1)
+        });
+        let output = OutputConfig {
+            precision: FieldPrecision {
+                available: 1,
+                held: 2,
+                total: 4,
+            },
+            ..OutputConfig::default()
+        };
+
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n1,1.2,2.35,3.5802,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawal_respects_minimum_balance() {
+        // With a 20 minimum balance only 80 of a 100 deposit can be withdrawn.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let config = EngineConfig {
+            minimum_balance: dec!(20.0),
+            ..EngineConfig::default()
+        };
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        )
+        .handle_transaction(&mut transaction_db, &mut client_db, &config)
+        .unwrap();
+        Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Amount::new(dec!(70.0)).ok(),
+        )
+        .handle_transaction(&mut transaction_db, &mut client_db, &config)
+        .unwrap();
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(
+            client_db
+                .get_client_record(&1)
+                .unwrap()
+                .withdrawable(&config),
+            dec!(10.0)
+        );
+
+        let result = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            3,
+            Amount::new(dec!(20.0)).ok(),
+        )
+        .handle_transaction(&mut transaction_db, &mut client_db, &config);
+        assert_eq!(
+            result,
+            Err(TransactionError::InsufficientFunds {
+                client_id: 1,
+                amount: dec!(20.0),
+                available: dec!(10.0),
+            })
+        );
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(30.0));
+        assert_eq!(client_record.total, dec!(30.0));
+    }
+
+    #[test]
+    fn withdrawal_respects_reserve_percentage() {
+        // With a 25% reserve only 75 of a 100 deposit can be withdrawn, while available still reports
+        // the full balance.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let config = EngineConfig {
+            reserve_pct: dec!(25),
+            ..EngineConfig::default()
+        };
+        Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Amount::new(dec!(100.0)).ok(),
+        )
+        .handle_transaction(&mut transaction_db, &mut client_db, &config)
+        .unwrap();
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(100.0));
+        assert_eq!(client_record.withdrawable(&config), dec!(75.0));
+
+        let result = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Amount::new(dec!(75.0001)).ok(),
+        )
+        .handle_transaction(&mut transaction_db, &mut client_db, &config);
+        assert_eq!(
+            result,
+            Err(TransactionError::InsufficientFunds {
+                client_id: 1,
+                amount: dec!(75.0001),
+                available: dec!(75.0),
+            })
+        );
+        Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            3,
+            Amount::new(dec!(75.0)).ok(),
+        )
+        .handle_transaction(&mut transaction_db, &mut client_db, &config)
+        .unwrap();
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(25.0));
+        assert_eq!(client_record.total, dec!(25.0));
+    }
+
+    #[test]
+    fn aggregates_sum_exactly() {
+        // 65,535 clients each holding 0.1 available and one holding a balance large enough to swallow
+        // small f64 additions sum to the exact reference.
+        let mut client_db = ClientDb::init();
+        for client_id in 0..50_000 {
+            client_db.insert_client_record(Client {
+                available: dec!(0.1),
+                held: dec!(0.1),
+                ..Client::new(client_id)
+            });
+        }
+        client_db.insert_client_record(Client {
+            available: dec!(1_000_000_000_000_000),
+            ..Client::new(u16::MAX)
+        });
+        for client_id in 50_000..u16::MAX {
+            client_db.insert_client_record(Client {
+                available: dec!(0.1),
+                ..Client::new(client_id)
+            });
+        }
+
+        assert_eq!(
+            client_db.total_available(),
+            Some(dec!(1_000_000_000_006_553.5))
+        );
+        assert_eq!(client_db.total_held(), Some(dec!(5_000)));
+    }
+
+    #[test]
+    fn aggregates_report_overflow_instead_of_panicking() {
+        // Each balance near the maximum is representable on its own, but their sum is not, so the aggregate
+        // is None rather than a panic; the other aggregate still sums normally.
+        let mut client_db = ClientDb::init();
+        for client_id in 1..=2 {
+            client_db.insert_client_record(Client {
+                available: dec!(50_000_000_000_000_000_000_000_000_000),
+                held: dec!(1.5),
+                ..Client::new(client_id)
+            });
+        }
+
+        assert_eq!(client_db.total_available(), None);
+        assert_eq!(client_db.total_held(), Some(dec!(3)));
+    }
+
+    #[test]
+    fn repeated_small_deposits_do_not_drift() {
+        // Ten thousand deposits of 0.1 total exactly 1000.0000, where summing f64s drifts from it.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        for transaction_id in 0..10_000 {
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                transaction_id,
+                Amount::new(dec!(0.1)).ok(),
+            )
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        }
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.total, dec!(1000.0000));
+        assert_eq!(client_record.available, dec!(1000.0000));
+        assert_ne!((0..10_000).map(|_| 0.1_f64).sum::<f64>(), 1000.0);
+
+        let mut written = Vec::new();
+        client_db
+            .write_csv(
+                &mut written,
+                &OutputConfig {
+                    amount_format: Some(AmountFormat::Fixed),
+                    ..OutputConfig::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "client,available,held,total,locked\n1,1000.0000,0.0000,1000.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn summary_counts_client_states() {
+        // Make sure each client is counted under every state that applies to it.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client::new(1));
+        client_db.insert_client_record(Client {
+            available: dec!(10.0),
+            total: dec!(10.0),
+            ..Client::new(2)
+        });
+        client_db.insert_client_record(Client {
+            held: dec!(5.0),
+            total: dec!(5.0),
+            ..Client::new(3)
+        });
+        client_db.insert_client_record(Client {
+            locked: true,
+            ..Client::new(4)
+        });
+
+        let summary = client_db.summary();
+        assert_eq!(
+            summary,
+            ClientSummary {
+                clients: 4,
+                locked: 1,
+                held: 1,
+                zero_balance: 2,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "4 clients, 1 locked, 1 with held funds, 2 zero-balance"
+        );
+    }
+
+    #[test]
+    fn second_chargeback_applies_to_locked_account_when_disputes_allowed() {
+        // Charge back two disputed deposits for the same client and make sure the second one still
+        // adjusts balances once the first has locked the account, while deposits stay rejected.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let config = EngineConfig {
+            locked_allows: LockedAllows::Disputes,
+            ..EngineConfig::default()
+        };
+        let test_transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.0)).ok()),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(20.0)).ok()),
+            Transaction::new(TransactionType::Deposit, 1, 3, Amount::new(dec!(40.0)).ok()),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 2, None),
+        ];
+        for transaction in test_transactions {
+            transaction
+                .handle_transaction(&mut transaction_db, &mut client_db, &config)
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        let result = Transaction::new(TransactionType::Deposit, 1, 4, Amount::new(dec!(5.0)).ok())
+            .handle_transaction(&mut transaction_db, &mut client_db, &config);
+        assert_eq!(
+            result,
+            Err(TransactionError::AccountLocked {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+            })
+        );
+
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(40.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(40.0));
+        assert!(client_record.locked);
+    }
+
+    #[test]
+    fn client_ids_zero_padded_to_width() -> Result<(), Box<dyn Error>> {
+        // Make sure client 42 is written as 00042 under a width of 5, and that writing fails before any output
+        // when an id is wider than the configured width.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1.5),
+            total: dec!(1.5),
+            ..Client::new(42)
+        });
+        let output = OutputConfig {
+            client_id_width: Some(5),
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n00042,1.5,0.0,1.5,false\n"
+        );
+
+        let output = OutputConfig {
+            client_id_width: Some(1),
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        let err = client_db
+            .write_csv(&mut written, &output)
+            .expect_err("a two digit id should not fit a width of 1");
+        assert_eq!(
+            err.to_string(),
+            "client id 42 is wider than the client id width of 1"
+        );
+        assert!(written.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn currency_column_written_when_configured() -> Result<(), Box<dyn Error>> {
+        // Make sure the configured currency code is appended to every record as a trailing column.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1.5),
+            total: dec!(1.5),
+            ..Client::new(1)
+        });
+        let output = OutputConfig {
+            currency: Some("USD".to_string()),
+            ..OutputConfig::default()
+        };
+
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked,currency\n1,1.5,0.0,1.5,false,USD\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn canonical_amount_formats() -> Result<(), Box<dyn Error>> {
+        // Make sure the fixed format always writes every decimal place and the trimmed format never
+        // writes trailing zeros.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(100.5),
+            total: dec!(100.5),
+            ..Client::new(1)
+        });
+        let fixed = OutputConfig {
+            amount_format: Some(AmountFormat::Fixed),
+            ..OutputConfig::default()
+        };
+        let trimmed = OutputConfig {
+            amount_format: Some(AmountFormat::Trimmed),
+            ..OutputConfig::default()
+        };
+
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &fixed)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n1,100.5000,0.0000,100.5000,false\n"
+        );
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &trimmed)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n1,100.5,0,100.5,false\n"
+        );
+        Ok(())
+    }
+
+    // Writer accepting at most 8 bytes per write and failing with a transient error on its second write.
+    struct FlakyWriter {
+        written: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            if self.writes == 2 {
+                return Err(io::Error::new(ErrorKind::TimedOut, "sink busy"));
+            }
+            let accepted = buf.len().min(8);
+            self.written.extend_from_slice(&buf[..accepted]);
+            Ok(accepted)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transient_write_error_is_retried() -> Result<(), Box<dyn Error>> {
+        // Make sure a sink that fails once and only accepts partial writes still receives the complete output.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1.5),
+            total: dec!(1.5),
+            ..Client::new(1)
+        });
+        let mut sink = FlakyWriter {
+            written: Vec::new(),
+            writes: 0,
+        };
+
+        client_db.write_csv(&mut sink, &OutputConfig::default())?;
+        assert!(sink.writes > 2);
+        assert_eq!(
+            String::from_utf8(sink.written)?,
+            "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cumulative_totals_unaffected_by_disputes() -> Result<(), Box<dyn Error>> {
+        // Make sure applied deposits and withdrawals accumulate, rejected ones and disputes do not,
+        // and the totals are written when requested.
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let test_transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.0)).ok()),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(5.0)).ok()),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Amount::new(dec!(3.0)).ok(),
+            ),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                4,
+                Amount::new(dec!(50.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+        ];
+        for transaction in test_transactions {
+            let _ = transaction.handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            );
+            transaction_db.insert_transaction(transaction);
+        }
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.total_deposited.value(), dec!(15.0));
+        assert_eq!(client_record.total_withdrawn.value(), dec!(3.0));
+        assert_eq!(client_record.total, dec!(2.0));
+
+        let output = OutputConfig {
+            cumulative_totals: true,
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked,total_deposited,total_withdrawn\n\
+             1,2.0,0.0,2.0,true,15.0,3.0\n"
+        );
+        Ok(())
+    }
+
+    // Apply a deposit of 10, dispute it and resolve it under the given resolve policy.
+    fn resolve_with_policy(resolve_to: ResolveTo) -> (ClientDb, TransactionDb, EngineConfig) {
+        let (mut client_db, mut transaction_db) = create_client_transaction_dbs();
+        let config = EngineConfig {
+            resolve_to,
+            ..EngineConfig::default()
+        };
+        let test_transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.0)).ok()),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+        ];
+        for transaction in test_transactions {
+            transaction
+                .handle_transaction(&mut transaction_db, &mut client_db, &config)
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        (client_db, transaction_db, config)
+    }
+
+    #[test]
+    fn resolve_to_available_credits_available_funds() {
+        // The default policy makes resolved funds available immediately, so there is nothing to release.
+        let (mut client_db, mut transaction_db, config) = resolve_with_policy(ResolveTo::Available);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(10.0));
+        assert_eq!(client_record.pending, dec!(0.0));
+
+        let result = Transaction::new(TransactionType::Release, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+        );
+        assert_eq!(
+            result,
+            Err(TransactionError::NothingPending {
+                client_id: 1,
+                transaction_id: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn resolve_to_pending_waits_for_release() -> Result<(), Box<dyn Error>> {
+        // Resolved funds are pending (but still part of the total) until a release makes them available.
+        let (mut client_db, mut transaction_db, config) = resolve_with_policy(ResolveTo::Pending);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.pending, dec!(10.0));
+        assert_eq!(client_record.total, dec!(10.0));
+
+        let output = OutputConfig {
+            pending_column: true,
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,pending,locked\n1,0.0,0.0,10.0,10.0,false\n"
+        );
+
+        Transaction::new(TransactionType::Release, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+        )?;
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(10.0));
+        assert_eq!(client_record.pending, dec!(0.0));
+        assert_eq!(client_record.total, dec!(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_formatted_for_locale() -> Result<(), Box<dyn Error>> {
+        // Make sure thousands separators and the decimal point follow the locale, with fields containing
+        // the csv delimiter quoted.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1234.56),
+            held: dec!(-1234567.5),
+            total: dec!(0.25),
+            ..Client::new(1)
+        });
+        let english = OutputConfig {
+            locale: Some(Locale::En),
+            ..OutputConfig::default()
+        };
+        let german = OutputConfig {
+            locale: Some(Locale::De),
+            ..OutputConfig::default()
+        };
+
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &english)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n1,\"1,234.56\",\"-1,234,567.5\",0.25,false\n"
+        );
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &german)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n1,\"1.234,56\",\"-1.234.567,5\",\"0,25\",false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn clients_written_to_writer() -> Result<(), Box<dyn Error>> {
+        // Make sure the header and client rows are written to the supplied writer.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(7.25),
+            total: dec!(7.25),
+            ..Client::new(2)
+        });
+        client_db.insert_client_record(Client {
+            held: dec!(3),
+            total: dec!(3),
+            locked: true,
+            ..Client::new(1)
+        });
+        let mut out = Vec::new();
+        client_db.to_csv_writer(&mut out, &OutputConfig::default())?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client,available,held,total,locked\n\
+             1,0.0,3.0,3.0,true\n\
+             2,7.25,0.0,7.25,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn clients_written_in_id_order() -> Result<(), Box<dyn Error>> {
+        // Clients are written in ascending id order regardless of the order they were inserted in.
+        let mut client_db = ClientDb::init();
+        for client_id in [3, 1, 2] {
+            client_db.insert_client_record(Client::new(client_id));
+        }
+        let mut out = Vec::new();
+        client_db.write_csv(&mut out, &OutputConfig::default())?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "client,available,held,total,locked\n\
+             1,0.0,0.0,0.0,false\n\
+             2,0.0,0.0,0.0,false\n\
+             3,0.0,0.0,0.0,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn clients_written_to_shard_of_their_id() -> Result<(), Box<dyn Error>> {
+        // Each client lands in shard `client_id % 4` in id order, and the empty shard still gets a header.
+        let mut client_db = ClientDb::init();
+        for client_id in [7, 1, 4, 3, 8, 5] {
+            client_db.insert_client_record(Client::new(client_id));
+        }
+        let mut shards = vec![Vec::new(); 4];
+        client_db.write_csv_shards(shards.iter_mut().collect(), &OutputConfig::default())?;
+        let shards: Vec<String> = shards
+            .into_iter()
+            .map(String::from_utf8)
+            .collect::<Result<_, _>>()?;
+        let shard_csv = |client_ids: &[u16]| {
+            client_ids.iter().fold(
+                "client,available,held,total,locked\n".to_string(),
+                |csv, client_id| format!("{}{},0.0,0.0,0.0,false\n", csv, client_id),
+            )
+        };
+        assert_eq!(
+            shards,
+            [
+                shard_csv(&[4, 8]),
+                shard_csv(&[1, 5]),
+                shard_csv(&[]),
+                shard_csv(&[3, 7]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn schema_comment_precedes_header() -> Result<(), Box<dyn Error>> {
+        // Make sure the schema comment is the first line, followed by the header it describes.
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(Client {
+            available: dec!(1.5),
+            total: dec!(1.5),
+            ..Client::new(1)
+        });
+        let output = OutputConfig {
+            schema_comment: true,
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "# columns: client,available,held,total,locked; precision=4\nclient,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+        );
+
+        // Mixed precisions are listed per balance column.
+        let output = OutputConfig {
+            schema_comment: true,
+            precision: FieldPrecision {
+                available: 2,
+                held: 4,
+                total: 4,
+            },
+            currency: Some("USD".to_string()),
+            ..OutputConfig::default()
+        };
+        assert_eq!(
+            output.schema_comment_line(),
+            "# columns: client,available,held,total,locked,currency; precision=available:2,held:4,total:4"
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,17 @@
+// ------------------------------------------------------------------------------------------------
+// ------------------------------- DATABASE COLLECTION TYPES --------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Map and set types backing the Client and Transaction Databases. By default these are hash based for
+// speed. With the `deterministic` feature they are ordered by id instead, so iteration (and with it the
+// order clients are written and stored in) is reproducible between runs, at some cost in speed.
+
+#[cfg(not(feature = "deterministic"))]
+pub type Map<K, V> = std::collections::HashMap<K, V>;
+#[cfg(not(feature = "deterministic"))]
+pub type Set<T> = std::collections::HashSet<T>;
+
+#[cfg(feature = "deterministic")]
+pub type Map<K, V> = std::collections::BTreeMap<K, V>;
+#[cfg(feature = "deterministic")]
+pub type Set<T> = std::collections::BTreeSet<T>;
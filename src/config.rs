@@ -0,0 +1,328 @@
+// ------------------------------------------------------------------------------------------------
+// -------------------------------- ENGINE CONFIG STRUCT ------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+use crate::amount::AMOUNT_DECIMAL_PLACES;
+use crate::error::TransactionError;
+use crate::transaction::TransactionType;
+use clap::ValueEnum;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::time::Duration;
+
+// Options controlling how transactions are applied. Built from the CLI arguments by the binary,
+// with `Default` giving the engine's standard behaviour.
+#[derive(Default)]
+pub struct EngineConfig {
+    // Abort processing unless every row carries a sequence number following on from the previous row.
+    pub require_contiguous_seq: bool,
+    // What a dispute does when holding the disputed amount would take available funds below zero.
+    pub negative_available: NegativeAvailablePolicy,
+    // Daily interest rate credited on held funds, accrued using the transaction timestamps.
+    pub held_interest_rate: Option<Decimal>,
+    // Stop processing once this much time has passed, keeping whatever has been applied so far.
+    pub max_runtime: Option<Duration>,
+    // Floor of available funds that withdrawals cannot take the client below.
+    pub minimum_balance: Decimal,
+    // Largest amount a deposit or withdrawal may be for, guarding against fraud and fat-finger errors.
+    pub max_amount: Option<Decimal>,
+    // Percentage of available funds held back as a reserve that cannot be withdrawn.
+    pub reserve_pct: Decimal,
+    // How amounts are rounded to 4.d.p as they are read, and how interest is rounded when credited.
+    pub rounding: RoundingMode,
+    // Drop trailing columns beyond the header instead of rejecting the row.
+    pub ignore_extra_fields: bool,
+    // Abort processing on the first row that cannot be parsed, instead of skipping it.
+    pub strict: bool,
+    // Transactions still applied to a client once its account has been locked by a chargeback.
+    pub locked_allows: LockedAllows,
+    // Where a resolve returns the disputed funds to.
+    pub resolve_to: ResolveTo,
+    // Which chargebacks lock the client's account.
+    pub chargeback_locks: ChargebackLocks,
+    // What happens to a resolve of a transaction that has already been charged back.
+    pub resolve_after_chargeback: ResolveAfterChargeback,
+    // Withdrawals may only be disputed within this long of their timestamp, modelling a chargeback window.
+    pub withdrawal_dispute_window: Option<Duration>,
+    // Warn about deposits/withdrawals on accounts that were locked and have since been unlocked.
+    pub warn_reactivated: bool,
+    // Hold transactions timestamped after the processing clock until a clock row reaches their timestamp.
+    pub defer_future_dated: bool,
+    // Starting processing clock, in seconds since the unix epoch. Defaults to the wall clock at the start of the run.
+    pub processing_clock: Option<u64>,
+    // Decimal places amounts are read with, at most MAX_PRECISION. Unset reads amounts to 4.d.p.
+    pub precision: Option<u32>,
+    // Reject transactions whose non-zero amount rounds to zero at their precision, instead of applying them as zero.
+    pub reject_subprecision: bool,
+    // Reject transactions whose amount is written with more significant digits than this, guarding against
+    // absurd values such as 12345678901234.5678.
+    pub max_significant_digits: Option<u32>,
+    // Alert on clients making more than a number of timestamped transactions within a time window.
+    pub burst_limit: Option<BurstLimit>,
+    // Transaction types applied during the run; rows of any other type are skipped. Empty applies every type.
+    pub only_types: Vec<TransactionType>,
+    // Record every applied transaction, with the client's resulting balances, in the processing stats.
+    pub record_ledger: bool,
+    // Client id of a system suspense account credited at the end of the run with the rounding residues of
+    // every applied deposit/withdrawal, so the grand total reconciles with the unrounded amounts.
+    pub suspense_account: Option<u16>,
+    // Parse rows by hand from a single record buffer reused across the rows of each input, rather than
+    // allocating a record per row and deserialising it. Transactions read are identical either way.
+    pub fast_parse: bool,
+}
+
+impl EngineConfig {
+    // Decimal places amounts are read with.
+    pub fn amount_precision(&self) -> u32 {
+        self.precision.unwrap_or(AMOUNT_DECIMAL_PLACES)
+    }
+
+    // True if the rejection is configured to be ignored, leaving the transaction a silent no-op rather than
+    // a reported rejection.
+    pub fn ignores(&self, err: &TransactionError) -> bool {
+        matches!(err, TransactionError::ResolveAfterChargeback { .. })
+            && self.resolve_after_chargeback == ResolveAfterChargeback::Ignore
+    }
+
+    // True if rows of the transaction type are applied during this run.
+    pub fn applies_type(&self, transaction_type: TransactionType) -> bool {
+        self.only_types.is_empty() || self.only_types.contains(&transaction_type)
+    }
+}
+
+// How an amount or balance is rounded to a number of decimal places, to match an institution's accounting rules.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum RoundingMode {
+    // Round to the nearest, with halves rounded away from zero, e.g. 1.23455 to 1.2346.
+    #[default]
+    HalfUp,
+    // Round to the nearest, with halves rounded to the even neighbour (banker's rounding), e.g. 1.23465 to 1.2346.
+    HalfEven,
+    // Drop the digits beyond the precision, rounding towards zero, e.g. 1.23459 to 1.2345.
+    Truncate,
+    // Rounding supplied by a library embedder, e.g. for currency-specific or regulatory rules.
+    #[clap(skip)]
+    Custom(RoundingFn),
+}
+
+// Custom rounding function, called with a value and the number of decimal places to round it to.
+pub type RoundingFn = fn(Decimal, u32) -> Decimal;
+
+impl RoundingMode {
+    // Round a value to the given number of decimal places.
+    pub fn round(self, value: Decimal, decimal_places: u32) -> Decimal {
+        let strategy = match self {
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::Truncate => RoundingStrategy::ToZero,
+            RoundingMode::Custom(round) => return round(value, decimal_places),
+        };
+        value.round_dp_with_strategy(decimal_places, strategy)
+    }
+}
+
+// Most transactions a client may make within a sliding time window before an alert is raised.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurstLimit {
+    pub threshold: usize,
+    pub window: Duration,
+}
+
+// Policy for a dispute whose amount exceeds the client's available funds, e.g. a deposit that was
+// mostly withdrawn before being disputed.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum NegativeAvailablePolicy {
+    // Hold the full amount, letting available go negative.
+    #[default]
+    Allow,
+    // Ignore the dispute.
+    Reject,
+    // Only hold what is available, so available never goes below zero.
+    // Only the amount held is later released or charged back, and a dispute that would hold nothing is rejected.
+    Clamp,
+}
+
+// Transactions that may still be applied to a locked account. Deposits and withdrawals are always rejected.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LockedAllows {
+    // Reject every transaction.
+    #[default]
+    #[clap(name = "none")]
+    Nothing,
+    // Apply disputes, resolves and further chargebacks so late claims still adjust the balances.
+    Disputes,
+}
+
+// Balance that a resolve moves the disputed funds into.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResolveTo {
+    // Funds become available immediately.
+    #[default]
+    Available,
+    // Funds are pending until a `release` row for the tx moves them to available.
+    Pending,
+}
+
+// Handling of a resolve arriving after the transaction was charged back. The funds have already left the
+// account (or, for a withdrawal, been returned), so there is nothing to release either way.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResolveAfterChargeback {
+    // Reject the resolve, reporting it like any other rejection.
+    #[default]
+    Reject,
+    // Skip the resolve as a no-op, without reporting it.
+    Ignore,
+}
+
+// Chargebacks that lock the client's account. A dispute may cover only part of a transaction, so a
+// chargeback may only reverse part of it.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChargebackLocks {
+    // Every chargeback locks the account.
+    #[default]
+    Any,
+    // Only a chargeback that completes reversing the whole transaction locks the account.
+    Full,
+}
+
+// ------------------------------------------------------------------------------------------------
+// -------------------------------- OUTPUT CONFIG STRUCT ------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Options controlling how the client database is written out.
+#[derive(Default)]
+pub struct OutputConfig {
+    // Only write clients that had a transaction applied during this run.
+    pub changed_only: bool,
+    // Decimal places each balance column is rounded to.
+    pub precision: FieldPrecision,
+    // How each balance is rounded to its precision.
+    pub rounding: RoundingMode,
+    // Also write each client's funds pending release.
+    pub pending_column: bool,
+    // Also write each client's cumulative deposited and withdrawn totals.
+    pub cumulative_totals: bool,
+    // Currency code written in a trailing `currency` column when set.
+    pub currency: Option<String>,
+    // Canonical text format for balances. When unset balances are written as the shortest f64 representation.
+    pub amount_format: Option<AmountFormat>,
+    // Thousands separator and decimal point convention for balances, for human-facing reports.
+    pub locale: Option<Locale>,
+    // Write a `#` comment line describing the columns and precision before the header.
+    pub schema_comment: bool,
+    // Whether clients are written as csv or as a JSON array.
+    pub format: OutputFormat,
+    // Column the clients are ordered by, ascending unless descending is set.
+    pub sort_by: SortBy,
+    pub descending: bool,
+    // Zero-pad the client column to this many digits, e.g. 00042 for a width of 5.
+    pub client_id_width: Option<u32>,
+}
+
+impl OutputConfig {
+    // Names of the columns written to the output, in order.
+    pub fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec!["client", "available", "held", "total"];
+        if self.pending_column {
+            columns.push("pending");
+        }
+        columns.push("locked");
+        if self.cumulative_totals {
+            columns.extend(["total_deposited", "total_withdrawn"]);
+        }
+        if self.currency.is_some() {
+            columns.push("currency");
+        }
+        columns
+    }
+
+    // Comment line describing the output, e.g. `# columns: client,available,held,total,locked; precision=4`.
+    // Precision is given per balance column when the columns are written with different precisions.
+    pub fn schema_comment_line(&self) -> String {
+        let FieldPrecision {
+            available,
+            held,
+            total,
+        } = self.precision;
+        let precision = if available == held && held == total {
+            available.to_string()
+        } else {
+            format!("available:{},held:{},total:{}", available, held, total)
+        };
+        format!(
+            "# columns: {}; precision={}",
+            self.columns().join(","),
+            precision
+        )
+    }
+}
+
+// File formats the client database can be written in.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // An array of client objects with the csv column names as fields and balances as numbers.
+    Json,
+}
+
+// Columns the clients can be ordered by in the output. Clients with equal balances are ordered by client id.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+// Canonical formats a balance can be written in, so output is consistent and diffable between runs.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AmountFormat {
+    // Always write the configured number of decimal places, e.g. 100.5000.
+    Fixed,
+    // Strip all trailing zeros (and a trailing decimal point), e.g. 100.5 or 100.
+    Trimmed,
+}
+
+// Largest number of decimal places a balance column can be written with.
+pub const MAX_PRECISION: u32 = 8;
+
+// Decimal places for each balance column of the output.
+pub struct FieldPrecision {
+    pub available: u32,
+    pub held: u32,
+    pub total: u32,
+}
+
+// Balances are written to 4.d.p by default, matching the precision amounts are read with.
+impl Default for FieldPrecision {
+    fn default() -> Self {
+        FieldPrecision {
+            available: 4,
+            held: 4,
+            total: 4,
+        }
+    }
+}
+
+// Number formatting conventions for balances written to the output.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Locale {
+    // 1,234.56
+    En,
+    // 1.234,56
+    De,
+    // 1 234,56
+    Fr,
+}
+
+impl Locale {
+    // Thousands separator and decimal point of the locale.
+    pub fn separators(self) -> (char, char) {
+        match self {
+            Locale::En => (',', '.'),
+            Locale::De => ('.', ','),
+            Locale::Fr => (' ', ','),
+        }
+    }
+}
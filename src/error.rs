@@ -0,0 +1,347 @@
+use crate::transaction::TransactionType;
+use rust_decimal::Decimal;
+use std::fmt;
+use std::time::Duration;
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------- TRANSACTION ERROR ENUM -----------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Reasons a transaction can be rejected without being applied to a client record.
+// Each variant carries the amounts and balances involved so the rejection can be explained.
+// Amounts are explained without trailing zeros, e.g. 12 rather than 12.0000.
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    AccountLocked {
+        transaction_type: TransactionType,
+        client_id: u16,
+    },
+    MissingAmount {
+        transaction_type: TransactionType,
+        client_id: u16,
+    },
+    InsufficientFunds {
+        client_id: u16,
+        amount: Decimal,
+        available: Decimal,
+    },
+    InsufficientFundsForDispute {
+        client_id: u16,
+        transaction_id: u32,
+        amount: Decimal,
+        available: Decimal,
+    },
+    UnknownTransaction {
+        transaction_type: TransactionType,
+        transaction_id: u32,
+    },
+    NoDisputableAmount {
+        transaction_type: TransactionType,
+        transaction_id: u32,
+    },
+    DuplicateEvent {
+        transaction_type: TransactionType,
+        transaction_id: u32,
+    },
+    NothingPending {
+        client_id: u16,
+        transaction_id: u32,
+    },
+    AccountNotLocked {
+        client_id: u16,
+    },
+    SubprecisionAmount {
+        transaction_type: TransactionType,
+        client_id: u16,
+        decimal_places: u32,
+    },
+    AmountTooLarge {
+        transaction_type: TransactionType,
+        client_id: u16,
+        amount: Decimal,
+        max_amount: Decimal,
+    },
+    NotDisputed {
+        transaction_type: TransactionType,
+        transaction_id: u32,
+    },
+    AlreadyDisputed {
+        transaction_id: u32,
+    },
+    AmountOverflow {
+        transaction_type: TransactionType,
+        client_id: u16,
+    },
+    DisputeExceedsDisputable {
+        transaction_id: u32,
+        amount: Decimal,
+        disputable: Decimal,
+    },
+    FullyChargedBack {
+        transaction_id: u32,
+    },
+    ResolveAfterChargeback {
+        transaction_id: u32,
+    },
+    DisputeWindowClosed {
+        transaction_id: u32,
+        elapsed: u64,
+        window: Duration,
+    },
+    InvalidAmount {
+        transaction_type: TransactionType,
+        client_id: u16,
+        reason: String,
+    },
+    ClientMismatch {
+        transaction_type: TransactionType,
+        transaction_id: u32,
+        client_id: u16,
+        owner_id: u16,
+    },
+    DuplicateTransactionId {
+        transaction_type: TransactionType,
+        transaction_id: u32,
+    },
+    MissingCorrectionReason {
+        transaction_id: u32,
+    },
+    CorrectionOfDisputed {
+        transaction_id: u32,
+    },
+    InsufficientFundsForCorrection {
+        client_id: u16,
+        transaction_id: u32,
+        amount: Decimal,
+        available: Decimal,
+    },
+}
+
+// Human readable explanation of why the transaction was rejected.
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::AccountLocked {
+                transaction_type,
+                client_id,
+            } => write!(
+                f,
+                "{} rejected, client {} account is locked",
+                transaction_type.as_str(),
+                client_id
+            ),
+            TransactionError::MissingAmount {
+                transaction_type,
+                client_id,
+            } => write!(
+                f,
+                "{} for client {} rejected, no valid amount supplied",
+                transaction_type.as_str(),
+                client_id
+            ),
+            TransactionError::InsufficientFunds {
+                client_id,
+                amount,
+                available,
+            } => write!(
+                f,
+                "withdrawal of {} rejected, client {} has only {} available",
+                amount.normalize(),
+                client_id,
+                available.normalize()
+            ),
+            TransactionError::InsufficientFundsForDispute {
+                client_id,
+                transaction_id,
+                amount,
+                available,
+            } => write!(
+                f,
+                "dispute of tx {} for {} rejected, client {} has only {} available",
+                transaction_id,
+                amount.normalize(),
+                client_id,
+                available.normalize()
+            ),
+            TransactionError::UnknownTransaction {
+                transaction_type,
+                transaction_id,
+            } => write!(
+                f,
+                "{} rejected, tx {} does not exist",
+                transaction_type.as_str(),
+                transaction_id
+            ),
+            TransactionError::NoDisputableAmount {
+                transaction_type,
+                transaction_id,
+            } => write!(
+                f,
+                "{} rejected, tx {} has no amount",
+                transaction_type.as_str(),
+                transaction_id
+            ),
+            TransactionError::DuplicateEvent {
+                transaction_type,
+                transaction_id,
+            } => write!(
+                f,
+                "duplicate {} of tx {} ignored",
+                transaction_type.as_str(),
+                transaction_id
+            ),
+            TransactionError::NothingPending {
+                client_id,
+                transaction_id,
+            } => write!(
+                f,
+                "release rejected, tx {} of client {} has no resolved funds pending release",
+                transaction_id, client_id
+            ),
+            TransactionError::AccountNotLocked { client_id } => {
+                write!(
+                    f,
+                    "unlock rejected, client {} account is not locked",
+                    client_id
+                )
+            }
+            TransactionError::SubprecisionAmount {
+                transaction_type,
+                client_id,
+                decimal_places,
+            } => write!(
+                f,
+                "{} for client {} rejected, amount is below {} decimal place precision",
+                transaction_type.as_str(),
+                client_id,
+                decimal_places
+            ),
+            TransactionError::AmountTooLarge {
+                transaction_type,
+                client_id,
+                amount,
+                max_amount,
+            } => write!(
+                f,
+                "{} of {} for client {} rejected, amount is over the maximum of {}",
+                transaction_type.as_str(),
+                amount.normalize(),
+                client_id,
+                max_amount.normalize()
+            ),
+            TransactionError::NotDisputed {
+                transaction_type,
+                transaction_id,
+            } => write!(
+                f,
+                "{} rejected, tx {} is not under dispute",
+                transaction_type.as_str(),
+                transaction_id
+            ),
+            TransactionError::AlreadyDisputed { transaction_id } => write!(
+                f,
+                "dispute rejected, tx {} is already under dispute",
+                transaction_id
+            ),
+            TransactionError::AmountOverflow {
+                transaction_type,
+                client_id,
+            } => write!(
+                f,
+                "{} for client {} rejected, amount overflows the account's balances or cumulative totals",
+                transaction_type.as_str(),
+                client_id
+            ),
+            TransactionError::DisputeExceedsDisputable {
+                transaction_id,
+                amount,
+                disputable,
+            } => write!(
+                f,
+                "dispute of {} rejected, only {} of tx {} can still be disputed",
+                amount.normalize(),
+                disputable.normalize(),
+                transaction_id
+            ),
+            TransactionError::FullyChargedBack { transaction_id } => write!(
+                f,
+                "dispute rejected, tx {} has already been fully charged back",
+                transaction_id
+            ),
+            TransactionError::ResolveAfterChargeback { transaction_id } => write!(
+                f,
+                "resolve rejected, tx {} has already been charged back so there is nothing to release",
+                transaction_id
+            ),
+            TransactionError::DisputeWindowClosed {
+                transaction_id,
+                elapsed,
+                window,
+            } => write!(
+                f,
+                "dispute rejected, withdrawal tx {} was made {}s earlier, outside the dispute window of {}s",
+                transaction_id,
+                elapsed,
+                window.as_secs_f64()
+            ),
+            TransactionError::InvalidAmount {
+                transaction_type,
+                client_id,
+                reason,
+            } => write!(
+                f,
+                "{} for client {} rejected, {}",
+                transaction_type.as_str(),
+                client_id,
+                reason
+            ),
+            TransactionError::ClientMismatch {
+                transaction_type,
+                transaction_id,
+                client_id,
+                owner_id,
+            } => write!(
+                f,
+                "{} of tx {} by client {} rejected, tx belongs to client {}",
+                transaction_type.as_str(),
+                transaction_id,
+                client_id,
+                owner_id
+            ),
+            TransactionError::DuplicateTransactionId {
+                transaction_type,
+                transaction_id,
+            } => write!(
+                f,
+                "{} rejected, tx {} has already been used by an earlier transaction",
+                transaction_type.as_str(),
+                transaction_id
+            ),
+            TransactionError::MissingCorrectionReason { transaction_id } => write!(
+                f,
+                "correction of tx {} rejected, no reason supplied",
+                transaction_id
+            ),
+            TransactionError::CorrectionOfDisputed { transaction_id } => write!(
+                f,
+                "correction rejected, tx {} is under dispute or has been charged back",
+                transaction_id
+            ),
+            TransactionError::InsufficientFundsForCorrection {
+                client_id,
+                transaction_id,
+                amount,
+                available,
+            } => write!(
+                f,
+                "correction of tx {} taking {} rejected, client {} has only {} available",
+                transaction_id,
+                amount.normalize(),
+                client_id,
+                available.normalize()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
@@ -0,0 +1,58 @@
+//! Toy transaction payment engine, applying deposits, withdrawals, disputes, resolves and chargebacks
+//! to client accounts.
+//!
+//! The `transaction_engine` binary is a thin command line wrapper over this library, which can also be
+//! embedded directly, e.g. in a service:
+//!
+//! ```
+//! use rust_decimal_macros::dec;
+//! use transaction_engine::{
+//!     Amount, ClientDb, EngineConfig, Transaction, TransactionDb, TransactionType,
+//! };
+//!
+//! let mut client_db = ClientDb::init();
+//! let mut transaction_db = TransactionDb::init();
+//! let deposit = Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.5)).ok());
+//! deposit.handle_transaction(&mut transaction_db, &mut client_db, &EngineConfig::default())?;
+//! // Record the deposit so that it can be disputed later.
+//! transaction_db.insert_transaction(deposit);
+//!
+//! let client = client_db.get_client_record(&1).expect("deposit creates the client");
+//! assert_eq!(client.available(), dec!(10.5));
+//! assert_eq!(client.total(), dec!(10.5));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Whole csv inputs are applied with [`apply_transactions`], and transactions already in memory with
+//! [`apply_transactions_iter`]. Both return a [`ProcessingStats`] reporting the run, including every
+//! [`Rejection`] and a [`ProcessingSummary`] of the resulting balances.
+
+pub mod alert;
+pub mod amount;
+pub mod canonical;
+pub mod cli_args;
+pub mod client;
+mod collections;
+pub mod config;
+pub mod error;
+pub mod metadata;
+pub mod monitor;
+#[cfg(feature = "http")]
+pub mod remote;
+pub mod scenario;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod storage;
+pub mod timeout;
+pub mod transaction;
+
+pub use alert::{Alert, AlertKind, AlertSink};
+pub use amount::Amount;
+pub use client::{Client, ClientDb};
+pub use config::{EngineConfig, OutputConfig};
+pub use error::TransactionError;
+pub use storage::Storage;
+pub use transaction::{
+    apply_transactions, apply_transactions_iter, ProcessingStats, ProcessingSummary, Rejection,
+    Transaction, TransactionDb, TransactionType,
+};
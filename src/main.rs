@@ -1,19 +1,67 @@
-mod cli_args;
-mod client;
-mod transaction;
-
 use clap::Parser;
-use cli_args::CliArgs;
-use client::ClientDb;
-use transaction::TransactionDb;
+use std::fs::File;
+use std::io::BufWriter;
+use transaction_engine::alert::StderrAlertSink;
+use transaction_engine::cli_args::{self, CliArgs};
+use transaction_engine::metadata::RunMetadata;
+use transaction_engine::scenario::{self, ScenarioConfig};
+#[cfg(feature = "sqlite")]
+use transaction_engine::sqlite;
+use transaction_engine::{canonical, transaction, ClientDb, TransactionDb};
+
+// Exit codes distinguishing why a run failed, so scripts can tell a bad input file from a broken pipe.
+// Reading or applying the transactions failed.
+const EXIT_INPUT_ERROR: i32 = 2;
+// Writing an output failed.
+const EXIT_OUTPUT_ERROR: i32 = 3;
+// An input path supplied on the command line could not be opened.
+const EXIT_INVALID_PATH: i32 = 4;
 
 fn main() {
     // Read args supplied to binary. CLAP throws error if no argument is supplied.
     // Explains that the transaction file argument is required.
     let args: CliArgs = cli_args::CliArgs::parse();
 
-    // Create csv reader from supplied path to binary. Panics if invalid file.
-    let tx_reader = args.create_tx_reader();
+    // Run a tool instead of applying transactions if one was requested.
+    if let Some(cli_args::Command::Canonicalize { input, output }) = &args.command {
+        let rdr = match cli_args::create_tx_reader(input, None) {
+            Ok(rdr) => rdr,
+            Err(err) => {
+                println!("Error opening transaction input: {}", err);
+                std::process::exit(EXIT_INVALID_PATH)
+            }
+        };
+        let written = match output {
+            Some(path) => File::create(path)
+                .map_err(Into::into)
+                .and_then(|file| canonical::canonicalize(rdr, BufWriter::new(file))),
+            None => canonical::canonicalize(rdr, std::io::stdout().lock()),
+        };
+        if let Err(err) = written {
+            println!("Error canonicalizing transaction file: {}", err);
+            std::process::exit(EXIT_INPUT_ERROR)
+        }
+        return;
+    }
+
+    // With --scenario, replay the same transactions under each named set of options and write the labeled
+    // results, instead of a single run.
+    if !args.scenarios.is_empty() {
+        replay_scenarios(&args);
+        return;
+    }
+
+    // Open the inputs from supplied paths to binary, or exit if a path cannot be opened.
+    let tx_inputs = match args.open_tx_inputs() {
+        Ok(tx_inputs) => tx_inputs,
+        Err(err) => {
+            println!("Error opening transaction input: {}", err);
+            std::process::exit(EXIT_INVALID_PATH)
+        }
+    };
+
+    // Options controlling how transactions are applied.
+    let config = args.engine_config();
 
     // Create Transaction Database for storing desposit and withdrawals in case of dispute|resolve|chargeback.
     // In a real-life scenario it is assumed that the associated function init would initiate a database connection.
@@ -23,17 +71,286 @@ fn main() {
     // In a real-life scenario it is assumed that the associated function init would initiate a database connection.
     let mut client_db = ClientDb::init();
 
+    // When a SQLite database is supplied, start from its stored state instead of empty databases.
+    #[cfg(feature = "sqlite")]
+    let mut store = match &args.db {
+        Some(path) => match sqlite::SqliteStore::open(path).and_then(|store| {
+            client_db = store.load_client_db()?;
+            transaction_db = store.load_transaction_db()?;
+            Ok(store)
+        }) {
+            Ok(store) => Some(store),
+            Err(err) => {
+                println!("Error loading state from SQLite database: {}", err);
+                std::process::exit(EXIT_INPUT_ERROR)
+            }
+        },
+        None => None,
+    };
+
+    // Bound the number of transactions kept for disputes if requested.
+    if let Some(max_tracked) = args.max_tracked_transactions {
+        transaction_db.set_max_tracked(max_tracked);
+    }
+
+    // Start from a previous run's client output if a snapshot was supplied.
+    if let Some(snapshot_reader) = args.create_snapshot_reader() {
+        let snapshot_reader = match snapshot_reader {
+            Ok(snapshot_reader) => snapshot_reader,
+            Err(err) => {
+                println!("Error opening client snapshot: {}", err);
+                std::process::exit(EXIT_INVALID_PATH)
+            }
+        };
+        if let Err(err) = client_db.load_snapshot(snapshot_reader, &config) {
+            println!("Error loading client snapshot: {}", err);
+            std::process::exit(EXIT_INPUT_ERROR)
+        }
+    }
+
+    // Validate that disputes reference known transactions before applying anything.
+    if args.check_references {
+        let checked = tx_inputs.readers_to_check().and_then(|tx_readers| {
+            transaction::check_references(tx_readers, &transaction_db, &config)
+        });
+        match checked {
+            Ok(dangling) if dangling.is_empty() => {}
+            Ok(dangling) => {
+                for reference in &dangling {
+                    eprintln!("{}", reference);
+                }
+                println!("Found {} dangling transaction references", dangling.len());
+                std::process::exit(EXIT_INPUT_ERROR)
+            }
+            Err(err) => {
+                println!("Error checking transaction references: {}", err);
+                std::process::exit(EXIT_INPUT_ERROR)
+            }
+        }
+    }
+
     // Apply Transactions to Client Database or exit on error.
-    if let Err(err) =
-        transaction::apply_transactions(tx_reader, &mut transaction_db, &mut client_db)
-    {
-        println!("Error applying transactions to client database: {}", err);
-        std::process::exit(1)
+    // With --explain, the reason for each rejected transaction is written to stderr.
+    let mut stderr = std::io::stderr();
+    let explain: Option<&mut dyn std::io::Write> = match args.explain {
+        true => Some(&mut stderr),
+        false => None,
+    };
+    let tx_readers = tx_inputs.into_readers();
+    // With --shards, the files are applied concurrently with clients sharded across worker threads.
+    let applied = match args.shards {
+        Some(shards) => transaction::apply_transactions_sharded(
+            tx_readers,
+            shards,
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+        ),
+        None => transaction::apply_transactions(
+            tx_readers,
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            explain,
+        ),
+    };
+    let stats = match applied {
+        Ok(stats) => stats,
+        Err(err) => {
+            println!("Error applying transactions to client database: {}", err);
+            std::process::exit(EXIT_INPUT_ERROR)
+        }
+    };
+
+    // With --dry-run, only report the problems found with the input. Nothing is written and the database is
+    // left untouched.
+    if args.dry_run {
+        for error in stats.errors() {
+            eprintln!("{}", error);
+        }
+        eprintln!("{}", stats.dry_run_summary());
+        if stats.error_count() > 0 {
+            std::process::exit(EXIT_INPUT_ERROR)
+        }
+        return;
+    }
+
+    // Report skipped rows, how many transactions were rejected, and record each rejection if requested.
+    if let Some(warning) = stats.malformed_warning() {
+        eprintln!("{}", warning);
+    }
+    if let Some(summary) = stats.rejection_summary() {
+        eprintln!("{}", summary);
+    }
+    if let Some(file) = args.create_rejects_file() {
+        let written = file
+            .map_err(Into::into)
+            .and_then(|file| stats.write_rejections(file));
+        if let Err(err) = written {
+            println!("Error writing rejected transactions: {}", err);
+            std::process::exit(EXIT_OUTPUT_ERROR)
+        }
+    }
+
+    // Write the journal of applied transactions if requested.
+    if let Some(file) = args.create_ledger_file() {
+        let written = file
+            .map_err(Into::into)
+            .and_then(|file| stats.write_ledger(file));
+        if let Err(err) = written {
+            println!("Error writing transaction ledger: {}", err);
+            std::process::exit(EXIT_OUTPUT_ERROR)
+        }
+    }
+
+    // Flag clients transacting at a suspicious rate, and activity on previously locked accounts for audit.
+    stats.send_alerts(&mut StderrAlertSink);
+
+    // Warn that the output is partial if processing was cut short.
+    if let Some(warning) = stats.truncation_warning() {
+        eprintln!("{}", warning);
+    }
+    if let Some(warning) = stats.deferred_warning() {
+        eprintln!("{}", warning);
+    }
+
+    // Quick operator sanity check of the resulting client states.
+    if args.client_summary {
+        eprintln!("{}", client_db.summary());
+    }
+
+    // Report clients whose balances do not add up, catching bugs in how transactions were applied.
+    if args.audit {
+        let discrepancies = client_db.audit();
+        for discrepancy in &discrepancies {
+            eprintln!("{}", discrepancy);
+        }
+        eprintln!("audit found {} discrepancies", discrepancies.len());
+    }
+
+    // Report disputes left open by this run, for compliance to categorise by reason.
+    if args.dispute_report {
+        for open_dispute in transaction_db.open_disputes() {
+            eprintln!("{}", open_dispute);
+        }
+    }
+
+    // Print the requested transaction lineage for operators investigating a dispute.
+    if let Some(transaction_id) = args.lineage {
+        for transaction in transaction_db.lineage(transaction_id) {
+            eprintln!(
+                "tx {}: {} client {} amount {}",
+                transaction.transaction_id,
+                transaction.transaction_type.as_str(),
+                transaction.client_id,
+                transaction
+                    .amount
+                    .map_or_else(String::new, |amount| amount.to_string())
+            );
+        }
+    }
+
+    // Persist the updated state so that it survives to the next run.
+    #[cfg(feature = "sqlite")]
+    if let Some(store) = store.as_mut() {
+        if let Err(err) = store.save(&client_db, &transaction_db) {
+            println!("Error saving state to SQLite database: {}", err);
+            std::process::exit(EXIT_OUTPUT_ERROR)
+        }
     }
 
-    // Send Client Records csv formatted to stdout or exit on error.
-    if let Err(err) = client_db.to_csv_stdout() {
-        println!("Error sending client database to stdout: {}", err);
-        std::process::exit(1)
+    // Send Client Records csv formatted to stdout, or to an output file, or to sharded files, or exit on error.
+    let output_checksum = match args.shard_output {
+        Some(shards) => {
+            let written = args
+                .create_output_shards(shards)
+                .map_err(Into::into)
+                .and_then(|outs| client_db.write_csv_shards(outs, &args.output_config()));
+            if let Err(err) = written {
+                println!("Error writing sharded client output: {}", err);
+                std::process::exit(EXIT_OUTPUT_ERROR)
+            }
+            None
+        }
+        None => match args.create_output_file() {
+            Some(file) => match file
+                .map_err(Into::into)
+                .and_then(|file| client_db.write_output(file, &args.output_config()))
+            {
+                Ok(checksum) => Some(checksum),
+                Err(err) => {
+                    println!("Error writing client database to output file: {}", err);
+                    std::process::exit(EXIT_OUTPUT_ERROR)
+                }
+            },
+            None => match client_db.write_output(
+                BufWriter::new(std::io::stdout().lock()),
+                &args.output_config(),
+            ) {
+                Ok(checksum) => Some(checksum),
+                Err(err) => {
+                    println!("Error sending client database to stdout: {}", err);
+                    std::process::exit(EXIT_OUTPUT_ERROR)
+                }
+            },
+        },
+    };
+
+    // Record the provenance of this run if requested.
+    // Metadata is not supported with sharded output, so a checksum is always available here.
+    if let (Some(meta_path), Some(output_checksum)) = (&args.meta, output_checksum) {
+        let metadata = RunMetadata::new(
+            args.transaction_file_paths.clone(),
+            stats.rows_read,
+            output_checksum,
+            args.prev_hash.clone(),
+            &client_db,
+        );
+        if let Err(err) = metadata.write_to_path(meta_path) {
+            eprintln!("Error writing run metadata: {}", err);
+            std::process::exit(EXIT_OUTPUT_ERROR)
+        }
+    }
+
+    // Summarise what the run applied, on stderr so it does not mix with the client output.
+    eprintln!("{}", stats.summary);
+}
+
+// Apply the buffered transaction inputs once per scenario. Each scenario's options are added to the command
+// line it was given with, so they are parsed and validated exactly as they would be for a single run.
+fn replay_scenarios(args: &CliArgs) {
+    let inputs = match args.read_tx_inputs() {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            println!("Error opening transaction input: {}", err);
+            std::process::exit(EXIT_INVALID_PATH)
+        }
+    };
+    let configs: Vec<ScenarioConfig> = args
+        .scenarios
+        .iter()
+        .map(|scenario| {
+            let scenario_args = std::env::args().chain(scenario.options.iter().cloned());
+            let scenario_args =
+                CliArgs::try_parse_from(scenario_args).unwrap_or_else(|err| err.exit());
+            ScenarioConfig {
+                name: scenario.name.clone(),
+                engine: scenario_args.engine_config(),
+                output: scenario_args.output_config(),
+            }
+        })
+        .collect();
+    let replayed =
+        scenario::replay_scenarios(&inputs, &configs, BufWriter::new(std::io::stdout().lock()));
+    match replayed {
+        Ok(all_stats) => {
+            for (config, stats) in configs.iter().zip(&all_stats) {
+                eprintln!("{}: {}", config.name, stats.summary);
+            }
+        }
+        Err(err) => {
+            println!("Error replaying scenarios: {}", err);
+            std::process::exit(EXIT_INPUT_ERROR)
+        }
     }
 }
@@ -0,0 +1,230 @@
+use crate::client::ClientDb;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ------------------------------------------------------------------------------------------------
+// -------------------------------- RUN METADATA STRUCT -------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Provenance record tying the output of a run to its inputs, written as JSON for audit trails.
+#[derive(Serialize, Debug)]
+pub struct RunMetadata {
+    pub input_files: Vec<String>,
+    pub row_count: usize,
+    // Seconds since the unix epoch at which processing finished.
+    pub processed_at: u64,
+    pub engine_version: String,
+    // Hex encoded SHA-256 of the client csv written by the run.
+    pub output_checksum: String,
+    // Aggregate balances across all clients at the end of the run.
+    // Written as JSON numbers, or null if the sum is too large to represent.
+    #[serde(with = "rust_decimal::serde::float_option")]
+    pub total_available: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::float_option")]
+    pub total_held: Option<Decimal>,
+    // Chained hash of the previous run, when this run follows on from it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<String>,
+    // Hex encoded SHA-256 chaining this run's output checksum to the previous run's chained hash, so a
+    // sequence of runs is tamper-evident: altering any earlier output changes every later chained hash.
+    pub chained_hash: String,
+}
+
+// Writer wrapper computing the SHA-256 checksum of everything written through it.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------ RUN METADATA ASSOCIATED FUNCTIONS -------------------------------
+// ------------------------------------------------------------------------------------------------
+
+impl RunMetadata {
+    // Build the metadata for a completed run, stamped with the current time and engine version.
+    pub fn new(
+        input_files: Vec<String>,
+        row_count: usize,
+        output_checksum: String,
+        prev_hash: Option<String>,
+        client_db: &ClientDb,
+    ) -> Self {
+        let processed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        RunMetadata {
+            input_files,
+            row_count,
+            processed_at,
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            chained_hash: chain_hash(prev_hash.as_deref(), &output_checksum),
+            output_checksum,
+            total_available: client_db.total_available(),
+            total_held: client_db.total_held(),
+            prev_hash,
+        }
+    }
+
+    // Write the metadata as JSON to the given path.
+    pub fn write_to_path(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+// Hex encoded SHA-256 of the previous run's chained hash followed by this run's output checksum. The first
+// run of a chain has no previous hash, so only its output checksum is hashed.
+pub fn chain_hash(prev_hash: Option<&str>, output_checksum: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.unwrap_or_default());
+    hasher.update(output_checksum);
+    hex(&hasher.finalize())
+}
+
+// Lowercase hex encoding of a digest.
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    // Hex encoded checksum of the bytes written so far.
+    pub fn hex_digest(&self) -> String {
+        hex(&self.hasher.clone().finalize())
+    }
+}
+
+// Only the bytes accepted by the inner writer are added to the checksum.
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EngineConfig, OutputConfig};
+    use crate::transaction::{self, TransactionDb};
+    use csv::{ReaderBuilder, Trim};
+
+    #[test]
+    fn metadata_file_contains_run_fields() -> Result<(), Box<dyn Error>> {
+        // Run a small file through the engine and make sure the written metadata records the input,
+        // row count, timestamp, version and the checksum of the produced output.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, 5.0
+                    withdrawal, 1, 3, 2.5";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = ClientDb::init();
+        let stats = transaction::apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        let mut output = ChecksumWriter::new(Vec::new());
+        client_db.write_csv(&mut output, &OutputConfig::default())?;
+        let expected_checksum = Sha256::digest(&output.inner)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let dir = tempfile::tempdir()?;
+        let meta_path = dir.path().join("meta.json").display().to_string();
+        RunMetadata::new(
+            vec!["input.csv".to_string()],
+            stats.rows_read,
+            output.hex_digest(),
+            None,
+            &client_db,
+        )
+        .write_to_path(&meta_path)?;
+
+        let meta: serde_json::Value = serde_json::from_reader(File::open(&meta_path)?)?;
+        assert_eq!(meta["input_files"], serde_json::json!(["input.csv"]));
+        assert_eq!(meta["row_count"], 3);
+        assert!(meta["processed_at"].as_u64().is_some());
+        assert_eq!(meta["engine_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(meta["output_checksum"], expected_checksum);
+        assert_eq!(meta["total_available"], 12.5);
+        assert_eq!(meta["total_held"], 0.0);
+        assert!(meta.get("prev_hash").is_none());
+        Ok(())
+    }
+
+    // Checksum of the client output of a run applying the given transactions.
+    fn output_checksum(data: &str) -> Result<String, Box<dyn Error>> {
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut client_db = ClientDb::init();
+        transaction::apply_transactions(
+            vec![rdr],
+            &mut TransactionDb::init(),
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+        let mut output = ChecksumWriter::new(Vec::new());
+        client_db.write_csv(&mut output, &OutputConfig::default())?;
+        Ok(output.hex_digest())
+    }
+
+    #[test]
+    fn chained_hash_links_runs() -> Result<(), Box<dyn Error>> {
+        // Chaining the same output to the previous run's hash always gives the same hash, which differs
+        // from the unchained hash and changes if the run's input is altered.
+        let first = chain_hash(
+            None,
+            &output_checksum("type, client, tx, amount\ndeposit, 1, 1, 10.0")?,
+        );
+        let second_checksum = output_checksum("type, client, tx, amount\ndeposit, 1, 2, 5.0")?;
+        let chained = chain_hash(Some(&first), &second_checksum);
+        assert_eq!(chained, chain_hash(Some(&first), &second_checksum));
+        assert_eq!(chained.len(), 64);
+        assert_ne!(chained, chain_hash(None, &second_checksum));
+
+        let altered_checksum = output_checksum("type, client, tx, amount\ndeposit, 1, 2, 6.0")?;
+        assert_ne!(chained, chain_hash(Some(&first), &altered_checksum));
+
+        let metadata = RunMetadata::new(
+            vec!["input.csv".to_string()],
+            1,
+            second_checksum,
+            Some(first.clone()),
+            &ClientDb::init(),
+        );
+        assert_eq!(metadata.prev_hash, Some(first));
+        assert_eq!(metadata.chained_hash, chained);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use crate::alert::{Alert, AlertKind};
+use crate::config::BurstLimit;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------- BURST MONITOR STRUCT -----------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Tracks a sliding window of recent transaction timestamps for each client, flagging clients whose
+// activity within the window exceeds the configured threshold.
+pub struct BurstMonitor {
+    limit: BurstLimit,
+    windows: HashMap<u16, VecDeque<u64>>,
+}
+
+impl BurstMonitor {
+    pub fn new(limit: BurstLimit) -> Self {
+        BurstMonitor {
+            limit,
+            windows: HashMap::new(),
+        }
+    }
+
+    // Record a transaction for the client at the timestamp (seconds since the unix epoch), dropping
+    // timestamps that have slid out of the window. Returns an alert when the client goes over the
+    // threshold. A burst is alerted once, when it crosses the threshold, rather than on every further row.
+    pub fn record(&mut self, client_id: u16, timestamp: u64, line: u64) -> Option<Alert> {
+        let window = self.windows.entry(client_id).or_default();
+        while window.front().is_some_and(|&oldest| {
+            Duration::from_secs(timestamp.saturating_sub(oldest)) >= self.limit.window
+        }) {
+            window.pop_front();
+        }
+        window.push_back(timestamp);
+        (window.len() == self.limit.threshold + 1).then_some(Alert {
+            client_id,
+            line,
+            kind: AlertKind::Burst {
+                transactions: window.len(),
+                window: self.limit.window,
+                threshold: self.limit.threshold,
+            },
+        })
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor() -> BurstMonitor {
+        BurstMonitor::new(BurstLimit {
+            threshold: 3,
+            window: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn burst_triggers_alert() {
+        // Four transactions within a minute cross the threshold of three, alerting once for the burst.
+        let mut monitor = monitor();
+        let alerts: Vec<String> = [100, 110, 120, 130, 140]
+            .into_iter()
+            .zip(2..)
+            .filter_map(|(timestamp, line)| monitor.record(1, timestamp, line))
+            .map(|alert| alert.to_string())
+            .collect();
+        assert_eq!(
+            alerts,
+            vec![
+                "alert: line 5: client 1 made 4 transactions within 60s, over the burst threshold of 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn normal_rate_does_not_alert() {
+        // One transaction every 30 seconds never has more than two in the window, and other clients'
+        // activity is tracked separately.
+        let mut monitor = monitor();
+        for (timestamp, line) in (0..20).map(|step| step * 30).zip(2..) {
+            assert_eq!(monitor.record(1, timestamp, line), None);
+            assert_eq!(monitor.record(2, timestamp, line), None);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::error::Error;
+use std::io::Read;
+
+// ------------------------------------------------------------------------------------------------
+// ---------------------------------- REMOTE INPUT FUNCTIONS --------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Prefixes of input paths that are fetched over the network rather than read from disk.
+const URL_SCHEMES: [&str; 3] = ["http://", "https://", "s3://"];
+
+// True if the input path is a URL to fetch rather than a local file.
+pub fn is_url(path: &str) -> bool {
+    URL_SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+// Map an `s3://bucket/key` URL to the bucket's virtual-hosted https endpoint. Requests are unsigned, so
+// the object must be publicly readable (private objects can be supplied as a presigned https URL instead).
+fn to_http_url(url: &str) -> String {
+    match url
+        .strip_prefix("s3://")
+        .and_then(|path| path.split_once('/'))
+    {
+        Some((bucket, key)) => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
+        None => url.to_string(),
+    }
+}
+
+// Fetch the input at the URL, returning the response body as a stream for the csv reader.
+// Network failures and non-success statuses are reported with the URL that could not be fetched.
+pub fn open_url(url: &str) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    let response = ureq::get(&to_http_url(url))
+        .call()
+        .map_err(|err| match err {
+            ureq::Error::Status(status, _) => {
+                format!("fetching {} failed with HTTP status {}", url, status)
+            }
+            ureq::Error::Transport(transport) => match transport.source() {
+                Some(cause) => format!("could not fetch {}: {}: {}", url, transport.kind(), cause),
+                None => format!("could not fetch {}: {}", url, transport.kind()),
+            },
+        })?;
+    Ok(response.into_reader())
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientDb;
+    use crate::config::EngineConfig;
+    use crate::transaction::{self, TransactionDb};
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal_macros::dec;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serve a single request on a local port with the given status line and body, returning the base URL.
+    fn serve_once(status: &'static str, body: &'static str) -> Result<String, Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = BufReader::new(stream);
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(
+                request.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        Ok(format!("http://{}", address))
+    }
+
+    #[test]
+    fn transactions_read_from_http_url() -> Result<(), Box<dyn Error>> {
+        // Serve a csv from a local HTTP server and make sure it is streamed into the engine.
+        let url = serve_once(
+            "200 OK",
+            "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n",
+        )?;
+        let url = format!("{}/transactions.csv", url);
+        assert!(is_url(&url));
+
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(open_url(&url)?);
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = ClientDb::init();
+        transaction::apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(
+            client_db.get_client_record(&1).unwrap().available,
+            dec!(6.0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn http_error_status_reported_with_url() -> Result<(), Box<dyn Error>> {
+        // Make sure a missing remote file is reported clearly rather than parsed as an empty csv.
+        let url = format!("{}/missing.csv", serve_once("404 Not Found", "")?);
+        let err = open_url(&url)
+            .err()
+            .expect("404 response should be reported");
+        assert_eq!(
+            err.to_string(),
+            format!("fetching {} failed with HTTP status 404", url)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn s3_url_maps_to_bucket_endpoint() {
+        // Make sure s3 URLs are fetched from the bucket's https endpoint and other URLs are left alone.
+        assert_eq!(
+            to_http_url("s3://ledger/2024/transactions.csv"),
+            "https://ledger.s3.amazonaws.com/2024/transactions.csv"
+        );
+        assert_eq!(
+            to_http_url("https://example.com/transactions.csv"),
+            "https://example.com/transactions.csv"
+        );
+    }
+}
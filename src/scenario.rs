@@ -0,0 +1,153 @@
+use crate::cli_args::transaction_reader_builder;
+use crate::client::ClientDb;
+use crate::config::{EngineConfig, OutputConfig};
+use crate::transaction::{apply_transactions, ProcessingStats, TransactionDb};
+use std::error::Error;
+use std::io::Write;
+use std::str::FromStr;
+
+// ------------------------------------------------------------------------------------------------
+// ---------------------------------------- SCENARIO STRUCT ---------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// A named set of command line options to replay the transactions under, given as `name=options`, e.g.
+// `floor=--minimum-balance 5`. The options are split on whitespace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub options: Vec<String>,
+}
+
+impl FromStr for Scenario {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((name, options)) if !name.trim().is_empty() => Ok(Scenario {
+                name: name.trim().to_string(),
+                options: options.split_whitespace().map(str::to_string).collect(),
+            }),
+            _ => Err(format!(
+                "`{}` is not a scenario, expected NAME=OPTIONS, e.g. floor=--minimum-balance 5",
+                value
+            )),
+        }
+    }
+}
+
+// The configuration a scenario's options resolve to.
+pub struct ScenarioConfig {
+    pub name: String,
+    pub engine: EngineConfig,
+    pub output: OutputConfig,
+}
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------------ REPLAY SCENARIOS FUNCTION ---------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Applies the same buffered transaction inputs under each scenario in turn, each starting from empty
+// databases, so operators can compare the outcome of different policies. Each scenario's clients are
+// written to the writer after a `# scenario: <name>` label line. Returns the processing stats of each
+// scenario, in order.
+pub fn replay_scenarios<W: Write>(
+    inputs: &[Vec<u8>],
+    scenarios: &[ScenarioConfig],
+    mut out: W,
+) -> Result<Vec<ProcessingStats>, Box<dyn Error>> {
+    let mut all_stats = Vec::new();
+    for scenario in scenarios {
+        let readers = inputs
+            .iter()
+            .map(|input| transaction_reader_builder().from_reader(input.as_slice()))
+            .collect();
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = ClientDb::init();
+        let stats = apply_transactions(
+            readers,
+            &mut transaction_db,
+            &mut client_db,
+            &scenario.engine,
+            None,
+        )?;
+        writeln!(out, "# scenario: {}", scenario.name)?;
+        client_db.write_output(&mut out, &scenario.output)?;
+        all_stats.push(stats);
+    }
+    Ok(all_stats)
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn scenario_parsed_from_name_and_options() {
+        // Make sure the name and whitespace separated options are split apart, that a scenario may have no
+        // options, and that a scenario without a name is refused.
+        assert_eq!(
+            "floor=--minimum-balance 5".parse(),
+            Ok(Scenario {
+                name: "floor".to_string(),
+                options: vec!["--minimum-balance".to_string(), "5".to_string()],
+            })
+        );
+        assert_eq!(
+            "default=".parse(),
+            Ok(Scenario {
+                name: "default".to_string(),
+                options: Vec::new(),
+            })
+        );
+        assert!("--minimum-balance 5".parse::<Scenario>().is_err());
+        assert!("=--minimum-balance 5".parse::<Scenario>().is_err());
+    }
+
+    #[test]
+    fn each_scenario_written_with_its_label() -> Result<(), Box<dyn Error>> {
+        // The withdrawal of 8 is applied by default, but rejected when a balance of 5 must be kept, so the two
+        // labeled outputs differ in client 1's balances.
+        let input = "type, client, tx, amount
+                     deposit, 1, 1, 10.0
+                     withdrawal, 1, 2, 8.0
+                     deposit, 2, 3, 3.0"
+            .as_bytes()
+            .to_vec();
+        let scenarios = [
+            ScenarioConfig {
+                name: "default".to_string(),
+                engine: EngineConfig::default(),
+                output: OutputConfig::default(),
+            },
+            ScenarioConfig {
+                name: "floor".to_string(),
+                engine: EngineConfig {
+                    minimum_balance: dec!(5),
+                    ..EngineConfig::default()
+                },
+                output: OutputConfig::default(),
+            },
+        ];
+        let mut written = Vec::new();
+        let stats = replay_scenarios(&[input], &scenarios, &mut written)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "# scenario: default\n\
+             client,available,held,total,locked\n\
+             1,2.0,0.0,2.0,false\n\
+             2,3.0,0.0,3.0,false\n\
+             # scenario: floor\n\
+             client,available,held,total,locked\n\
+             1,10.0,0.0,10.0,false\n\
+             2,3.0,0.0,3.0,false\n"
+        );
+        assert_eq!(stats[0].rejections.len(), 0);
+        assert_eq!(stats[1].rejections.len(), 1);
+        Ok(())
+    }
+}
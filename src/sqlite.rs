@@ -0,0 +1,705 @@
+use crate::amount::Amount;
+use crate::client::{Client, ClientDb};
+use crate::transaction::{DisputeAmounts, Transaction, TransactionDb, TransactionType};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, ToSql};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::path::Path;
+
+// ------------------------------------------------------------------------------------------------
+// -------------------------------- SQLITE STORE STRUCT -------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// SQLite snapshot of the Client and Transaction databases, persisting them between runs. It is not a
+// `Storage` implementation: the stored state is loaded into in-memory databases before a run and the whole
+// state is saved back after it, so any transaction forgotten during the run is deleted from the file too.
+// Client records are stored whole, with their balances (so held funds from disputes are reflected), whether
+// they were ever locked, and the running sums the audit checks their total against, and
+// deposits/withdrawals are stored so that they can still be disputed in a later run, along with the
+// dispute state and amounts of each disputed transaction so an open dispute can be resolved or charged back
+// later, and a partially charged back transaction disputed again. The dispute/resolve/chargeback/correction
+// events of each transaction are stored in order, so a corrected transaction is disputed at its corrected
+// amount in a later run. Each deposit/withdrawal keeps its timestamp, so the withdrawal dispute window still
+// applies after a reload, and its position in arrival order, so the oldest is still forgotten first.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+const CREATE_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        client                INTEGER PRIMARY KEY,
+        available             TEXT    NOT NULL,
+        held                  TEXT    NOT NULL,
+        total                 TEXT    NOT NULL,
+        locked                INTEGER NOT NULL,
+        was_ever_locked       INTEGER NOT NULL DEFAULT 0,
+        pending               TEXT    NOT NULL DEFAULT '0',
+        total_deposited       TEXT    NOT NULL DEFAULT '0',
+        total_withdrawn       TEXT    NOT NULL DEFAULT '0',
+        interest_accrued_to   INTEGER,
+        opening_total         TEXT,
+        interest_credited     TEXT    NOT NULL DEFAULT '0',
+        charged_back          TEXT    NOT NULL DEFAULT '0',
+        rounding_residues     TEXT    NOT NULL DEFAULT '0',
+        reclaimed_withdrawals TEXT    NOT NULL DEFAULT '0'
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx        INTEGER PRIMARY KEY,
+        type      TEXT    NOT NULL,
+        client    INTEGER NOT NULL,
+        amount    TEXT,
+        timestamp INTEGER,
+        sequence  INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS dispute_states (
+        tx    INTEGER PRIMARY KEY,
+        state TEXT    NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS dispute_amounts (
+        tx           INTEGER PRIMARY KEY,
+        disputed     TEXT    NOT NULL,
+        charged_back TEXT    NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS dispute_events (
+        tx       INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        type     TEXT    NOT NULL,
+        client   INTEGER NOT NULL,
+        amount   TEXT,
+        reason   TEXT,
+        PRIMARY KEY (tx, position)
+    );";
+
+// Client columns added after the clients table was first created, added to databases created before them.
+// Existing rows take the column default; a missing opening total is read as the client's total, which is
+// what it was taken to be before the running sums were stored.
+const ADDED_CLIENT_COLUMNS: [(&str, &str); 10] = [
+    ("was_ever_locked", "INTEGER NOT NULL DEFAULT 0"),
+    ("pending", "TEXT NOT NULL DEFAULT '0'"),
+    ("total_deposited", "TEXT NOT NULL DEFAULT '0'"),
+    ("total_withdrawn", "TEXT NOT NULL DEFAULT '0'"),
+    ("interest_accrued_to", "INTEGER"),
+    ("opening_total", "TEXT"),
+    ("interest_credited", "TEXT NOT NULL DEFAULT '0'"),
+    ("charged_back", "TEXT NOT NULL DEFAULT '0'"),
+    ("rounding_residues", "TEXT NOT NULL DEFAULT '0'"),
+    ("reclaimed_withdrawals", "TEXT NOT NULL DEFAULT '0'"),
+];
+
+// Transaction columns added after the transactions table was first created. Transactions stored before them
+// have no timestamp, and no sequence so they are loaded first, ordered by id.
+const ADDED_TRANSACTION_COLUMNS: [(&str, &str); 2] =
+    [("timestamp", "INTEGER"), ("sequence", "INTEGER")];
+
+// Balances and amounts are stored as decimal text so they round trip exactly. Databases created before
+// this stored them in REAL columns, which SQLite converts numeric text back into, so REAL values are read too.
+struct SqlDecimal(Decimal);
+
+impl ToSql for SqlDecimal {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.to_string()))
+    }
+}
+
+impl FromSql for SqlDecimal {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(text) => std::str::from_utf8(text)
+                .ok()
+                .and_then(|text| text.parse().ok())
+                .map(SqlDecimal)
+                .ok_or(FromSqlError::InvalidType),
+            ValueRef::Real(real) => Decimal::try_from(real)
+                .map(SqlDecimal)
+                .map_err(|err| FromSqlError::Other(Box::new(err))),
+            ValueRef::Integer(integer) => Ok(SqlDecimal(Decimal::from(integer))),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------ SQLITE STORE ASSOCIATED FUNCTIONS -------------------------------
+// ------------------------------------------------------------------------------------------------
+
+impl SqliteStore {
+    // Open (or create) the SQLite database at the given path and ensure the tables, and every column of
+    // them, exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(CREATE_TABLES)?;
+        add_missing_columns(&conn, "clients", &ADDED_CLIENT_COLUMNS)?;
+        add_missing_columns(&conn, "transactions", &ADDED_TRANSACTION_COLUMNS)?;
+        Ok(SqliteStore { conn })
+    }
+
+    // Load every stored client record into a fresh Client Database.
+    pub fn load_client_db(&self) -> Result<ClientDb, Box<dyn Error>> {
+        let mut client_db = ClientDb::init();
+        let mut stmt = self.conn.prepare(
+            "SELECT client, available, held, total, locked, was_ever_locked, pending, total_deposited,
+                    total_withdrawn, interest_accrued_to, opening_total, interest_credited, charged_back,
+                    rounding_residues, reclaimed_withdrawals
+             FROM clients",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let total = row.get::<_, SqlDecimal>(3)?.0;
+            client_db.insert_client_record(Client {
+                available: row.get::<_, SqlDecimal>(1)?.0,
+                held: row.get::<_, SqlDecimal>(2)?.0,
+                total,
+                locked: row.get(4)?,
+                was_ever_locked: row.get(5)?,
+                pending: row.get::<_, SqlDecimal>(6)?.0,
+                total_deposited: Amount::new(row.get::<_, SqlDecimal>(7)?.0)?,
+                total_withdrawn: Amount::new(row.get::<_, SqlDecimal>(8)?.0)?,
+                interest_accrued_to: row.get(9)?,
+                opening_total: row
+                    .get::<_, Option<SqlDecimal>>(10)?
+                    .map_or(total, |opening_total| opening_total.0),
+                interest_credited: row.get::<_, SqlDecimal>(11)?.0,
+                charged_back: row.get::<_, SqlDecimal>(12)?.0,
+                rounding_residues: row.get::<_, SqlDecimal>(13)?.0,
+                reclaimed_withdrawals: row.get::<_, SqlDecimal>(14)?.0,
+                ..Client::new(row.get(0)?)
+            });
+        }
+        Ok(client_db)
+    }
+
+    // Load every stored deposit/withdrawal in the order they arrived in, and the dispute states, amounts and
+    // events, into a fresh Transaction Database.
+    pub fn load_transaction_db(&self) -> Result<TransactionDb, Box<dyn Error>> {
+        let mut transaction_db = TransactionDb::init();
+        let mut stmt = self.conn.prepare(
+            "SELECT tx, type, client, amount, timestamp FROM transactions ORDER BY sequence, tx",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let transaction_type: String = row.get(1)?;
+            let transaction_type: TransactionType = transaction_type.parse()?;
+            transaction_db.insert_transaction(Transaction {
+                timestamp: row.get(4)?,
+                ..Transaction::new(
+                    transaction_type,
+                    row.get(2)?,
+                    row.get(0)?,
+                    row.get::<_, Option<SqlDecimal>>(3)?
+                        .map(|amount| Amount::new(amount.0))
+                        .transpose()?,
+                )
+            });
+        }
+        let mut stmt = self.conn.prepare("SELECT tx, state FROM dispute_states")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let state: String = row.get(1)?;
+            transaction_db.set_dispute_state(row.get(0)?, state.parse()?);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT tx, disputed, charged_back FROM dispute_amounts")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let amounts = DisputeAmounts {
+                disputed: Amount::new(row.get::<_, SqlDecimal>(1)?.0)?,
+                charged_back: Amount::new(row.get::<_, SqlDecimal>(2)?.0)?,
+            };
+            transaction_db.set_dispute_amounts(row.get(0)?, amounts);
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT tx, type, client, amount, reason FROM dispute_events ORDER BY tx, position",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let transaction_type: String = row.get(1)?;
+            transaction_db.push_dispute_event(Transaction {
+                reason: row.get(4)?,
+                ..Transaction::new(
+                    transaction_type.parse()?,
+                    row.get(2)?,
+                    row.get(0)?,
+                    row.get::<_, Option<SqlDecimal>>(3)?
+                        .map(|amount| Amount::new(amount.0))
+                        .transpose()?,
+                )
+            });
+        }
+        Ok(transaction_db)
+    }
+
+    // Write the current state of both databases to SQLite within a single sqlite transaction. Client records
+    // replace any stored with the same id, while the stored transactions and their dispute states, amounts
+    // and events are replaced whole, so those forgotten since they were loaded are deleted.
+    pub fn save(
+        &mut self,
+        client_db: &ClientDb,
+        transaction_db: &TransactionDb,
+    ) -> Result<(), Box<dyn Error>> {
+        let sql_tx = self.conn.transaction()?;
+        sql_tx.execute_batch(
+            "DELETE FROM transactions;
+             DELETE FROM dispute_states;
+             DELETE FROM dispute_amounts;
+             DELETE FROM dispute_events;",
+        )?;
+        {
+            let mut insert_client = sql_tx.prepare(
+                "INSERT OR REPLACE INTO clients (client, available, held, total, locked, was_ever_locked,
+                    pending, total_deposited, total_withdrawn, interest_accrued_to, opening_total,
+                    interest_credited, charged_back, rounding_residues, reclaimed_withdrawals)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            )?;
+            for client in client_db.client_records() {
+                insert_client.execute(params![
+                    client.client_id,
+                    SqlDecimal(client.available),
+                    SqlDecimal(client.held),
+                    SqlDecimal(client.total),
+                    client.locked,
+                    client.was_ever_locked,
+                    SqlDecimal(client.pending),
+                    SqlDecimal(client.total_deposited.value()),
+                    SqlDecimal(client.total_withdrawn.value()),
+                    client.interest_accrued_to,
+                    SqlDecimal(client.opening_total),
+                    SqlDecimal(client.interest_credited),
+                    SqlDecimal(client.charged_back),
+                    SqlDecimal(client.rounding_residues),
+                    SqlDecimal(client.reclaimed_withdrawals)
+                ])?;
+            }
+
+            let mut insert_transaction = sql_tx.prepare(
+                "INSERT INTO transactions (tx, type, client, amount, timestamp, sequence)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for (sequence, transaction) in transaction_db.iter_in_order().enumerate() {
+                insert_transaction.execute(params![
+                    transaction.transaction_id,
+                    transaction.transaction_type.as_str(),
+                    transaction.client_id,
+                    transaction.amount.map(|amount| SqlDecimal(amount.value())),
+                    transaction.timestamp,
+                    sequence
+                ])?;
+            }
+
+            let mut insert_dispute_state =
+                sql_tx.prepare("INSERT INTO dispute_states (tx, state) VALUES (?1, ?2)")?;
+            for (transaction_id, state) in transaction_db.dispute_states() {
+                insert_dispute_state.execute(params![transaction_id, state.as_str()])?;
+            }
+
+            let mut insert_dispute_amounts = sql_tx.prepare(
+                "INSERT INTO dispute_amounts (tx, disputed, charged_back)
+                 VALUES (?1, ?2, ?3)",
+            )?;
+            for (transaction_id, amounts) in transaction_db.all_dispute_amounts() {
+                insert_dispute_amounts.execute(params![
+                    transaction_id,
+                    SqlDecimal(amounts.disputed.value()),
+                    SqlDecimal(amounts.charged_back.value())
+                ])?;
+            }
+
+            let mut insert_dispute_event = sql_tx.prepare(
+                "INSERT INTO dispute_events (tx, position, type, client, amount, reason)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for (transaction_id, events) in transaction_db.dispute_events() {
+                for (position, event) in events.iter().enumerate() {
+                    insert_dispute_event.execute(params![
+                        transaction_id,
+                        position,
+                        event.transaction_type.as_str(),
+                        event.client_id,
+                        event.amount.map(|amount| SqlDecimal(amount.value())),
+                        event.reason
+                    ])?;
+                }
+            }
+        }
+        sql_tx.commit()?;
+        Ok(())
+    }
+}
+
+// Add any of the given columns missing from a table created by an older version.
+fn add_missing_columns(
+    conn: &Connection,
+    table: &str,
+    added_columns: &[(&str, &str)],
+) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (column, definition) in added_columns {
+        if !columns.iter().any(|name| name == column) {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::error::TransactionError;
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+
+    #[test]
+    fn applied_transactions_persist_across_reopen() -> Result<(), Box<dyn Error>> {
+        // Apply a deposit, a withdrawal and a dispute, save, then reopen the file and make sure
+        // balances (including held funds) and the disputable deposit are restored.
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("engine.sqlite");
+
+        let mut store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        let transactions = vec![
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(50.0)).ok()),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Amount::new(dec!(20.0)).ok(),
+            ),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+        ];
+        for transaction in transactions {
+            transaction
+                .handle_transaction(
+                    &mut transaction_db,
+                    &mut client_db,
+                    &EngineConfig::default(),
+                )
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        store.save(&client_db, &transaction_db)?;
+        drop(store);
+
+        let store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(80.0));
+        assert_eq!(client_record.held, dec!(50.0));
+        assert_eq!(client_record.total, dec!(130.0));
+        assert!(!client_record.locked);
+        assert_eq!(transaction_db.iter_in_order().count(), 3);
+        assert_eq!(transaction_db.dispute_amounts(2).disputed.value(), dec!(50));
+
+        // The restored deposit is still under dispute, so can be charged back in the new run.
+        let chargeback = Transaction::new(TransactionType::Chargeback, 1, 2, None);
+        chargeback
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(80.0));
+        assert!(client_record.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn correction_persists_across_reopen() -> Result<(), Box<dyn Error>> {
+        // Correct a deposit of 100 to 90, save, then reopen the file and make sure the correction is restored:
+        // the deposit's lineage still includes it and a dispute in the new run holds the corrected 90.
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("engine.sqlite");
+
+        let mut store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        let transactions = vec![
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction {
+                reason: Some("keying error".to_string()),
+                ..Transaction::new(
+                    TransactionType::Correction,
+                    1,
+                    1,
+                    Amount::new(dec!(90.0)).ok(),
+                )
+            },
+        ];
+        for transaction in transactions {
+            transaction
+                .handle_transaction(
+                    &mut transaction_db,
+                    &mut client_db,
+                    &EngineConfig::default(),
+                )
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        store.save(&client_db, &transaction_db)?;
+        drop(store);
+
+        let store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        let lineage = transaction_db.lineage(1);
+        assert_eq!(lineage.len(), 2);
+        assert_eq!(lineage[1].reason.as_deref(), Some("keying error"));
+        assert_eq!(
+            transaction_db.effective_amount(1),
+            Amount::new(dec!(90)).ok()
+        );
+
+        let dispute = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        dispute
+            .handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0.0));
+        assert_eq!(client_record.held, dec!(90.0));
+        assert!(client_db.audit().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn every_client_field_persists_across_reopen() -> Result<(), Box<dyn Error>> {
+        // Save a client with pending funds, running sums and a past lock set, then make sure every field is
+        // restored as it was, so the client still passes the audit and can release its pending funds.
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("engine.sqlite");
+        let client = Client {
+            available: dec!(60.5),
+            held: dec!(10),
+            total: dec!(100.5),
+            locked: false,
+            was_ever_locked: true,
+            pending: dec!(30),
+            total_deposited: Amount::new(dec!(150))?,
+            total_withdrawn: Amount::new(dec!(20))?,
+            interest_accrued_to: Some(86_400),
+            opening_total: dec!(5),
+            interest_credited: dec!(0.5),
+            charged_back: dec!(40),
+            rounding_residues: dec!(0),
+            reclaimed_withdrawals: dec!(5),
+            ..Client::new(1)
+        };
+        let mut client_db = ClientDb::init();
+        client_db.insert_client_record(client);
+        assert!(client_db.audit().is_empty());
+        let mut store = SqliteStore::open(&db_path)?;
+        store.save(&client_db, &TransactionDb::init())?;
+        drop(store);
+
+        let mut client_db = SqliteStore::open(&db_path)?.load_client_db()?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(60.5));
+        assert_eq!(client_record.held, dec!(10));
+        assert_eq!(client_record.total, dec!(100.5));
+        assert!(!client_record.locked);
+        assert!(client_record.was_ever_locked);
+        assert_eq!(client_record.pending, dec!(30));
+        assert_eq!(client_record.total_deposited.value(), dec!(150));
+        assert_eq!(client_record.total_withdrawn.value(), dec!(20));
+        assert_eq!(client_record.interest_accrued_to, Some(86_400));
+        assert_eq!(client_record.opening_total, dec!(5));
+        assert_eq!(client_record.interest_credited, dec!(0.5));
+        assert_eq!(client_record.charged_back, dec!(40));
+        assert_eq!(client_record.reclaimed_withdrawals, dec!(5));
+        assert!(client_db.audit().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn transactions_reload_in_arrival_order_with_timestamps() -> Result<(), Box<dyn Error>> {
+        // Deposits/withdrawals arriving out of id order are reloaded in the order they arrived in, with their
+        // timestamps, so a withdrawal disputed outside the dispute window in a later run is still rejected.
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("engine.sqlite");
+
+        let mut store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        let transactions = vec![
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                9,
+                Amount::new(dec!(100.0)).ok(),
+            ),
+            Transaction {
+                timestamp: Some(1_000),
+                ..Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    2,
+                    Amount::new(dec!(10.0)).ok(),
+                )
+            },
+            Transaction::new(TransactionType::Deposit, 1, 5, Amount::new(dec!(5.0)).ok()),
+        ];
+        for transaction in transactions {
+            transaction
+                .handle_transaction(
+                    &mut transaction_db,
+                    &mut client_db,
+                    &EngineConfig::default(),
+                )
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        store.save(&client_db, &transaction_db)?;
+        drop(store);
+
+        let store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        let order: Vec<u32> = transaction_db
+            .iter_in_order()
+            .map(|transaction| transaction.transaction_id)
+            .collect();
+        assert_eq!(order, vec![9, 2, 5]);
+
+        let config = EngineConfig {
+            withdrawal_dispute_window: Some(Duration::from_secs(3600)),
+            ..EngineConfig::default()
+        };
+        let dispute = Transaction {
+            timestamp: Some(4_601),
+            ..Transaction::new(TransactionType::Dispute, 1, 2, None)
+        };
+        assert_eq!(
+            dispute.handle_transaction(&mut transaction_db, &mut client_db, &config),
+            Err(TransactionError::DisputeWindowClosed {
+                transaction_id: 2,
+                elapsed: 3_601,
+                window: Duration::from_secs(3_600),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn forgotten_transactions_deleted_on_save() -> Result<(), Box<dyn Error>> {
+        // Transactions forgotten once more than the maximum are tracked are deleted from the file when saved,
+        // along with their dispute history, rather than coming back in the next run.
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("engine.sqlite");
+
+        let mut store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let mut transaction_db = store.load_transaction_db()?;
+        let transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.0)).ok()),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(20.0)).ok()),
+            Transaction::new(TransactionType::Deposit, 1, 3, Amount::new(dec!(30.0)).ok()),
+        ];
+        for transaction in transactions {
+            transaction
+                .handle_transaction(
+                    &mut transaction_db,
+                    &mut client_db,
+                    &EngineConfig::default(),
+                )
+                .unwrap();
+            transaction_db.insert_transaction(transaction);
+        }
+        store.save(&client_db, &transaction_db)?;
+
+        let mut transaction_db = store.load_transaction_db()?;
+        transaction_db.set_max_tracked(1);
+        store.save(&client_db, &transaction_db)?;
+        drop(store);
+
+        let transaction_db = SqliteStore::open(&db_path)?.load_transaction_db()?;
+        let remaining: Vec<u32> = transaction_db
+            .iter_in_order()
+            .map(|transaction| transaction.transaction_id)
+            .collect();
+        assert_eq!(remaining, vec![3]);
+        assert_eq!(transaction_db.dispute_state(1), None);
+        assert!(transaction_db.dispute_events().next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn tables_without_added_columns_upgraded() -> Result<(), Box<dyn Error>> {
+        // A database created before every client field was stored has the missing columns added when opened,
+        // with its clients loaded as they were before: the total as the opening total and no running sums.
+        // Its transactions, stored without a timestamp or sequence, are loaded in id order.
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("engine.sqlite");
+        let conn = Connection::open(&db_path)?;
+        conn.execute_batch(
+            "CREATE TABLE clients (
+                 client    INTEGER PRIMARY KEY,
+                 available TEXT    NOT NULL,
+                 held      TEXT    NOT NULL,
+                 total     TEXT    NOT NULL,
+                 locked    INTEGER NOT NULL
+             );
+             INSERT INTO clients VALUES (1, '7.5', '2.5', '10', 0);
+             CREATE TABLE transactions (
+                 tx     INTEGER PRIMARY KEY,
+                 type   TEXT    NOT NULL,
+                 client INTEGER NOT NULL,
+                 amount TEXT
+             );
+             INSERT INTO transactions VALUES (4, 'deposit', 1, '7.5'), (3, 'deposit', 1, '2.5');",
+        )?;
+        drop(conn);
+
+        let store = SqliteStore::open(&db_path)?;
+        let mut client_db = store.load_client_db()?;
+        let transaction_db = store.load_transaction_db()?;
+        let order: Vec<u32> = transaction_db
+            .iter_in_order()
+            .map(|transaction| transaction.transaction_id)
+            .collect();
+        assert_eq!(order, vec![3, 4]);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.total, dec!(10));
+        assert_eq!(client_record.opening_total, dec!(10));
+        assert_eq!(client_record.pending, dec!(0));
+        assert_eq!(client_record.total_deposited, Amount::ZERO);
+        assert!(client_db.audit().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+// ------------------------------------------------------------------------------------------------
+// ----------------------------------------- STORAGE TRAIT ----------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Key-value store backing the records of the Client and Transaction Databases. Both databases are generic
+// over it, defaulting to the in-memory `Map`, so that an integrator can back the engine with a persistent
+// store (e.g. sled or SQLite) by implementing this trait, without any change to how transactions are applied.
+pub trait Storage<K, V> {
+    // Reference to the record stored under the key, if any.
+    fn get(&self, key: &K) -> Option<&V>;
+
+    // Mutable reference to the record stored under the key, if any.
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+
+    // Mutable reference to the record stored under the key, first storing the default record if there is none.
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V;
+
+    // Store the record under the key, returning the record it replaced, if any.
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+
+    // Remove the record stored under the key, returning it if there was one.
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    // Iterate over every key and record, in whatever order the store keeps them.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_>;
+
+    // Number of records stored.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------------- IN-MEMORY STORAGE ----------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Hash based store, the default unless the `deterministic` feature is enabled.
+impl<K: Hash + Eq, V> Storage<K, V> for HashMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V {
+        self.entry(key).or_insert_with(default)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        HashMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        HashMap::remove(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+// Ordered store, the default with the `deterministic` feature.
+impl<K: Ord, V> Storage<K, V> for BTreeMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        BTreeMap::get_mut(self, key)
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V {
+        self.entry(key).or_insert_with(default)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_> {
+        Box::new(BTreeMap::iter(self))
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli_args::transaction_reader_builder;
+    use crate::client::{Client, ClientDb};
+    use crate::config::EngineConfig;
+    use crate::transaction::{self, Transaction, TransactionDb};
+    use rust_decimal_macros::dec;
+    use std::cell::RefCell;
+    use std::fmt::Debug;
+    use std::rc::Rc;
+
+    // Storage recording every call made to it, keeping its records in a hash map.
+    struct RecordingStorage<K, V> {
+        records: HashMap<K, V>,
+        calls: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl<K, V> RecordingStorage<K, V> {
+        fn new(calls: &Rc<RefCell<Vec<String>>>) -> Self {
+            RecordingStorage {
+                records: HashMap::new(),
+                calls: Rc::clone(calls),
+            }
+        }
+
+        fn record(&self, call: String) {
+            self.calls.borrow_mut().push(call);
+        }
+    }
+
+    impl<K: Hash + Eq + Debug, V> Storage<K, V> for RecordingStorage<K, V> {
+        fn get(&self, key: &K) -> Option<&V> {
+            self.record(format!("get {:?}", key));
+            self.records.get(key)
+        }
+
+        fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            self.record(format!("get_mut {:?}", key));
+            self.records.get_mut(key)
+        }
+
+        fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V {
+            self.record(format!("get_or_insert_with {:?}", key));
+            self.records.entry(key).or_insert_with(default)
+        }
+
+        fn insert(&mut self, key: K, value: V) -> Option<V> {
+            self.record(format!("insert {:?}", key));
+            self.records.insert(key, value)
+        }
+
+        fn remove(&mut self, key: &K) -> Option<V> {
+            self.record(format!("remove {:?}", key));
+            self.records.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_> {
+            self.record("iter".to_string());
+            Box::new(self.records.iter())
+        }
+
+        fn len(&self) -> usize {
+            self.record("len".to_string());
+            self.records.len()
+        }
+    }
+
+    #[test]
+    fn client_db_delegates_to_storage() {
+        // Make sure the Client Database stores, looks up and iterates its records through the storage it was given.
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut client_db = ClientDb::with_storage(RecordingStorage::new(&calls));
+        client_db.insert_client_record(Client::new(1));
+        assert!(client_db.get_client_record(&1).is_some());
+        client_db.get_or_create_client_record(2);
+        assert_eq!(client_db.client_records().count(), 2);
+        assert_eq!(
+            *calls.borrow(),
+            ["insert 1", "get_mut 1", "get_or_insert_with 2", "iter"]
+        );
+    }
+
+    #[test]
+    fn engine_applies_transactions_through_storage() -> Result<(), Box<dyn std::error::Error>> {
+        // Transactions applied to databases backed by custom storage give the same balances, with the deposit
+        // stored in and the dispute looked up from the transaction storage.
+        let client_calls = Rc::new(RefCell::new(Vec::new()));
+        let transaction_calls = Rc::new(RefCell::new(Vec::new()));
+        let mut client_db = ClientDb::with_storage(RecordingStorage::new(&client_calls));
+        let mut transaction_db = TransactionDb::with_storage(
+            RecordingStorage::<u32, Transaction>::new(&transaction_calls),
+        );
+        let input = "type, client, tx, amount
+                     deposit, 1, 1, 10.0
+                     dispute, 1, 1,";
+        transaction::apply_transactions(
+            vec![transaction_reader_builder().from_reader(input.as_bytes())],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client = client_db.get_client_record(&1).unwrap();
+        assert_eq!((client.available(), client.held()), (dec!(0), dec!(10)));
+        assert!(client_calls
+            .borrow()
+            .contains(&"get_or_insert_with 1".to_string()));
+        assert!(transaction_calls.borrow().contains(&"insert 1".to_string()));
+        assert!(transaction_calls.borrow().contains(&"get 1".to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{self, ErrorKind, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// Size of each chunk read from the underlying input by the reader thread.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// Chunks read ahead of the csv reader, bounding the memory used when the input is faster than processing.
+const CHUNKS_AHEAD: usize = 4;
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------------ TIMEOUT READER STRUCT -------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+// Reader over a streamed input (e.g. stdin or a fetched URL) that fails with a TimedOut error if no data
+// arrives within the timeout, instead of blocking forever on a stalled producer.
+// The input is read on a watchdog thread which hands chunks over a channel, so a read that never returns
+// only blocks that thread. The thread is left behind if the timeout fires, and ends with the process.
+pub struct TimeoutReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    // Unread part of the last chunk received.
+    chunk: Vec<u8>,
+    position: usize,
+    timeout: Duration,
+    // Name of the input, used to explain a timeout.
+    source: String,
+    finished: bool,
+}
+
+// ------------------------------------------------------------------------------------------------
+// ------------------------------ TIMEOUT READER ASSOCIATED FUNCTIONS -----------------------------
+// ------------------------------------------------------------------------------------------------
+
+impl TimeoutReader {
+    pub fn new<R: Read + Send + 'static>(mut inner: R, timeout: Duration, source: &str) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(CHUNKS_AHEAD);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let read = match inner.read(&mut chunk) {
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
+            chunk.truncate(read);
+            // An empty chunk marks the end of the input. Stop once it is sent or the reader is dropped.
+            if sender.send(Ok(chunk)).is_err() || read == 0 {
+                return;
+            }
+        });
+        TimeoutReader {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            timeout,
+            source: source.to_string(),
+            finished: false,
+        }
+    }
+}
+
+impl Read for TimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            match self.chunks.recv_timeout(self.timeout) {
+                Ok(Ok(chunk)) => {
+                    self.finished = chunk.is_empty();
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!(
+                            "no data received from {} within {}s",
+                            self.source,
+                            self.timeout.as_secs_f64()
+                        ),
+                    ))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.finished = true;
+                    return Ok(0);
+                }
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// --------------------------------------- UNIT TESTS ---------------------------------------------
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    // In-memory input that waits before handing over each of its chunks, like a slow upstream producer.
+    struct SlowReader {
+        chunks: Vec<&'static [u8]>,
+        delay: Duration,
+    }
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            if self.chunks.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn stalled_input_times_out() {
+        // An input that takes longer than the timeout to produce data fails with a TimedOut error naming
+        // the input, without waiting for the input itself.
+        let slow = SlowReader {
+            chunks: vec![b"type,client,tx,amount\n"],
+            delay: Duration::from_secs(5),
+        };
+        let mut reader = TimeoutReader::new(slow, Duration::from_millis(50), "stdin");
+        let started = Instant::now();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "no data received from stdin within 0.05s");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn slow_input_within_timeout_read_fully() -> io::Result<()> {
+        // An input producing each chunk within the timeout is read in full, however long it takes overall.
+        let slow = SlowReader {
+            chunks: vec![b"type,client,tx,amount\n", b"deposit,1,1,10.0\n"],
+            delay: Duration::from_millis(10),
+        };
+        let mut reader = TimeoutReader::new(slow, Duration::from_secs(5), "stdin");
+        let mut read = String::new();
+        reader.read_to_string(&mut read)?;
+        assert_eq!(read, "type,client,tx,amount\ndeposit,1,1,10.0\n");
+        Ok(())
+    }
+}
@@ -1,46 +1,1361 @@
-use csv::Reader;
+use csv::{Reader, StringRecord};
+use rust_decimal::Decimal;
+use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
-use std::{collections::HashMap, error::Error, fs::File};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{error::Error, fmt, io, str::FromStr};
 
+use crate::alert::{Alert, AlertKind, AlertSink};
+use crate::amount::{
+    excess_digits, is_subprecision, round_deserialise, rounding_residue, Amount, AmountError,
+    AMOUNT_DECIMAL_PLACES,
+};
 use crate::client;
+use crate::collections::Map;
+use crate::config::{EngineConfig, RoundingMode};
+use crate::error::TransactionError;
+use crate::monitor::BurstMonitor;
+use crate::storage::Storage;
 
 // ------------------------------------------------------------------------------------------------
 // --------------------------------- APPLY TRANSACTIONS FUNCION -----------------------------------
 // ------------------------------------------------------------------------------------------------
 
-// Iterates over rows of transactions from csv reader.
+// Number of parsed transactions buffered per file for each shard before the parser waits.
+const SHARD_CHANNEL_CAPACITY: usize = 1_024;
+
+// Iterates over rows of transactions from each csv reader in turn.
 // Handles each transaction with respect to the Client and Transaction Databases.
-pub fn apply_transactions(
-    mut rdr: Reader<File>,
-    transaction_db: &mut TransactionDb,
-    client_db: &mut client::ClientDb,
+// If an explanation writer is supplied, the reason for every rejected transaction is written to it
+// alongside the line number of the row in the csv.
+// If a maximum runtime is configured, processing stops once it is exceeded leaving the remaining rows unapplied.
+// If future-dated transactions are deferred, a transaction timestamped after the processing clock is held
+// until a `clock` row advances the clock to (or past) its timestamp. Held transactions are applied in
+// timestamp order, then file order, and any still held at the end of the run are left unapplied.
+// If only some transaction types are configured to be applied, rows of other types are skipped entirely.
+// If a burst limit is configured, clients making too many timestamped transactions within the window are
+// alerted on in the processing stats. Transactions are still applied as normal.
+// Rows that cannot be parsed are skipped and recorded in the processing stats, unless the run is strict in
+// which case the first aborts processing. Failing to read the input always aborts processing.
+pub fn apply_transactions<R: io::Read>(
+    readers: Vec<Reader<R>>,
+    transaction_db: &mut TransactionDb<impl Storage<u32, Transaction>>,
+    client_db: &mut client::ClientDb<impl Storage<u16, client::Client>>,
+    config: &EngineConfig,
+    explain: Option<&mut dyn io::Write>,
+) -> Result<ProcessingStats, Box<dyn Error>> {
+    let rows = readers.into_iter().flat_map(|rdr| read_rows(rdr, config));
+    apply_rows(rows, transaction_db, client_db, config, explain)
+}
+
+// Applies transactions already in memory, e.g. when embedding the engine, exactly as `apply_transactions`
+// applies the rows of a csv. Rejections are reported against the position of the transaction in the
+// iterator, starting from 1, in place of a line number.
+pub fn apply_transactions_iter<I: IntoIterator<Item = Transaction>>(
+    transactions: I,
+    transaction_db: &mut TransactionDb<impl Storage<u32, Transaction>>,
+    client_db: &mut client::ClientDb<impl Storage<u16, client::Client>>,
+    config: &EngineConfig,
+    explain: Option<&mut dyn io::Write>,
+) -> Result<ProcessingStats, Box<dyn Error>> {
+    let rows = transactions
+        .into_iter()
+        .zip(1..)
+        .map(|(transaction, position)| Ok(Row::Parsed(transaction, position)));
+    apply_rows(rows, transaction_db, client_db, config, explain)
+}
+
+// A row of the input, either parsed into a transaction along with its line number or skipped as malformed.
+enum Row {
+    Parsed(Transaction, u64),
+    Malformed(Box<dyn Error>),
+}
+
+// Parses the rows of a csv reader. Rows that cannot be read or parsed are malformed, unless the run is
+// strict or the input itself could not be read, in which case they are errors aborting processing.
+fn read_rows<'a, R: io::Read + 'a>(
+    mut rdr: Reader<R>,
+    config: &'a EngineConfig,
+) -> Box<dyn Iterator<Item = Result<Row, Box<dyn Error>>> + 'a> {
+    let headers = match rdr.headers() {
+        Ok(headers) => RowHeaders::new(headers.clone()),
+        Err(err) => return Box::new(std::iter::once(Err(err.into()))),
+    };
+    if config.fast_parse {
+        return read_rows_reusing_record(rdr, headers, config);
+    }
+    Box::new(rdr.into_records().map(move |row| {
+        let record = match row {
+            Ok(record) => record,
+            Err(err) if config.strict || err.is_io_error() => return Err(err.into()),
+            Err(err) => return Ok(Row::Malformed(err.into())),
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        match headers.parse_record(record, line, config) {
+            Ok(transaction) => Ok(Row::Parsed(transaction, line)),
+            Err(err) if config.strict => Err(err),
+            Err(err) => Ok(Row::Malformed(err)),
+        }
+    }))
+}
+
+// Parses the rows of a csv reader as `read_rows` does, but reads every row into the same record and parses
+// its fields by hand, so no record is allocated per row.
+fn read_rows_reusing_record<'a, R: io::Read + 'a>(
+    mut rdr: Reader<R>,
+    headers: RowHeaders,
+    config: &'a EngineConfig,
+) -> Box<dyn Iterator<Item = Result<Row, Box<dyn Error>>> + 'a> {
+    let mut record = StringRecord::new();
+    Box::new(std::iter::from_fn(move || {
+        match rdr.read_record(&mut record) {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(err) if config.strict || err.is_io_error() => return Some(Err(err.into())),
+            Err(err) => return Some(Ok(Row::Malformed(err.into()))),
+        }
+        let line = record.position().map_or(0, |position| position.line());
+        Some(match headers.parse_fields(&record, line, config) {
+            Ok(transaction) => Ok(Row::Parsed(transaction, line)),
+            Err(err) if config.strict => Err(err),
+            Err(err) => Ok(Row::Malformed(err)),
+        })
+    }))
+}
+
+// Core loop applying rows in order, shared by the csv and in-memory entry points.
+fn apply_rows<I: IntoIterator<Item = Result<Row, Box<dyn Error>>>>(
+    rows: I,
+    transaction_db: &mut TransactionDb<impl Storage<u32, Transaction>>,
+    client_db: &mut client::ClientDb<impl Storage<u16, client::Client>>,
+    config: &EngineConfig,
+    mut explain: Option<&mut dyn io::Write>,
+) -> Result<ProcessingStats, Box<dyn Error>> {
+    let deadline = config
+        .max_runtime
+        .map(|max_runtime| Instant::now() + max_runtime);
+    let mut clock = config.processing_clock.unwrap_or_else(unix_now);
+    let mut deferred: BTreeMap<(u64, usize), (Transaction, u64)> = BTreeMap::new();
+    let mut last_seq: Option<u64> = None;
+    let mut burst_monitor = config.burst_limit.map(BurstMonitor::new);
+    let mut stats = ProcessingStats::default();
+    for row in rows {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            stats.truncated = true;
+            stats.deferred = deferred.len();
+            finish_run(client_db, config, &mut stats);
+            return Ok(stats);
+        }
+        stats.rows_read += 1;
+        let (transaction, line) = match row? {
+            Row::Parsed(transaction, line) => (transaction, line),
+            Row::Malformed(err) => {
+                skip_malformed_row(err, &mut explain, &mut stats)?;
+                continue;
+            }
+        };
+        if config.require_contiguous_seq {
+            last_seq = Some(check_seq(last_seq, transaction.seq, line)?);
+        }
+        // Clock rows only advance the processing clock, releasing any transactions now effective.
+        if transaction.transaction_type == TransactionType::Clock {
+            clock = clock.max(transaction.timestamp.unwrap_or(clock));
+            let still_deferred = deferred.split_off(&(clock.saturating_add(1), 0));
+            for (_, (transaction, line)) in std::mem::replace(&mut deferred, still_deferred) {
+                apply_row(
+                    transaction,
+                    line,
+                    transaction_db,
+                    client_db,
+                    config,
+                    &mut explain,
+                    &mut stats,
+                )?;
+            }
+            continue;
+        }
+        // Rows of types excluded from the run are skipped without being recorded.
+        if !config.applies_type(transaction.transaction_type) {
+            continue;
+        }
+        if let (Some(monitor), Some(timestamp)) = (burst_monitor.as_mut(), transaction.timestamp) {
+            stats
+                .alerts
+                .extend(monitor.record(transaction.client_id, timestamp, line));
+        }
+        if config.defer_future_dated
+            && transaction
+                .timestamp
+                .is_some_and(|timestamp| timestamp > clock)
+        {
+            let effective_at = transaction.timestamp.unwrap_or(clock);
+            deferred.insert((effective_at, stats.rows_read), (transaction, line));
+            continue;
+        }
+        apply_row(
+            transaction,
+            line,
+            transaction_db,
+            client_db,
+            config,
+            &mut explain,
+            &mut stats,
+        )?;
+    }
+    stats.deferred = deferred.len();
+    finish_run(client_db, config, &mut stats);
+    Ok(stats)
+}
+
+// Credits the rounding residues of the run to the suspense account, if one is kept, then summarises the
+// clients the run finished with.
+fn finish_run(
+    client_db: &mut client::ClientDb<impl Storage<u16, client::Client>>,
+    config: &EngineConfig,
+    stats: &mut ProcessingStats,
+) {
+    if let Some(suspense_account) = config.suspense_account {
+        client_db.post_to_suspense(suspense_account, stats.rounding_residue);
+    }
+    stats.summary.record_clients(client_db);
+}
+
+// Applies a single parsed row to the Client and Transaction Databases, explaining rejections and
+// recording them, any warnings and (when configured) the ledger entry of an applied row in the processing stats.
+fn apply_row(
+    transaction: Transaction,
+    line: u64,
+    transaction_db: &mut TransactionDb<impl Storage<u32, Transaction>>,
+    client_db: &mut client::ClientDb<impl Storage<u16, client::Client>>,
+    config: &EngineConfig,
+    explain: &mut Option<&mut dyn io::Write>,
+    stats: &mut ProcessingStats,
 ) -> Result<(), Box<dyn Error>> {
-    for row in rdr.deserialize() {
-        let transaction: Transaction = row?;
-        transaction.handle_transaction(transaction_db, client_db);
-        transaction_db.insert_transaction(transaction) // Only adds transaction if of type deposit/withdrawal.
+    let already_locked = transaction.transaction_type == TransactionType::Chargeback
+        && client_db
+            .get_client_record(&transaction.client_id)
+            .is_some_and(|client| client.locked);
+    let reactivated = config.warn_reactivated
+        && matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        )
+        && client_db
+            .get_client_record(&transaction.client_id)
+            .is_some_and(|client| client.was_ever_locked && !client.locked);
+    let result = transaction.handle_transaction(transaction_db, client_db, config);
+    let applied = result.is_ok();
+    if applied {
+        stats.summary.record_applied(transaction.transaction_type);
+    }
+    // Funds lost rounding a deposit are owed to the suspense account, while those lost rounding a
+    // withdrawal were paid out of it.
+    if result.is_ok() {
+        match transaction.transaction_type {
+            TransactionType::Deposit => stats.rounding_residue += transaction.rounding_residue,
+            TransactionType::Withdrawal => stats.rounding_residue -= transaction.rounding_residue,
+            _ => {}
+        }
+    }
+    if config.record_ledger && result.is_ok() {
+        if let Some(client) = client_db.get_client_record(&transaction.client_id) {
+            stats
+                .ledger
+                .push(LedgerEntry::new(&transaction, transaction_db, client));
+        }
+    }
+    match result {
+        // Rejections configured to be ignored leave the transaction a silent no-op.
+        Err(err) if config.ignores(&err) => {}
+        Err(err) => {
+            if let Some(writer) = explain.as_mut() {
+                writeln!(writer, "line {}: {}", line, err)?;
+            }
+            stats
+                .rejections
+                .push(Rejection::new(&transaction, line, err));
+        }
+        // Chargebacks on an already locked account are only applied when configured, so call them out.
+        Ok(()) if already_locked => {
+            if let Some(writer) = explain.as_mut() {
+                writeln!(
+                    writer,
+                    "line {}: chargeback of tx {} applied to already locked client {}",
+                    line, transaction.transaction_id, transaction.client_id
+                )?;
+            }
+        }
+        Ok(()) if reactivated => stats.alerts.push(Alert {
+            client_id: transaction.client_id,
+            line,
+            kind: AlertKind::Reactivated {
+                transaction_type: transaction.transaction_type,
+            },
+        }),
+        Ok(()) => {}
+    }
+    // Only applied transactions are stored, so a rejected deposit/withdrawal can never be disputed or charged
+    // back, and a rejected claim is not counted as an event against the transaction it references.
+    if applied {
+        transaction_db.insert_transaction(transaction);
+    }
+    Ok(())
+}
+
+// Records a row that could not be parsed, and so was skipped, explaining why if requested.
+fn skip_malformed_row(
+    err: Box<dyn Error>,
+    explain: &mut Option<&mut dyn io::Write>,
+    stats: &mut ProcessingStats,
+) -> io::Result<()> {
+    if let Some(writer) = explain.as_mut() {
+        writeln!(writer, "skipped malformed row: {}", err)?;
     }
+    stats.malformed_rows.push(err.to_string());
     Ok(())
 }
 
+// Current wall clock time in seconds since the unix epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+// Applies the transactions from several csv readers concurrently, sharding clients across worker threads.
+// Ordering semantics: every client's transactions are applied in the order they appear when the files are
+// concatenated in the order given, i.e. all of a client's rows from the first file (in row order), then
+// all of its rows from the second file, and so on. Each file is parsed on its own thread and every row is
+// sent to the worker owning its client, which drains the files one after another. The result therefore
+// matches `apply_transactions` on the same readers. A dispute/resolve/chargeback naming a different client
+// to the tx it references is rejected either way, though here as an unknown tx, as a tx is only visible to
+// the worker owning its client. A deposit/withdrawal reusing the tx id of an earlier applied transaction is
+// rejected as a duplicate whichever worker owns either client, see `TxIdClaims`.
+// Explanations, sequence checks and the maximum runtime are not supported in this mode. Rejections are
+// still recorded, grouped by shard rather than in row order.
+pub fn apply_transactions_sharded<R: io::Read + Send>(
+    readers: Vec<Reader<R>>,
+    shards: usize,
+    transaction_db: &mut TransactionDb,
+    client_db: &mut client::ClientDb,
+    config: &EngineConfig,
+) -> Result<ProcessingStats, Box<dyn Error>> {
+    let shards = shards.max(1);
+    let tx_ids = &TxIdClaims::new(transaction_db);
+    // senders[file][shard] feeds receivers[shard][file].
+    let mut senders: Vec<Vec<SyncSender<(Transaction, u64)>>> = Vec::new();
+    let mut receivers: Vec<Vec<Receiver<(Transaction, u64)>>> =
+        (0..shards).map(|_| Vec::new()).collect();
+    for _ in &readers {
+        let mut file_senders = Vec::new();
+        for shard_receivers in receivers.iter_mut() {
+            let (sender, receiver) = mpsc::sync_channel(SHARD_CHANNEL_CAPACITY);
+            file_senders.push(sender);
+            shard_receivers.push(receiver);
+        }
+        senders.push(file_senders);
+    }
+    let client_shards = std::mem::replace(client_db, client::ClientDb::init()).into_shards(shards);
+    let transaction_shards =
+        std::mem::replace(transaction_db, TransactionDb::init()).into_shards(shards);
+
+    let (parsed, shard_dbs) = thread::scope(|scope| {
+        let workers: Vec<_> = receivers
+            .into_iter()
+            .zip(client_shards.into_iter().zip(transaction_shards))
+            .map(|(file_receivers, (mut client_db, mut transaction_db))| {
+                scope.spawn(move || {
+                    let mut rejections = Vec::new();
+                    let mut summary = ProcessingSummary::default();
+                    for (file, receiver) in file_receivers.into_iter().enumerate() {
+                        for (transaction, line) in receiver {
+                            let claims_id = matches!(
+                                transaction.transaction_type,
+                                TransactionType::Deposit | TransactionType::Withdrawal
+                            );
+                            let position = (file, line);
+                            let result = match claims_id
+                                && tx_ids.is_taken(transaction.transaction_id, position)
+                            {
+                                true => Err(TransactionError::DuplicateTransactionId {
+                                    transaction_type: transaction.transaction_type,
+                                    transaction_id: transaction.transaction_id,
+                                }),
+                                false => transaction.handle_transaction(
+                                    &mut transaction_db,
+                                    &mut client_db,
+                                    config,
+                                ),
+                            };
+                            if claims_id {
+                                tx_ids.settle(transaction.transaction_id, position, result.is_ok());
+                            }
+                            // Rejections are recorded but not explained in this mode.
+                            match result {
+                                Ok(()) => {
+                                    summary.record_applied(transaction.transaction_type);
+                                    transaction_db.insert_transaction(transaction);
+                                }
+                                Err(err) if config.ignores(&err) => {}
+                                Err(err) => {
+                                    rejections.push(Rejection::new(&transaction, line, err))
+                                }
+                            }
+                        }
+                    }
+                    (client_db, transaction_db, rejections, summary)
+                })
+            })
+            .collect();
+        let parsers: Vec<_> = readers
+            .into_iter()
+            .zip(senders)
+            .enumerate()
+            .map(|(file, (rdr, file_senders))| {
+                scope.spawn(move || parse_into_shards(rdr, file, &file_senders, tx_ids, config))
+            })
+            .collect();
+
+        // Join every parser before the workers so each worker's channels are closed.
+        let parsed: Result<Vec<(usize, Vec<String>)>, String> = parsers
+            .into_iter()
+            .map(|parser| parser.join().expect("transaction parser thread panicked"))
+            .collect();
+        let shard_dbs: Vec<(
+            client::ClientDb,
+            TransactionDb,
+            Vec<Rejection>,
+            ProcessingSummary,
+        )> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("transaction worker thread panicked"))
+            .collect();
+        (parsed, shard_dbs)
+    });
+
+    let mut stats = ProcessingStats::default();
+    let mut collisions = Vec::new();
+    for (shard_client_db, shard_transaction_db, shard_rejections, shard_summary) in shard_dbs {
+        client_db.merge(shard_client_db);
+        collisions.extend(transaction_db.merge(shard_transaction_db));
+        stats.rejections.extend(shard_rejections);
+        stats.summary.merge_counts(&shard_summary);
+    }
+    if !collisions.is_empty() {
+        return Err(format!("tx ids {:?} were stored by more than one shard", collisions).into());
+    }
+    for (rows_read, malformed_rows) in parsed? {
+        stats.rows_read += rows_read;
+        stats.malformed_rows.extend(malformed_rows);
+    }
+    stats.summary.record_clients(client_db);
+    Ok(stats)
+}
+
+// Parses every row of the reader at the given index, sending each transaction and its line number to the shard
+// owning its client. Each deposit/withdrawal is registered as claiming its tx id before it is sent.
+// Returns the number of rows read and the reasons any malformed rows were skipped, as in `apply_transactions`.
+// Errors are returned as strings so they can be passed back from the parsing thread.
+fn parse_into_shards<R: io::Read>(
+    mut rdr: Reader<R>,
+    file: usize,
+    senders: &[SyncSender<(Transaction, u64)>],
+    tx_ids: &TxIdClaims,
+    config: &EngineConfig,
+) -> Result<(usize, Vec<String>), String> {
+    let headers = RowHeaders::new(rdr.headers().map_err(|err| err.to_string())?.clone());
+    let mut rows_read = 0;
+    let mut malformed_rows = Vec::new();
+    let mut record = StringRecord::new();
+    loop {
+        match rdr.read_record(&mut record) {
+            Ok(true) => rows_read += 1,
+            Ok(false) => break,
+            Err(err) if config.strict || err.is_io_error() => return Err(err.to_string()),
+            Err(err) => {
+                rows_read += 1;
+                malformed_rows.push(err.to_string());
+                continue;
+            }
+        }
+        let line = record.position().map_or(0, |position| position.line());
+        let parsed = match config.fast_parse {
+            true => headers.parse_fields(&record, line, config),
+            false => headers.parse_record(record.clone(), line, config),
+        };
+        let transaction = match parsed {
+            Ok(transaction) => transaction,
+            Err(err) if config.strict => return Err(err.to_string()),
+            Err(err) => {
+                malformed_rows.push(err.to_string());
+                continue;
+            }
+        };
+        // Clock rows only matter when deferring future-dated transactions, which is not supported here.
+        if transaction.transaction_type == TransactionType::Clock
+            || !config.applies_type(transaction.transaction_type)
+        {
+            continue;
+        }
+        if matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            tx_ids.register(transaction.transaction_id, (file, line));
+        }
+        let shard = usize::from(transaction.client_id) % senders.len();
+        senders[shard]
+            .send((transaction, line))
+            .map_err(|err| err.to_string())?;
+    }
+    Ok((rows_read, malformed_rows))
+}
+
+// Position of a row in the inputs of a sharded run: the index of its file and its line within the file.
+type RowPosition = (usize, u64);
+
+// Whether a deposit/withdrawal row claiming a tx id has been applied, once its worker has handled it.
+#[derive(Clone, Copy, PartialEq)]
+enum ClaimState {
+    Pending,
+    Applied,
+    Rejected,
+}
+
+// Deposit/withdrawal tx ids shared by the workers of a sharded run, so that a tx id reused by a client owned
+// by another worker is still rejected as a duplicate. Parsers register each deposit/withdrawal row as pending
+// before sending it on, and workers settle each row once it is applied or rejected. As in
+// `apply_transactions`, a tx id is only taken by an earlier row that was applied, so a worker first waits for
+// any earlier rows with the same tx id to be settled. Those rows are always registered by then, as a worker
+// only reaches the rows of a file once every earlier file has been parsed.
+// Ids of transactions forgotten to stay within a maximum number tracked remain taken for the run.
+struct TxIdClaims {
+    claims: Mutex<HashMap<u32, Vec<(RowPosition, ClaimState)>>>,
+    settled: Condvar,
+}
+
+impl TxIdClaims {
+    // Claims of the transactions already stored, which precede every row.
+    fn new(transaction_db: &TransactionDb) -> Self {
+        let claims = transaction_db
+            .iter_in_order()
+            .map(|transaction| {
+                (
+                    transaction.transaction_id,
+                    vec![((0, 0), ClaimState::Applied)],
+                )
+            })
+            .collect();
+        TxIdClaims {
+            claims: Mutex::new(claims),
+            settled: Condvar::new(),
+        }
+    }
+
+    // Register the row at the position as claiming the tx id, pending its worker.
+    fn register(&self, transaction_id: u32, position: RowPosition) {
+        self.claims
+            .lock()
+            .expect("tx id claims lock poisoned")
+            .entry(transaction_id)
+            .or_default()
+            .push((position, ClaimState::Pending));
+    }
+
+    // True if a row before the position with the same tx id was applied, waiting until every such row is settled.
+    fn is_taken(&self, transaction_id: u32, position: RowPosition) -> bool {
+        let mut claims = self.claims.lock().expect("tx id claims lock poisoned");
+        loop {
+            let earlier: Vec<ClaimState> = claims
+                .get(&transaction_id)
+                .into_iter()
+                .flatten()
+                .filter(|(claimed_at, _)| *claimed_at < position)
+                .map(|(_, state)| *state)
+                .collect();
+            if earlier.contains(&ClaimState::Applied) {
+                return true;
+            }
+            if !earlier.contains(&ClaimState::Pending) {
+                return false;
+            }
+            claims = self
+                .settled
+                .wait(claims)
+                .expect("tx id claims lock poisoned");
+        }
+    }
+
+    // Settle the claim of the row at the position, waking any workers waiting on it.
+    fn settle(&self, transaction_id: u32, position: RowPosition, applied: bool) {
+        let mut claims = self.claims.lock().expect("tx id claims lock poisoned");
+        let claim = claims
+            .get_mut(&transaction_id)
+            .into_iter()
+            .flatten()
+            .find(|(claimed_at, _)| *claimed_at == position);
+        if let Some((_, state)) = claim {
+            *state = match applied {
+                true => ClaimState::Applied,
+                false => ClaimState::Rejected,
+            };
+        }
+        self.settled.notify_all();
+    }
+}
+
+// Headers of a transaction csv, along with the position of the id columns that are validated
+// before a row is deserialised, and of the amount column checked for sub-precision amounts.
+// The remaining columns are only used when parsing fields by hand.
+struct RowHeaders {
+    headers: StringRecord,
+    type_column: Option<usize>,
+    client_column: Option<usize>,
+    tx_column: Option<usize>,
+    amount_column: Option<usize>,
+    seq_column: Option<usize>,
+    timestamp_column: Option<usize>,
+    reason_column: Option<usize>,
+    // True if any column is named more than once, which deserialising reports as an error.
+    duplicate_columns: bool,
+}
+
+impl RowHeaders {
+    fn new(headers: StringRecord) -> Self {
+        let column = |name: &str| headers.iter().position(|header| header == name);
+        let unique_headers: HashSet<&str> = headers.iter().collect();
+        RowHeaders {
+            type_column: column("type"),
+            client_column: column("client"),
+            tx_column: column("tx"),
+            amount_column: column("amount"),
+            seq_column: column("seq"),
+            timestamp_column: column("timestamp"),
+            reason_column: column("reason"),
+            duplicate_columns: unique_headers.len() < headers.len(),
+            headers,
+        }
+    }
+
+    // Parse a row by hand, reading each field straight from the record rather than deserialising it.
+    // A row that cannot be read this way is deserialised instead, so malformed rows are reported identically.
+    fn parse_fields(
+        &self,
+        record: &StringRecord,
+        line: u64,
+        config: &EngineConfig,
+    ) -> Result<Transaction, Box<dyn Error>> {
+        let fields = (record.len() <= self.headers.len() || config.ignore_extra_fields)
+            .then(|| self.read_fields(record))
+            .flatten();
+        match fields {
+            Some(mut transaction) => {
+                let raw_amount = self.amount_column.and_then(|column| record.get(column));
+                // As when deserialising, ids are checked before the amount is read again from the raw field.
+                check_amount(&mut transaction, raw_amount, config);
+                Ok(transaction)
+            }
+            None => self.parse_record(record.clone(), line, config),
+        }
+    }
+
+    // Read the fields of a row, or None if any is missing or does not parse as its type.
+    fn read_fields(&self, record: &StringRecord) -> Option<Transaction> {
+        if self.duplicate_columns {
+            return None;
+        }
+        let field = |column: Option<usize>| column.and_then(|column| record.get(column));
+        // As when deserialising, an empty optional field has no value, but one that does not parse is an error.
+        let optional = |column: Option<usize>| match field(column) {
+            None | Some("") => Some(None),
+            Some(value) => value.parse().ok().map(Some),
+        };
+        Some(Transaction {
+            transaction_type: field(self.type_column)?.parse().ok()?,
+            client_id: field(self.client_column)?.parse().ok()?,
+            transaction_id: field(self.tx_column)?.parse().ok()?,
+            amount: Amount::parse(field(self.amount_column)?).ok(),
+            seq: optional(self.seq_column)?,
+            timestamp: optional(self.timestamp_column)?,
+            reason: field(self.reason_column)
+                .filter(|reason| !reason.is_empty())
+                .map(str::to_string),
+            subprecision: false,
+            invalid_amount: None,
+            rounding_residue: Decimal::ZERO,
+        })
+    }
+
+    // Deserialise a row into a transaction, reporting malformed rows with their line number.
+    fn parse_record(
+        &self,
+        mut record: StringRecord,
+        line: u64,
+        config: &EngineConfig,
+    ) -> Result<Transaction, Box<dyn Error>> {
+        // The reader is flexible so that rows with trailing columns reach here and can be reported clearly.
+        if record.len() > self.headers.len() {
+            if !config.ignore_extra_fields {
+                return Err(format!("too many fields on line {}", line).into());
+            }
+            record.truncate(self.headers.len());
+        }
+        // Report empty id columns explicitly rather than as an opaque parse error.
+        if is_empty_field(&record, self.client_column) {
+            return Err(format!("missing client id on line {}", line).into());
+        }
+        if is_empty_field(&record, self.tx_column) {
+            return Err(format!("missing tx id on line {}", line).into());
+        }
+        let mut transaction: Transaction = record.deserialize(Some(&self.headers))?;
+        let raw_amount = self.amount_column.and_then(|column| record.get(column));
+        check_amount(&mut transaction, raw_amount, config);
+        Ok(transaction)
+    }
+}
+
+// Checks the amount of a parsed transaction against the raw amount field, however the row was parsed.
+fn check_amount(transaction: &mut Transaction, raw_amount: Option<&str>, config: &EngineConfig) {
+    // Amounts are deserialised to 4.d.p rounding halves up, so are read again from the raw field under any
+    // other mode or precision.
+    let precision = config.amount_precision();
+    if (!matches!(config.rounding, RoundingMode::HalfUp) || precision != AMOUNT_DECIMAL_PLACES)
+        && transaction.amount.is_some()
+    {
+        transaction.amount = raw_amount.and_then(|raw_amount| {
+            Amount::parse_with_precision(raw_amount, config.rounding, precision).ok()
+        });
+    }
+    // The raw amount is checked as rounding has already turned a sub-precision amount into zero.
+    if config.reject_subprecision {
+        transaction.subprecision = raw_amount
+            .is_some_and(|raw_amount| is_subprecision(raw_amount, config.rounding, precision));
+    }
+    if config.suspense_account.is_some() {
+        transaction.rounding_residue = raw_amount.map_or(Decimal::ZERO, |raw_amount| {
+            rounding_residue(raw_amount, config.rounding, precision)
+        });
+    }
+    // A non-empty amount that did not parse (e.g. `.` or `-`) is kept apart from an empty one.
+    if transaction.amount.is_none() {
+        transaction.invalid_amount = raw_amount
+            .filter(|raw_amount| !raw_amount.is_empty())
+            .and_then(|raw_amount| Amount::parse(raw_amount).err());
+    }
+    // Amounts with too many significant digits are invalid, so are rejected rather than applied.
+    if let Some(err) = config
+        .max_significant_digits
+        .zip(raw_amount)
+        .and_then(|(max_digits, raw_amount)| excess_digits(raw_amount, max_digits))
+    {
+        transaction.amount = None;
+        transaction.invalid_amount = Some(err);
+    }
+}
+
+// Report of a call to `apply_transactions`, so a library caller gets everything about the run in one value:
+// the rows read, every rejection with its line and reason, skipped rows, warnings, and a summary of the
+// transactions applied and the resulting balances.
+#[derive(Debug, Default)]
+pub struct ProcessingStats {
+    pub rows_read: usize,
+    // True if processing stopped early because the maximum runtime was exceeded.
+    pub truncated: bool,
+    // Risk alerts raised in row order: clients whose transaction rate exceeded the configured burst limit, and
+    // deposits/withdrawals applied to accounts that were locked and later unlocked.
+    pub alerts: Vec<Alert>,
+    // Number of future-dated transactions still not effective, and so left unapplied, at the end of the run.
+    pub deferred: usize,
+    // Every transaction rejected without being applied, with the reason it was rejected.
+    pub rejections: Vec<Rejection>,
+    // Why each row that could not be parsed, and so was skipped, was malformed.
+    pub malformed_rows: Vec<String>,
+    // Every applied transaction in the order it was applied, when the ledger is recorded.
+    pub ledger: Vec<LedgerEntry>,
+    // Sum of the rounding residues of applied deposits less those of applied withdrawals.
+    pub rounding_residue: Decimal,
+    // Transactions applied by type, and the clients once the run finished.
+    pub summary: ProcessingSummary,
+}
+
+impl ProcessingStats {
+    // Send every alert raised during the run, in order, to the sink.
+    pub fn send_alerts(&self, sink: &mut dyn AlertSink) {
+        for alert in &self.alerts {
+            sink.alert(alert);
+        }
+    }
+
+    // Warning to show the operator when the output only reflects part of the input.
+    pub fn truncation_warning(&self) -> Option<String> {
+        self.truncated.then(|| {
+            format!(
+                "warning: maximum runtime exceeded after {} rows, output reflects a truncated input",
+                self.rows_read
+            )
+        })
+    }
+
+    // Warning to show the operator when future-dated transactions were left unapplied.
+    pub fn deferred_warning(&self) -> Option<String> {
+        (self.deferred > 0).then(|| {
+            format!(
+                "warning: {} future-dated transactions were not yet effective and were left unapplied",
+                self.deferred
+            )
+        })
+    }
+
+    // Every problem found with the input: why each malformed row was skipped, then why each rejected
+    // transaction was left unapplied.
+    pub fn errors(&self) -> impl Iterator<Item = String> + '_ {
+        self.malformed_rows
+            .iter()
+            .cloned()
+            .chain(self.rejections.iter().map(Rejection::to_string))
+    }
+
+    // Number of malformed rows and rejected transactions.
+    pub fn error_count(&self) -> usize {
+        self.malformed_rows.len() + self.rejections.len()
+    }
+
+    // Summary of a dry run, for the operator to tell whether the input is ready for a full run.
+    pub fn dry_run_summary(&self) -> String {
+        format!(
+            "dry run: {} rows validated, {} errors found",
+            self.rows_read,
+            self.error_count()
+        )
+    }
+
+    // Warning to show the operator when malformed rows were skipped.
+    pub fn malformed_warning(&self) -> Option<String> {
+        (!self.malformed_rows.is_empty()).then(|| {
+            format!(
+                "warning: {} malformed rows were skipped",
+                self.malformed_rows.len()
+            )
+        })
+    }
+
+    // Summary to show the operator when any transactions were rejected.
+    pub fn rejection_summary(&self) -> Option<String> {
+        (!self.rejections.is_empty()).then(|| {
+            format!(
+                "warning: {} transactions were rejected and left unapplied",
+                self.rejections.len()
+            )
+        })
+    }
+
+    // Write every ledger entry as a csv row of client, tx, type, amount and the client's resulting balances.
+    pub fn write_ledger<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record([
+            "client",
+            "tx",
+            "type",
+            "amount",
+            "available",
+            "held",
+            "total",
+            "locked",
+        ])?;
+        for entry in &self.ledger {
+            wtr.write_record([
+                entry.client_id.to_string(),
+                entry.transaction_id.to_string(),
+                entry.transaction_type.as_str().to_string(),
+                entry
+                    .amount
+                    .map_or_else(String::new, |amount| amount.to_string()),
+                entry.available.normalize().to_string(),
+                entry.held.normalize().to_string(),
+                entry.total.normalize().to_string(),
+                entry.locked.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    // Write every rejection as a csv row of line, type, client, tx and reason.
+    pub fn write_rejections<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["line", "type", "client", "tx", "reason"])?;
+        for rejection in &self.rejections {
+            wtr.write_record([
+                rejection.line.to_string(),
+                rejection.transaction_type.as_str().to_string(),
+                rejection.client_id.to_string(),
+                rejection.transaction_id.to_string(),
+                rejection.error.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+// Totals describing a run: how many transactions of each type were applied, and how many clients there
+// were along with their summed available and held funds once the run finished. A sum too large to
+// represent is None.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProcessingSummary {
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub disputes: usize,
+    pub resolves: usize,
+    pub chargebacks: usize,
+    pub releases: usize,
+    pub unlocks: usize,
+    pub corrections: usize,
+    pub clients: usize,
+    pub total_available: Option<Decimal>,
+    pub total_held: Option<Decimal>,
+}
+
+impl ProcessingSummary {
+    // Count a transaction applied to a client. Clock rows are never applied to a client so are not counted.
+    fn record_applied(&mut self, transaction_type: TransactionType) {
+        match transaction_type {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Withdrawal => self.withdrawals += 1,
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Resolve => self.resolves += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            TransactionType::Release => self.releases += 1,
+            TransactionType::Unlock => self.unlocks += 1,
+            TransactionType::Correction => self.corrections += 1,
+            TransactionType::Clock => {}
+        }
+    }
+
+    // Add the applied transaction counts of another summary, e.g. of a shard.
+    fn merge_counts(&mut self, other: &ProcessingSummary) {
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.releases += other.releases;
+        self.unlocks += other.unlocks;
+        self.corrections += other.corrections;
+    }
+
+    // Record the clients and their summed balances.
+    fn record_clients(&mut self, client_db: &client::ClientDb<impl Storage<u16, client::Client>>) {
+        self.clients = client_db.client_records().count();
+        self.total_available = client_db.total_available();
+        self.total_held = client_db.total_held();
+    }
+}
+
+impl fmt::Display for ProcessingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "applied {} deposits, {} withdrawals, {} disputes, {} resolves, {} chargebacks, {} releases, {} \
+             unlocks and {} corrections; {} clients with {} available and {} held",
+            self.deposits,
+            self.withdrawals,
+            self.disputes,
+            self.resolves,
+            self.chargebacks,
+            self.releases,
+            self.unlocks,
+            self.corrections,
+            self.clients,
+            SummedFunds(self.total_available),
+            SummedFunds(self.total_held)
+        )
+    }
+}
+
+// Funds summed across every client, written as `overflowed` when the sum is too large to represent.
+struct SummedFunds(Option<Decimal>);
+
+impl fmt::Display for SummedFunds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(total) => write!(f, "{}", total.normalize()),
+            None => f.write_str("overflowed"),
+        }
+    }
+}
+
+// A transaction applied to a client, with the amount it moved and the client's balances once applied.
+// The amount of a dispute/resolve/release/chargeback is the disputed amount of the transaction it references,
+// and the amount of a correction is the amount it corrected the transaction to.
+#[derive(Debug, PartialEq)]
+pub struct LedgerEntry {
+    pub client_id: u16,
+    pub transaction_id: u32,
+    pub transaction_type: TransactionType,
+    pub amount: Option<Amount>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl LedgerEntry {
+    fn new(
+        transaction: &Transaction,
+        transaction_db: &TransactionDb<impl Storage<u32, Transaction>>,
+        client: &client::Client,
+    ) -> Self {
+        let amount = match transaction.transaction_type {
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Release
+            | TransactionType::Chargeback => {
+                transaction_db.disputed_amount(transaction.transaction_id)
+            }
+            _ => transaction.amount,
+        };
+        LedgerEntry {
+            client_id: transaction.client_id,
+            transaction_id: transaction.transaction_id,
+            transaction_type: transaction.transaction_type,
+            amount,
+            available: client.available(),
+            held: client.held(),
+            total: client.total(),
+            locked: client.locked(),
+        }
+    }
+}
+
+// A transaction that was rejected, along with the line of its row and the reason it was rejected.
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    pub line: u64,
+    pub transaction_type: TransactionType,
+    pub client_id: u16,
+    pub transaction_id: u32,
+    pub error: TransactionError,
+}
+
+impl Rejection {
+    fn new(transaction: &Transaction, line: u64, error: TransactionError) -> Self {
+        Rejection {
+            line,
+            transaction_type: transaction.transaction_type,
+            client_id: transaction.client_id,
+            transaction_id: transaction.transaction_id,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+// A dispute/resolve/chargeback row whose referenced tx id does not exist anywhere in the file.
+#[derive(Debug, PartialEq)]
+pub struct DanglingReference {
+    pub line: u64,
+    pub transaction_type: TransactionType,
+    pub transaction_id: u32,
+}
+
+impl fmt::Display for DanglingReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {} references unknown tx {}",
+            self.line,
+            self.transaction_type.as_str(),
+            self.transaction_id
+        )
+    }
+}
+
+// A disputed deposit/withdrawal that has not yet been resolved or charged back.
+#[derive(Debug, PartialEq)]
+pub struct OpenDispute {
+    pub client_id: u16,
+    pub transaction_id: u32,
+    pub amount: Option<Amount>,
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for OpenDispute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {}: client {} amount {} reason {}",
+            self.transaction_id,
+            self.client_id,
+            self.amount
+                .map_or_else(String::new, |amount| amount.to_string()),
+            self.reason.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+// Parses every row of a transaction file, in file order, without applying any of them.
+// Malformed rows are reported with their line number as when applying the file.
+pub fn read_transactions<R: io::Read>(
+    mut rdr: Reader<R>,
+    config: &EngineConfig,
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let headers = RowHeaders::new(rdr.headers()?.clone());
+    rdr.records()
+        .map(|row| {
+            let record = row?;
+            let line = record.position().map_or(0, |position| position.line());
+            headers.parse_record(record, line, config)
+        })
+        .collect()
+}
+
+// Pre-pass over whole transaction files collecting every deposit/withdrawal tx id, then reporting each
+// dispute/resolve/chargeback that references none of them (or any already stored in the Transaction Database).
+// Unlike per-row processing this is independent of ordering, so a dispute before its deposit is not dangling.
+// Rows are parsed exactly as `apply_transactions` parses them, so malformed rows are skipped (or abort the check
+// if the run is strict) just as they are when the transactions are applied.
+pub fn check_references<R: io::Read>(
+    readers: Vec<Reader<R>>,
+    transaction_db: &TransactionDb<impl Storage<u32, Transaction>>,
+    config: &EngineConfig,
+) -> Result<Vec<DanglingReference>, Box<dyn Error>> {
+    let mut known_ids = HashSet::new();
+    let mut references = Vec::new();
+    for rdr in readers {
+        for row in read_rows(rdr, config) {
+            let (transaction, line) = match row? {
+                Row::Parsed(transaction, line) => (transaction, line),
+                Row::Malformed(_) => continue,
+            };
+            match transaction.transaction_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => {
+                    known_ids.insert(transaction.transaction_id);
+                }
+                // Only claims reference another transaction; the tx id of an unlock or clock row means nothing.
+                transaction_type @ (TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Release
+                | TransactionType::Correction) => references.push(DanglingReference {
+                    line,
+                    transaction_type,
+                    transaction_id: transaction.transaction_id,
+                }),
+                TransactionType::Unlock | TransactionType::Clock => {}
+            }
+        }
+    }
+    references.retain(|reference| {
+        !known_ids.contains(&reference.transaction_id)
+            && transaction_db
+                .retrieve_transaction_data(&reference.transaction_id)
+                .is_none()
+    });
+    Ok(references)
+}
+
+// True if the record has a column at the given index but the field is empty.
+fn is_empty_field(record: &StringRecord, column: Option<usize>) -> bool {
+    column
+        .and_then(|column| record.get(column))
+        .is_some_and(str::is_empty)
+}
+
+// Verifies a row's sequence number directly follows the last one seen, returning it as the new last seq.
+// A missing or out of order sequence number indicates lost transactions, so processing is aborted.
+fn check_seq(last_seq: Option<u64>, seq: Option<u64>, line: u64) -> Result<u64, Box<dyn Error>> {
+    let seq = seq.ok_or_else(|| format!("line {}: missing sequence number", line))?;
+    match last_seq {
+        Some(last) if last.checked_add(1) != Some(seq) => Err(format!(
+            "line {}: sequence gap, expected seq {} but found {}",
+            line,
+            last.wrapping_add(1),
+            seq
+        )
+        .into()),
+        _ => Ok(seq),
+    }
+}
+
 // ------------------------------------------------------------------------------------------------
 // -------------------------------- TRANSACTION DB STRUCT -----------------------------------------
 // ------------------------------------------------------------------------------------------------
 
-// Wrapper struct transaction database (hashmap) to avoid exposure to internal hashmap api.
-pub struct TransactionDb {
-    db: HashMap<u32, Transaction>,
+// Wrapper struct transaction database (hashmap by default, or any `Storage` for the deposits/withdrawals) to
+// avoid exposure to internal storage api.
+// Dispute, resolve and chargeback events are kept separately, keyed by the tx id they reference.
+// The dispute state of each disputed transaction is tracked so that only funds actually held by a
+// dispute can be resolved or charged back, along with how much of it was disputed and charged back.
+// The order transactions arrived in is kept so that they can be iterated in that order and, when a maximum
+// is set, the oldest can be forgotten.
+pub struct TransactionDb<S = Map<u32, Transaction>> {
+    db: S,
+    dispute_events: Map<u32, Vec<Transaction>>,
+    dispute_states: Map<u32, DisputeState>,
+    dispute_amounts: Map<u32, DisputeAmounts>,
+    arrival_order: VecDeque<u32>,
+    max_tracked: Option<usize>,
+}
+
+// Amounts of a disputed transaction. A dispute may cover only part of the transaction's amount, and
+// only that part is held and may be charged back. Once charged back, the rest can still be disputed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DisputeAmounts {
+    // Amount covered by the most recent dispute.
+    pub disputed: Amount,
+    // Total charged back across every dispute of the transaction.
+    pub charged_back: Amount,
+}
+
+// What applying a claim (dispute/resolve/release/chargeback/correction) needs to know about the transaction
+// it references. Looked up from the transaction db before the client record is borrowed for mutation, so
+// applying a transaction never needs both databases borrowed at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferencedTransaction {
+    pub client_id: u16,
+    pub transaction_type: TransactionType,
+    // Amount the transaction currently stands at, as corrected.
+    pub amount: Option<Amount>,
+    pub dispute_state: Option<DisputeState>,
+    pub dispute_amounts: DisputeAmounts,
+    // Amount a dispute can still cover, and the amount covered by the current dispute.
+    pub disputable: Option<Amount>,
+    pub disputed: Option<Amount>,
+    // When the transaction was made, if it was timestamped.
+    pub timestamp: Option<u64>,
+}
+
+// Where a transaction is in the dispute process. Transactions that have never been disputed have no state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeState {
+    // Funds are held pending a resolve or chargeback.
+    Disputed,
+    // The dispute was resolved and the held funds returned.
+    Resolved,
+    // The dispute ended in a chargeback and the held funds were withdrawn.
+    ChargedBack,
+}
+
+impl DisputeState {
+    // Lowercase name of the state, as persisted.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+        }
+    }
+}
+
+impl FromStr for DisputeState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        [
+            DisputeState::Disputed,
+            DisputeState::Resolved,
+            DisputeState::ChargedBack,
+        ]
+        .into_iter()
+        .find(|state| state.as_str() == value)
+        .ok_or_else(|| format!("unknown dispute state `{}`", value))
+    }
 }
 
 // Transaction type enum as finite list of options. Avoids matching transaction type as string.
-#[derive(Deserialize)]
-#[serde(rename_all = "snake_case")]
+// Deserialised case-insensitively (see below) as casing of the `type` column varies between sources.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Release,
+    Unlock,
+    Clock,
+    Correction,
+}
+
+impl TransactionType {
+    // Every transaction type, in the order they are listed in messages.
+    pub const ALL: [TransactionType; 9] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Release,
+        TransactionType::Unlock,
+        TransactionType::Clock,
+        TransactionType::Correction,
+    ];
+
+    // Lowercase name of the transaction type, matching the value used in the csv `type` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Release => "release",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Clock => "clock",
+            TransactionType::Correction => "correction",
+        }
+    }
+}
+
+// Parse a transaction type ignoring case and surrounding whitespace, e.g. `Deposit`, ` DEPOSIT ` and `deposit`
+// are all deposits. Whitespace is trimmed here too so readers built without trimming accept padded types.
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        TransactionType::ALL
+            .into_iter()
+            .find(|transaction_type| transaction_type.as_str().eq_ignore_ascii_case(value.trim()))
+            .ok_or_else(|| format!("unknown transaction type `{}`", value.trim()))
+    }
+}
+
+// Custom Deserialiser for the transaction type using the case-insensitive parse above.
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TransactionTypeVisitor;
+
+        impl<'de> Visitor<'de> for TransactionTypeVisitor {
+            type Value = TransactionType;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                let names: Vec<&str> = TransactionType::ALL
+                    .iter()
+                    .map(TransactionType::as_str)
+                    .collect();
+                write!(formatter, "one of {}", names.join(", "))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(TransactionTypeVisitor)
+    }
 }
 
 // Transaction Struct with renamed fields for clarity and to avoid using `type` keyword.
@@ -53,24 +1368,26 @@ pub struct Transaction {
     #[serde(rename = "tx")]
     pub transaction_id: u32,
     #[serde(deserialize_with = "round_deserialise")]
-    pub amount: Option<f64>,
-}
-
-// Custom Deserialiser to round transaction amount to 4.d.p. Runs on point of deserialising csv.
-fn round_deserialise<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let x: Result<f64, _> = Deserialize::deserialize(deserializer);
-    // If x is error then the field was None in the CSV as empty string cannot be deserialised.
-    // Therefore we return None as there is no amount to round.
-    match x {
-        Ok(value) => {
-            let rounded_to_precision = (value * 10_000.0).round() / 10_000.0;
-            Ok(Some(rounded_to_precision))
-        }
-        Err(_) => Ok(None),
-    }
+    pub amount: Option<Amount>,
+    // Optional delivery sequence number, used to detect lost transactions.
+    #[serde(default)]
+    pub seq: Option<u64>,
+    // Optional time the transaction occurred, in seconds since the unix epoch.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    // Optional reason code given on dispute rows (e.g. fraud, error), surfaced in the dispute report.
+    // Required on correction rows, as the audit reason for the correction.
+    #[serde(default)]
+    pub reason: Option<String>,
+    // True if the amount was non-zero but rounded to zero, only checked when such amounts are rejected.
+    #[serde(skip)]
+    pub subprecision: bool,
+    // Why a non-empty amount field could not be read as an amount, e.g. a lone `.` or a negative amount.
+    #[serde(skip)]
+    pub invalid_amount: Option<AmountError>,
+    // Part of the amount field lost rounding it to 4.d.p, only recorded when a suspense account is kept.
+    #[serde(skip)]
+    pub rounding_residue: Decimal,
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -82,22 +1399,389 @@ impl TransactionDb {
     // database would exist in real-life scenario and would init associated function
     // would create database connection.
     pub fn init() -> Self {
-        Self { db: HashMap::new() }
+        TransactionDb::with_storage(Map::new())
     }
 
-    // Insert transaction if of type deposit or withdrawal.
-    pub fn insert_transaction(&mut self, transaction: Transaction) {
-        match transaction.transaction_type {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                self.db.insert(transaction.transaction_id, transaction);
+    // Database tracking at most `max_tracked` deposits/withdrawals, see `set_max_tracked`.
+    pub fn with_max_tracked(max_tracked: usize) -> Self {
+        Self {
+            max_tracked: Some(max_tracked),
+            ..Self::init()
+        }
+    }
+
+    // Split the database into `shards` databases, placing each transaction (and its dispute events, state and amounts)
+    // in the shard owning its client. Any maximum number of tracked transactions is divided between the shards.
+    pub fn into_shards(mut self, shards: usize) -> Vec<TransactionDb> {
+        let mut shard_dbs: Vec<TransactionDb> = (0..shards)
+            .map(|_| TransactionDb {
+                max_tracked: self
+                    .max_tracked
+                    .map(|max_tracked| max_tracked.div_ceil(shards)),
+                ..TransactionDb::init()
+            })
+            .collect();
+        for transaction_id in self.arrival_order {
+            if let Some(transaction) = self.db.remove(&transaction_id) {
+                let shard = &mut shard_dbs[usize::from(transaction.client_id) % shards];
+                if let Some(events) = self.dispute_events.remove(&transaction_id) {
+                    shard.dispute_events.insert(transaction_id, events);
+                }
+                if let Some(state) = self.dispute_states.remove(&transaction_id) {
+                    shard.dispute_states.insert(transaction_id, state);
+                }
+                if let Some(amounts) = self.dispute_amounts.remove(&transaction_id) {
+                    shard.dispute_amounts.insert(transaction_id, amounts);
+                }
+                shard.db.insert(transaction_id, transaction);
+                shard.arrival_order.push_back(transaction_id);
             }
-            _ => {}
         }
+        shard_dbs
     }
-    // Retrieves immutable reference to a transaction from the database.
+
+    // Move every transaction, dispute event, dispute state and dispute amount of another database into this one.
+    // The other database's transactions are taken to have arrived after this one's. Transaction ids must be
+    // unique across the databases, so a transaction whose id this database already stores is not moved,
+    // leaving this database's transaction (and its dispute history) in place, and its id is returned.
+    pub fn merge(&mut self, mut other: TransactionDb) -> Vec<u32> {
+        let mut collisions = Vec::new();
+        for transaction_id in other.arrival_order {
+            let transaction = match other.db.remove(&transaction_id) {
+                Some(transaction) => transaction,
+                None => continue,
+            };
+            if self.db.contains_key(&transaction_id) {
+                collisions.push(transaction_id);
+                continue;
+            }
+            if let Some(events) = other.dispute_events.remove(&transaction_id) {
+                self.dispute_events.insert(transaction_id, events);
+            }
+            if let Some(state) = other.dispute_states.remove(&transaction_id) {
+                self.dispute_states.insert(transaction_id, state);
+            }
+            if let Some(amounts) = other.dispute_amounts.remove(&transaction_id) {
+                self.dispute_amounts.insert(transaction_id, amounts);
+            }
+            self.db.insert(transaction_id, transaction);
+            self.arrival_order.push_back(transaction_id);
+        }
+        self.evict_oldest();
+        collisions
+    }
+}
+
+impl<S: Storage<u32, Transaction>> TransactionDb<S> {
+    // Database keeping its deposits/withdrawals in the given storage, e.g. an integrator's persistent store.
+    pub fn with_storage(db: S) -> Self {
+        TransactionDb {
+            db,
+            dispute_events: Map::new(),
+            dispute_states: Map::new(),
+            dispute_amounts: Map::new(),
+            arrival_order: VecDeque::new(),
+            max_tracked: None,
+        }
+    }
+
+    // Limit the database to tracking `max_tracked` deposits/withdrawals, forgetting the oldest (along with
+    // their dispute events, state and amounts) once the limit is exceeded, so memory stays bounded on huge
+    // inputs. Forgotten transactions can no longer be disputed. Transactions currently under dispute are
+    // never forgotten, so their held funds can still be resolved or charged back.
+    pub fn set_max_tracked(&mut self, max_tracked: usize) {
+        self.max_tracked = Some(max_tracked);
+        self.evict_oldest();
+    }
+
+    // Number of deposits/withdrawals currently tracked.
+    pub fn tracked_transactions(&self) -> usize {
+        self.db.len()
+    }
+
+    // Insert transaction if of type deposit or withdrawal.
+    // Disputes, resolutions, chargebacks and corrections are recorded (once) as events against the
+    // transaction they reference, provided that transaction exists and belongs to the same client.
+    // Returns false, keeping the original, if a deposit/withdrawal with the same id is already stored.
+    pub fn insert_transaction(&mut self, transaction: Transaction) -> bool {
+        match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                // Transaction ids are globally unique, so a duplicate must not replace the original,
+                // which may be under dispute.
+                if self.is_duplicate_id(&transaction) {
+                    return false;
+                }
+                let transaction_id = transaction.transaction_id;
+                self.db.insert(transaction_id, transaction);
+                self.arrival_order.push_back(transaction_id);
+                self.evict_oldest();
+            }
+            _ => {
+                let same_client = self
+                    .db
+                    .get(&transaction.transaction_id)
+                    .is_some_and(|referenced| referenced.client_id == transaction.client_id);
+                if same_client && !self.is_duplicate_event(&transaction) {
+                    self.dispute_events
+                        .entry(transaction.transaction_id)
+                        .or_default()
+                        .push(transaction);
+                }
+            }
+        }
+        true
+    }
+
+    // Forget the oldest transactions not under dispute until no more than the maximum are tracked.
+    // Transactions under dispute that are passed over stay at the front, to be forgotten once settled.
+    fn evict_oldest(&mut self) {
+        let max_tracked = match self.max_tracked {
+            Some(max_tracked) => max_tracked,
+            None => return,
+        };
+        let mut disputed = Vec::new();
+        while self.db.len() > max_tracked {
+            let transaction_id = match self.arrival_order.pop_front() {
+                Some(transaction_id) => transaction_id,
+                None => break,
+            };
+            if self.dispute_state(transaction_id) == Some(DisputeState::Disputed) {
+                disputed.push(transaction_id);
+                continue;
+            }
+            self.db.remove(&transaction_id);
+            self.dispute_events.remove(&transaction_id);
+            self.dispute_states.remove(&transaction_id);
+            self.dispute_amounts.remove(&transaction_id);
+        }
+        for transaction_id in disputed.into_iter().rev() {
+            self.arrival_order.push_front(transaction_id);
+        }
+    }
+
+    // True if a dispute/resolve/chargeback of the same type has already been recorded against the tx.
+    // A chargeback may only cover part of the transaction, after which the rest can be disputed again, so
+    // a dispute is only a duplicate of one since the last chargeback, and any other event only a duplicate
+    // of one since the last dispute. A transaction may be corrected any number of times.
+    pub fn is_duplicate_event(&self, transaction: &Transaction) -> bool {
+        if transaction.transaction_type == TransactionType::Correction {
+            return false;
+        }
+        self.dispute_events
+            .get(&transaction.transaction_id)
+            .is_some_and(|events| {
+                let last_of = |transaction_type: TransactionType| {
+                    events
+                        .iter()
+                        .rposition(|event| event.transaction_type == transaction_type)
+                };
+                let round_start = match transaction.transaction_type {
+                    TransactionType::Dispute => {
+                        last_of(TransactionType::Chargeback).map_or(0, |position| position + 1)
+                    }
+                    _ => last_of(TransactionType::Dispute).unwrap_or(0),
+                };
+                events[round_start..]
+                    .iter()
+                    .any(|event| event.transaction_type == transaction.transaction_type)
+            })
+    }
+
+    // True if the transaction is a deposit/withdrawal whose id is already taken by a stored transaction.
+    pub fn is_duplicate_id(&self, transaction: &Transaction) -> bool {
+        matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) && self.db.get(&transaction.transaction_id).is_some()
+    }
+
+    // Current dispute state of a transaction, or None if it has never been disputed.
+    pub fn dispute_state(&self, transaction_id: u32) -> Option<DisputeState> {
+        self.dispute_states.get(&transaction_id).copied()
+    }
+
+    // Set the dispute state of a transaction, e.g. when loading persisted state.
+    #[cfg(feature = "sqlite")]
+    pub fn set_dispute_state(&mut self, transaction_id: u32, state: DisputeState) {
+        self.dispute_states.insert(transaction_id, state);
+    }
+
+    // Iterate over the dispute state of every disputed transaction.
+    #[cfg(feature = "sqlite")]
+    pub fn dispute_states(&self) -> impl Iterator<Item = (u32, DisputeState)> + '_ {
+        self.dispute_states
+            .iter()
+            .map(|(transaction_id, state)| (*transaction_id, *state))
+    }
+
+    // Amount the transaction currently stands at: the amount of its latest correction, or its own amount
+    // if it has never been corrected. None if the transaction does not exist or has no amount.
+    pub fn effective_amount(&self, transaction_id: u32) -> Option<Amount> {
+        let corrected = self.dispute_events.get(&transaction_id).and_then(|events| {
+            events
+                .iter()
+                .rfind(|event| event.transaction_type == TransactionType::Correction)
+        });
+        match corrected {
+            Some(correction) => correction.amount,
+            None => self.db.get(&transaction_id)?.amount,
+        }
+    }
+
+    // Amount a dispute of the transaction can still cover: its effective amount less anything already
+    // charged back. None if the transaction does not exist or has no amount.
+    pub fn disputable_amount(&self, transaction_id: u32) -> Option<Amount> {
+        let amount = self.effective_amount(transaction_id)?;
+        amount.checked_sub(self.dispute_amounts(transaction_id).charged_back)
+    }
+
+    // Amount covered by the most recent dispute of the transaction. Transactions disputed before
+    // disputed amounts were tracked are taken to be disputed in full.
+    pub fn disputed_amount(&self, transaction_id: u32) -> Option<Amount> {
+        match self.dispute_amounts.get(&transaction_id) {
+            Some(amounts) => Some(amounts.disputed),
+            None => self.effective_amount(transaction_id),
+        }
+    }
+
+    // Disputed and charged back amounts of a transaction, zero if it has never been disputed.
+    pub fn dispute_amounts(&self, transaction_id: u32) -> DisputeAmounts {
+        self.dispute_amounts
+            .get(&transaction_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Set the disputed and charged back amounts of a transaction, e.g. when loading persisted state.
+    #[cfg(feature = "sqlite")]
+    pub fn set_dispute_amounts(&mut self, transaction_id: u32, amounts: DisputeAmounts) {
+        self.dispute_amounts.insert(transaction_id, amounts);
+    }
+
+    // Record a dispute/resolve/chargeback/correction event as already applied to the transaction it
+    // references, e.g. when loading persisted state. Events must be recorded in the order they were applied.
+    #[cfg(feature = "sqlite")]
+    pub fn push_dispute_event(&mut self, transaction: Transaction) {
+        self.dispute_events
+            .entry(transaction.transaction_id)
+            .or_default()
+            .push(transaction);
+    }
+
+    // Iterate over the events recorded against every referenced transaction, each in the order applied.
+    #[cfg(feature = "sqlite")]
+    pub fn dispute_events(&self) -> impl Iterator<Item = (u32, &[Transaction])> + '_ {
+        self.dispute_events
+            .iter()
+            .map(|(transaction_id, events)| (*transaction_id, events.as_slice()))
+    }
+
+    // Iterate over the disputed and charged back amounts of every disputed transaction.
+    #[cfg(feature = "sqlite")]
+    pub fn all_dispute_amounts(&self) -> impl Iterator<Item = (u32, DisputeAmounts)> + '_ {
+        self.dispute_amounts
+            .iter()
+            .map(|(transaction_id, amounts)| (*transaction_id, *amounts))
+    }
+
+    // Move the referenced transaction to the dispute state reached by an applied dispute/resolve/chargeback.
+    // A dispute records the amount it covers, either its own amount or all that is still disputable, and a
+    // chargeback adds the disputed amount to the amount charged back.
+    fn update_dispute_state(&mut self, transaction: &Transaction, held: Decimal) {
+        let transaction_id = transaction.transaction_id;
+        let state = match transaction.transaction_type {
+            TransactionType::Dispute => {
+                // Only the amount the dispute actually held can later be resolved or charged back.
+                let disputed = Amount::new(held).unwrap_or_default();
+                self.dispute_amounts.insert(
+                    transaction_id,
+                    DisputeAmounts {
+                        disputed,
+                        ..self.dispute_amounts(transaction_id)
+                    },
+                );
+                DisputeState::Disputed
+            }
+            TransactionType::Resolve => DisputeState::Resolved,
+            TransactionType::Chargeback => {
+                let mut amounts = self.dispute_amounts(transaction_id);
+                let disputed = self.disputed_amount(transaction_id).unwrap_or_default();
+                amounts.charged_back = amounts
+                    .charged_back
+                    .checked_add(disputed)
+                    .unwrap_or(amounts.charged_back);
+                self.dispute_amounts.insert(transaction_id, amounts);
+                DisputeState::ChargedBack
+            }
+            _ => return,
+        };
+        self.dispute_states.insert(transaction_id, state);
+    }
+
+    // Everything applying a claim needs to know about the transaction it references, or None if the
+    // transaction is unknown.
+    pub fn referenced_transaction(&self, transaction_id: u32) -> Option<ReferencedTransaction> {
+        let transaction = self.db.get(&transaction_id)?;
+        Some(ReferencedTransaction {
+            client_id: transaction.client_id,
+            transaction_type: transaction.transaction_type,
+            amount: self.effective_amount(transaction_id),
+            dispute_state: self.dispute_state(transaction_id),
+            dispute_amounts: self.dispute_amounts(transaction_id),
+            disputable: self.disputable_amount(transaction_id),
+            disputed: self.disputed_amount(transaction_id),
+            timestamp: transaction.timestamp,
+        })
+    }
+
+    // Retrieves immutable reference to a transaction from the database.
     pub fn retrieve_transaction_data(&self, transaction_id: &u32) -> Option<&Transaction> {
         self.db.get(transaction_id)
     }
+
+    // Full lineage of a transaction: the original deposit/withdrawal followed by every
+    // dispute/resolve/chargeback/correction referencing it, in the order they were received.
+    pub fn lineage(&self, transaction_id: u32) -> Vec<&Transaction> {
+        self.db
+            .get(&transaction_id)
+            .into_iter()
+            .chain(
+                self.dispute_events
+                    .get(&transaction_id)
+                    .into_iter()
+                    .flatten(),
+            )
+            .collect()
+    }
+
+    // Every transaction whose most recent event is a dispute, ordered by tx id, along with the
+    // reason code given on that dispute.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut open_disputes: Vec<OpenDispute> = self
+            .dispute_events
+            .iter()
+            .filter_map(|(transaction_id, events)| {
+                let dispute = events.last()?;
+                let disputed = self.db.get(transaction_id)?;
+                (dispute.transaction_type == TransactionType::Dispute).then(|| OpenDispute {
+                    client_id: disputed.client_id,
+                    transaction_id: *transaction_id,
+                    amount: self.disputed_amount(*transaction_id),
+                    reason: dispute.reason.clone(),
+                })
+            })
+            .collect();
+        open_disputes.sort_by_key(|open_dispute| open_dispute.transaction_id);
+        open_disputes
+    }
+
+    // Iterate over immutable references to every stored deposit/withdrawal, oldest first, in the order they
+    // arrived in. Unlike the map itself, this order is the same on every run over the same input.
+    pub fn iter_in_order(&self) -> impl Iterator<Item = &Transaction> {
+        self.arrival_order
+            .iter()
+            .filter_map(|transaction_id| self.db.get(transaction_id))
+    }
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -105,26 +1789,166 @@ impl TransactionDb {
 // ------------------------------------------------------------------------------------------------
 
 impl Transaction {
-    // Applies transaction to a client record
+    // Create a transaction without any of the optional columns set.
+    pub fn new(
+        transaction_type: TransactionType,
+        client_id: u16,
+        transaction_id: u32,
+        amount: Option<Amount>,
+    ) -> Self {
+        Transaction {
+            transaction_type,
+            client_id,
+            transaction_id,
+            amount,
+            seq: None,
+            timestamp: None,
+            reason: None,
+            subprecision: false,
+            invalid_amount: None,
+            rounding_residue: Decimal::ZERO,
+        }
+    }
+
+    // Applies transaction to a client record, returning the reason if it was rejected.
+    // Once applied, a dispute/resolve/chargeback moves the referenced transaction to its new dispute state.
+    // The transaction db is only read until the client record has been updated, so the two are never
+    // borrowed at once.
     pub fn handle_transaction(
         &self,
-        transaction_db: &TransactionDb,
-        client_db: &mut client::ClientDb,
-    ) {
-        let client_record = client_db.get_client_record(&self.client_id);
+        transaction_db: &mut TransactionDb<impl Storage<u32, Transaction>>,
+        client_db: &mut client::ClientDb<impl Storage<u16, client::Client>>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let referenced = self.lookup_reference(transaction_db, config)?;
+        // Apply transaction to the client record, creating and storing a new record for an unknown client.
+        // The change in held funds is what a dispute actually held, which may be less than disputed when
+        // disputes are clamped to the available funds.
+        let client = client_db.get_or_create_client_record(self.client_id);
+        let held_before = client.held();
+        let result = client.apply_transaction_to_client(self, referenced.as_ref(), config);
+        let held = client.held() - held_before;
+        if result.is_ok() {
+            client_db.mark_modified(self.client_id);
+            transaction_db.update_dispute_state(self, held);
+        }
+        result
+    }
 
-        // If record exists deref and apply transaction to the record.
-        // If no record, create client record, apply transaction to the record, and store.
-        match client_record {
-            Some(record) => {
-                (*record).apply_transaction_to_client(self, transaction_db);
+    // Rejects a transaction that must not be applied whatever the state of the client, otherwise looking up
+    // the transaction it references (if any) for the client record to apply it against.
+    pub fn lookup_reference(
+        &self,
+        transaction_db: &TransactionDb<impl Storage<u32, Transaction>>,
+        config: &EngineConfig,
+    ) -> Result<Option<ReferencedTransaction>, TransactionError> {
+        // A repeated dispute/resolve/chargeback/release must not hold or release the same funds twice.
+        if transaction_db.is_duplicate_event(self) {
+            return Err(TransactionError::DuplicateEvent {
+                transaction_type: self.transaction_type,
+                transaction_id: self.transaction_id,
+            });
+        }
+        // A deposit/withdrawal reusing the id of an earlier one would otherwise be applied to the balances
+        // and then lost, as only the original is kept.
+        if transaction_db.is_duplicate_id(self) {
+            return Err(TransactionError::DuplicateTransactionId {
+                transaction_type: self.transaction_type,
+                transaction_id: self.transaction_id,
+            });
+        }
+        if config.reject_subprecision && self.subprecision {
+            return Err(TransactionError::SubprecisionAmount {
+                transaction_type: self.transaction_type,
+                client_id: self.client_id,
+                decimal_places: config.amount_precision(),
+            });
+        }
+        // Rows whose amount is applied must not have an unreadable amount treated as no amount at all. A
+        // malformed amount (e.g. `1.2.3` or `1e`) is a corrupt row whatever its type, so is rejected even where
+        // the amount is not used. An empty amount is not malformed.
+        if let Some(err) = &self.invalid_amount {
+            let amount_applied = matches!(
+                self.transaction_type,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Dispute
+                    | TransactionType::Correction
+            );
+            if amount_applied || matches!(err, AmountError::Malformed(_)) {
+                return Err(TransactionError::InvalidAmount {
+                    transaction_type: self.transaction_type,
+                    client_id: self.client_id,
+                    reason: err.to_string(),
+                });
+            }
+        }
+        // Deposits and withdrawals over the configured maximum are rejected before touching the balances, as are
+        // corrections that would bring a transaction over it.
+        if let (
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Correction,
+            Some(amount),
+            Some(max_amount),
+        ) = (self.transaction_type, self.amount, config.max_amount)
+        {
+            if amount.value() > max_amount {
+                return Err(TransactionError::AmountTooLarge {
+                    transaction_type: self.transaction_type,
+                    client_id: self.client_id,
+                    amount: amount.value(),
+                    max_amount,
+                });
             }
-            None => {
-                let mut new_client_record = client::Client::new(self.client_id);
-                new_client_record.apply_transaction_to_client(self, transaction_db);
-                client_db.insert_client_record(new_client_record);
+        }
+        let referenced = transaction_db.referenced_transaction(self.transaction_id);
+        // A claim against another client's transaction is rejected before any client record is looked up, so
+        // neither the claimant's record (which would otherwise be created if unknown) nor the owner's is touched.
+        if let (
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Release
+            | TransactionType::Correction,
+            Some(referenced),
+        ) = (self.transaction_type, &referenced)
+        {
+            if referenced.client_id != self.client_id {
+                return Err(TransactionError::ClientMismatch {
+                    transaction_type: self.transaction_type,
+                    transaction_id: self.transaction_id,
+                    client_id: self.client_id,
+                    owner_id: referenced.client_id,
+                });
+            }
+        }
+        // Withdrawals can only be disputed within the configured window of being made. Without both timestamps
+        // the time between them is unknown, so the dispute is allowed.
+        if let (
+            TransactionType::Dispute,
+            Some(ReferencedTransaction {
+                transaction_type: TransactionType::Withdrawal,
+                timestamp: Some(made_at),
+                ..
+            }),
+            Some(window),
+            Some(disputed_at),
+        ) = (
+            self.transaction_type,
+            &referenced,
+            config.withdrawal_dispute_window,
+            self.timestamp,
+        ) {
+            // Compared as durations, so a fractional window is not truncated to whole seconds.
+            let elapsed = disputed_at.saturating_sub(*made_at);
+            if Duration::from_secs(elapsed) > window {
+                return Err(TransactionError::DisputeWindowClosed {
+                    transaction_id: self.transaction_id,
+                    elapsed,
+                    window,
+                });
             }
         }
+        Ok(referenced)
     }
 }
 
@@ -135,30 +1959,21 @@ impl Transaction {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{
+        BurstLimit, ChargebackLocks, FieldPrecision, LockedAllows, OutputConfig,
+        ResolveAfterChargeback,
+    };
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal_macros::dec;
 
     #[test]
     fn dispute_resolve_chargeback_not_added_to_db() {
         // Make sure disuptes, resolutions, and chargebacks are not added to the transaction_db.
         let mut transaction_db = TransactionDb::init();
         let test_transactions = vec![
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client_id: 1,
-                transaction_id: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Resolve,
-                client_id: 1,
-                transaction_id: 1,
-                amount: None,
-            },
-            Transaction {
-                transaction_type: TransactionType::Chargeback,
-                client_id: 1,
-                transaction_id: 1,
-                amount: None,
-            },
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
         ];
         for transaction in test_transactions {
             transaction_db.insert_transaction(transaction);
@@ -171,23 +1986,2570 @@ mod tests {
         // Make sure deposits and withdrawals are added to the transaction db.
         let mut transaction_db = TransactionDb::init();
         let test_transactions = vec![
-            Transaction {
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.0)).ok()),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Amount::new(dec!(5.0)).ok(),
+            ),
+        ];
+        let number_of_transactions_to_be_inserted = test_transactions.len();
+        for transaction in test_transactions {
+            transaction_db.insert_transaction(transaction);
+        }
+        assert!(transaction_db.db.len() == number_of_transactions_to_be_inserted)
+    }
+
+    #[test]
+    fn explain_reports_insufficient_funds_withdrawal() -> Result<(), Box<dyn Error>> {
+        // Make sure a rejected withdrawal is explained with its line number, amount and the client's balance.
+        let data = "type, client, tx, amount
+                    deposit, 7, 1, 120.0
+                    withdrawal, 7, 2, 500.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanation = Vec::new();
+
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanation),
+        )?;
+        assert_eq!(
+            String::from_utf8(explanation)?,
+            "line 3: withdrawal of 500 rejected, client 7 has only 120 available\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_resolve_applies_only_once() -> Result<(), Box<dyn Error>> {
+        // Make sure a repeated resolve row does not release the held funds a second time and is explained.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, 5.0
+                    dispute, 1, 1,
+                    resolve, 1, 1,
+                    resolve, 1, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanation = Vec::new();
+
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanation),
+        )?;
+        assert_eq!(
+            String::from_utf8(explanation)?,
+            "line 6: duplicate resolve of tx 1 ignored\n"
+        );
+        assert_eq!(transaction_db.lineage(1).len(), 3);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(15.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert_eq!(client_record.total, dec!(15.0));
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_type_parses_mixed_case() -> Result<(), Box<dyn Error>> {
+        // Make sure the type column is matched regardless of casing.
+        let data = "type, client, tx, amount
+                    Deposit, 1, 1, 10.0
+                    DEPOSIT, 1, 2, 10.0
+                    withdrawal, 1, 3, 5.0
+                    Dispute, 1, 1,
+                    RESOLVE, 1, 1,
+                    ChargeBack, 1, 2,";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transaction_types = rdr
+            .deserialize()
+            .map(|row| row.map(|transaction: Transaction| transaction.transaction_type))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            transaction_types,
+            vec![
+                TransactionType::Deposit,
+                TransactionType::Deposit,
+                TransactionType::Withdrawal,
+                TransactionType::Dispute,
+                TransactionType::Resolve,
+                TransactionType::Chargeback,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn every_transaction_type_parses_padded_and_mixed_case() {
+        // Make sure every type is matched with any casing and surrounding whitespace, even without a
+        // trimming reader, while an unknown type still errors naming the type.
+        for transaction_type in TransactionType::ALL {
+            let name = transaction_type.as_str();
+            let mixed_case = name[..1].to_uppercase() + &name[1..];
+            for value in [
+                name.to_string(),
+                mixed_case,
+                format!(" {} ", name.to_uppercase()),
+            ] {
+                assert_eq!(value.parse(), Ok(transaction_type));
+            }
+        }
+        assert_eq!(
+            " Transfer ".parse::<TransactionType>(),
+            Err("unknown transaction type `Transfer`".to_string())
+        );
+        // A type that is not a string at all lists every type that is expected.
+        let err = serde_json::from_str::<TransactionType>("5").map(|_| ());
+        assert_eq!(
+            err.map_err(|err| err.to_string()),
+            Err("invalid type: integer `5`, expected one of deposit, withdrawal, dispute, resolve, \
+                 chargeback, release, unlock, clock, correction at line 1 column 1"
+                .to_string())
+        );
+
+        let data = "type,client,tx,amount
+                    \tDeposit ,1,1,10.0";
+        let mut rdr = ReaderBuilder::new().from_reader(data.as_bytes());
+        let row: Option<Result<Transaction, _>> = rdr.deserialize().next();
+        assert!(matches!(
+            row,
+            Some(Ok(Transaction {
+                transaction_type: TransactionType::Deposit,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn boundary_transaction_ids_disputable() -> Result<(), Box<dyn Error>> {
+        // Tx ids 0 and u32::MAX are valid ids for deposits and their disputes, repeated disputes of either are
+        // detected as duplicates, and an id past u32::MAX is a malformed row.
+        let data = "type, client, tx, amount
+                    deposit, 1, 0, 10.0
+                    deposit, 1, 4294967295, 5.0
+                    dispute, 1, 0,
+                    dispute, 1, 4294967295,
+                    dispute, 1, 0,
+                    dispute, 1, 4294967295,
+                    deposit, 1, 4294967296, 1.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(
+            stats
+                .rejections
+                .iter()
+                .map(|rejection| &rejection.error)
+                .collect::<Vec<_>>(),
+            vec![
+                &TransactionError::DuplicateEvent {
+                    transaction_type: TransactionType::Dispute,
+                    transaction_id: 0,
+                },
+                &TransactionError::DuplicateEvent {
+                    transaction_type: TransactionType::Dispute,
+                    transaction_id: u32::MAX,
+                },
+            ]
+        );
+        assert_eq!(stats.malformed_rows.len(), 1);
+        assert_eq!(
+            transaction_db.dispute_state(0),
+            Some(DisputeState::Disputed)
+        );
+        assert_eq!(
+            transaction_db.dispute_state(u32::MAX),
+            Some(DisputeState::Disputed)
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(15.0));
+        assert_eq!(client_record.total, dec!(15.0));
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_transaction_type_errors() {
+        // Make sure an unknown type is still a deserialisation error.
+        let data = "type, client, tx, amount
+                    transfer, 1, 1, 10.0";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let row: Option<Result<Transaction, _>> = rdr.deserialize().next();
+        assert!(matches!(row, Some(Err(_))));
+    }
+
+    #[test]
+    fn sequence_gap_is_reported() {
+        // Make sure a missing sequence number aborts processing under --require-contiguous-seq.
+        let data = "type, client, tx, amount, seq
+                    deposit, 1, 1, 10.0, 1
+                    deposit, 1, 2, 10.0, 2
+                    deposit, 1, 3, 10.0, 4";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            require_contiguous_seq: true,
+            ..EngineConfig::default()
+        };
+
+        let result = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        );
+        let err = result.expect_err("gap in sequence should be reported");
+        assert_eq!(
+            err.to_string(),
+            "line 4: sequence gap, expected seq 3 but found 4"
+        );
+    }
+
+    #[test]
+    fn lineage_lists_original_and_dispute_events_in_order() {
+        // Make sure the lineage of a disputed deposit contains the deposit, dispute and chargeback in order.
+        let mut transaction_db = TransactionDb::init();
+        let test_transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10.0)).ok()),
+            Transaction::new(TransactionType::Deposit, 1, 2, Amount::new(dec!(5.0)).ok()),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            Transaction::new(TransactionType::Chargeback, 1, 1, None),
+        ];
+        for transaction in test_transactions {
+            transaction_db.insert_transaction(transaction);
+        }
+
+        let lineage: Vec<TransactionType> = transaction_db
+            .lineage(1)
+            .iter()
+            .map(|transaction| transaction.transaction_type)
+            .collect();
+        assert_eq!(
+            lineage,
+            vec![
+                TransactionType::Deposit,
+                TransactionType::Dispute,
+                TransactionType::Chargeback
+            ]
+        );
+        assert!(transaction_db.lineage(3).is_empty());
+    }
+
+    #[test]
+    fn ledger_records_transactions_in_order() -> Result<(), Box<dyn Error>> {
+        // Applied transactions across two clients are recorded in the order applied, each with the
+        // resulting balances of its client. The rejected withdrawal is left out of the ledger.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, 5.0
+                    withdrawal, 1, 3, 2.5
+                    withdrawal, 2, 4, 50.0
+                    dispute, 2, 2,";
+        let config = EngineConfig {
+            record_ledger: true,
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+
+        let order: Vec<(u16, u32, TransactionType)> = stats
+            .ledger
+            .iter()
+            .map(|entry| {
+                (
+                    entry.client_id,
+                    entry.transaction_id,
+                    entry.transaction_type,
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (1, 1, TransactionType::Deposit),
+                (2, 2, TransactionType::Deposit),
+                (1, 3, TransactionType::Withdrawal),
+                (2, 2, TransactionType::Dispute),
+            ]
+        );
+
+        let mut written = Vec::new();
+        stats.write_ledger(&mut written)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,tx,type,amount,available,held,total,locked\n\
+             1,1,deposit,10,10,0,10,false\n\
+             2,2,deposit,5,5,0,5,false\n\
+             1,3,withdrawal,2.5,7.5,0,7.5,false\n\
+             2,2,dispute,5,0,5,5,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ledger_not_recorded_by_default() -> Result<(), Box<dyn Error>> {
+        // Ledger entries are only recorded when a ledger was asked for.
+        let stats = apply_csv(
+            "type, client, tx, amount\ndeposit, 1, 1, 10.0",
+            &EngineConfig::default(),
+        )?;
+        assert!(stats.ledger.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn ledger_records_corrections() -> Result<(), Box<dyn Error>> {
+        // A correction is journalled with the amount it corrected the deposit to and the resulting balances,
+        // after the original deposit, and a later dispute holds the corrected amount.
+        let data = "type, client, tx, amount, reason
+                    deposit, 1, 1, 100.0,
+                    correction, 1, 1, 90.0, keying error
+                    dispute, 1, 1, ,";
+        let config = EngineConfig {
+            record_ledger: true,
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+        let ledger: Vec<_> = stats
+            .ledger
+            .iter()
+            .map(|entry| (entry.transaction_type, entry.amount, entry.available))
+            .collect();
+        assert_eq!(
+            ledger,
+            [
+                (
+                    TransactionType::Deposit,
+                    Amount::new(dec!(100)).ok(),
+                    dec!(100)
+                ),
+                (
+                    TransactionType::Correction,
+                    Amount::new(dec!(90)).ok(),
+                    dec!(90)
+                ),
+                (
+                    TransactionType::Dispute,
+                    Amount::new(dec!(90)).ok(),
+                    dec!(0)
+                ),
+            ]
+        );
+        assert_eq!(stats.summary.corrections, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_transaction_id_keeps_original() -> Result<(), Box<dyn Error>> {
+        // A second deposit reusing tx 1 is rejected as a duplicate. The first deposit is retained, so
+        // the dispute holds its amount, and the duplicate never reaches the balances.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 1, 25.0
+                    dispute, 1, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(
+            stats.rejections,
+            vec![Rejection {
+                line: 3,
                 transaction_type: TransactionType::Deposit,
                 client_id: 1,
                 transaction_id: 1,
-                amount: Some(10.0),
+                error: TransactionError::DuplicateTransactionId {
+                    transaction_type: TransactionType::Deposit,
+                    transaction_id: 1,
+                },
+            }]
+        );
+        assert_eq!(
+            transaction_db.disputed_amount(1),
+            Some(Amount::new(dec!(10))?)
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(0));
+        assert_eq!(client_record.held, dec!(10));
+        assert_eq!(client_record.total, dec!(10));
+
+        // Inserting the duplicate directly is refused in the same way.
+        assert!(!transaction_db.insert_transaction(Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Amount::new(dec!(25))?),
+        )));
+        assert_eq!(
+            transaction_db.disputed_amount(1),
+            Some(Amount::new(dec!(10))?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rounding_residues_captured_in_suspense_account() -> Result<(), Box<dyn Error>> {
+        // Amounts are rounded to 4.d.p as they are read. What rounding takes off deposits (and adds to
+        // withdrawals) is credited to the suspense account, so the grand total across every client equals
+        // the unrounded deposits less the unrounded withdrawals.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 1.00004
+                    deposit, 1, 2, 1.00004
+                    deposit, 1, 3, 1.00004
+                    deposit, 2, 4, 2.00006
+                    withdrawal, 1, 5, 0.50004";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            suspense_account: Some(u16::MAX),
+            ..EngineConfig::default()
+        };
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+
+        assert_eq!(stats.rounding_residue, dec!(0.00004));
+        // Unwrap used here as we can say for certainty that the suspense record with id=u16::MAX exists
+        let suspense = client_db.get_client_record(&u16::MAX).unwrap();
+        assert_eq!(suspense.available, dec!(0.00004));
+        assert_eq!(suspense.total, dec!(0.00004));
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().total, dec!(2.5));
+        assert_eq!(client_db.total_available(), Some(dec!(4.50014)));
+        assert!(client_db.audit().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_read_with_rounding_mode() -> Result<(), Box<dyn Error>> {
+        // Under Truncate the digits beyond 4.d.p are dropped as the amount is read, rather than rounded up.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 1.23459";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            rounding: RoundingMode::Truncate,
+            ..EngineConfig::default()
+        };
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(1.2345));
+        assert_eq!(client_record.total, dec!(1.2345));
+        Ok(())
+    }
+
+    // Custom rounding always rounding away from zero, as a library embedder might supply.
+    fn round_up(value: Decimal, decimal_places: u32) -> Decimal {
+        value.round_dp_with_strategy(decimal_places, rust_decimal::RoundingStrategy::AwayFromZero)
+    }
+
+    #[test]
+    fn custom_rounding_used_for_input_and_output() -> Result<(), Box<dyn Error>> {
+        // A custom rounding function rounds the deposit of 1.00001 up to 1.0001 as it is read, where the
+        // default would round it down to 1, then rounds the balance up to 1.01 when written to 2.d.p.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 1.00001";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            rounding: RoundingMode::Custom(round_up),
+            ..EngineConfig::default()
+        };
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(
+            client_db.get_client_record(&1).unwrap().available,
+            dec!(1.0001)
+        );
+
+        let output = OutputConfig {
+            rounding: RoundingMode::Custom(round_up),
+            precision: FieldPrecision {
+                available: 2,
+                held: 2,
+                total: 2,
             },
-            Transaction {
+            ..OutputConfig::default()
+        };
+        let mut written = Vec::new();
+        client_db.write_output(&mut written, &output)?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "client,available,held,total,locked\n1,1.01,0.0,1.01,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn transactions_applied_from_memory() -> Result<(), Box<dyn Error>> {
+        // Transactions passed directly, without a csv, are applied as the equivalent rows would be, with
+        // rejections reported against their position in the iterator.
+        let transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10)).ok()),
+            Transaction::new(TransactionType::Deposit, 2, 2, Amount::new(dec!(5)).ok()),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Amount::new(dec!(4)).ok()),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                2,
+                4,
+                Amount::new(dec!(50)).ok(),
+            ),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+        ];
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions_iter(
+            transactions,
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(stats.rows_read, 5);
+        assert_eq!(stats.rejections.len(), 1);
+        assert_eq!(stats.rejections[0].line, 4);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(6));
+        assert_eq!(client_record.total, dec!(6));
+        // Unwrap used here as we can say for certainty that the client record with id=2_u16 exists
+        let client_record = client_db.get_client_record(&2).unwrap();
+        assert_eq!(client_record.available, dec!(0));
+        assert_eq!(client_record.held, dec!(5));
+        assert_eq!(client_record.total, dec!(5));
+        Ok(())
+    }
+
+    #[test]
+    fn summary_counts_applied_transactions() -> Result<(), Box<dyn Error>> {
+        // Only applied transactions are counted by type, so the rejected withdrawal is left out. The clients
+        // and their balances are summarised once the run finishes.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, 5.0
+                    deposit, 1, 3, 2.5
+                    withdrawal, 1, 4, 4.0
+                    withdrawal, 2, 5, 50.0
+                    dispute, 2, 2,";
+        let stats = apply_csv(data, &EngineConfig::default())?;
+
+        assert_eq!(
+            stats.summary,
+            ProcessingSummary {
+                deposits: 3,
+                withdrawals: 1,
+                disputes: 1,
+                clients: 2,
+                total_available: Some(dec!(8.5)),
+                total_held: Some(dec!(5)),
+                ..ProcessingSummary::default()
+            }
+        );
+        assert_eq!(
+            stats.summary.to_string(),
+            "applied 3 deposits, 1 withdrawals, 1 disputes, 0 resolves, 0 chargebacks, 0 releases, 0 \
+             unlocks and 0 corrections; 2 clients with 8.5 available and 5 held"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn run_report_of_mixed_file() -> Result<(), Box<dyn Error>> {
+        // A file mixing applied rows, a rejection and a malformed row is reported in full by the returned
+        // stats: every row read, the rejection with its line and reason, the skipped row, and the summary.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, 3.0
+                    withdrawal, 2, 3, 5.0
+                    dispute, 1, 1,
+                    deposit, x, 4, 1.0
+                    resolve, 1, 1,";
+        let stats = apply_csv(data, &EngineConfig::default())?;
+
+        assert_eq!(stats.rows_read, 6);
+        assert!(!stats.truncated);
+        assert_eq!(
+            stats.rejections,
+            vec![Rejection {
+                line: 4,
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 2,
+                transaction_id: 3,
+                error: TransactionError::InsufficientFunds {
+                    client_id: 2,
+                    amount: dec!(5),
+                    available: dec!(3),
+                },
+            }]
+        );
+        assert_eq!(stats.malformed_rows.len(), 1);
+        assert_eq!(
+            stats.summary,
+            ProcessingSummary {
+                deposits: 2,
+                disputes: 1,
+                resolves: 1,
+                clients: 2,
+                total_available: Some(dec!(13)),
+                total_held: Some(dec!(0)),
+                ..ProcessingSummary::default()
+            }
+        );
+        Ok(())
+    }
+
+    // Both databases held in one shared state struct, as a server would, applying each transaction by looking
+    // up the referenced transaction and only then borrowing the client record for mutation.
+    struct SharedState {
+        client_db: client::ClientDb,
+        transaction_db: TransactionDb,
+    }
+
+    impl SharedState {
+        fn apply(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+            let config = EngineConfig::default();
+            let referenced = transaction.lookup_reference(&self.transaction_db, &config)?;
+            let client = self
+                .client_db
+                .get_or_create_client_record(transaction.client_id);
+            let held_before = client.held();
+            client.apply_transaction_to_client(&transaction, referenced.as_ref(), &config)?;
+            let held = client.held() - held_before;
+            self.client_db.mark_modified(transaction.client_id);
+            self.transaction_db.update_dispute_state(&transaction, held);
+            self.transaction_db.insert_transaction(transaction);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shared_state_flow_matches_handle_transaction() -> Result<(), Box<dyn Error>> {
+        // Applying a mixed sequence through the shared state gives the same result for every transaction, and
+        // the same balances, as handle_transaction with separately borrowed databases.
+        let transactions = || {
+            let mut correction =
+                Transaction::new(TransactionType::Correction, 1, 1, Amount::new(dec!(8)).ok());
+            correction.reason = Some("keying error".to_string());
+            vec![
+                Transaction::new(TransactionType::Deposit, 1, 1, Amount::new(dec!(10)).ok()),
+                Transaction::new(TransactionType::Deposit, 2, 2, Amount::new(dec!(5)).ok()),
+                Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    3,
+                    Amount::new(dec!(20)).ok(),
+                ),
+                correction,
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                Transaction::new(TransactionType::Dispute, 2, 1, None),
+                Transaction::new(TransactionType::Resolve, 1, 1, None),
+                Transaction::new(TransactionType::Dispute, 2, 2, None),
+                Transaction::new(TransactionType::Chargeback, 2, 2, None),
+                Transaction::new(TransactionType::Deposit, 2, 4, Amount::new(dec!(1)).ok()),
+            ]
+        };
+
+        let mut state = SharedState {
+            client_db: client::ClientDb::init(),
+            transaction_db: TransactionDb::init(),
+        };
+        let mut client_db = client::ClientDb::init();
+        let mut transaction_db = TransactionDb::init();
+        for (transaction, shared) in transactions().into_iter().zip(transactions()) {
+            let handled = transaction.handle_transaction(
+                &mut transaction_db,
+                &mut client_db,
+                &EngineConfig::default(),
+            );
+            transaction_db.insert_transaction(transaction);
+            assert_eq!(state.apply(shared), handled);
+        }
+
+        let written = |client_db: &client::ClientDb| -> Result<String, Box<dyn Error>> {
+            let mut written = Vec::new();
+            client_db.write_output(&mut written, &OutputConfig::default())?;
+            Ok(String::from_utf8(written)?)
+        };
+        assert_eq!(written(&state.client_db)?, written(&client_db)?);
+        assert_eq!(
+            written(&client_db)?,
+            "client,available,held,total,locked\n1,8.0,0.0,8.0,false\n2,0.0,0.0,0.0,true\n"
+        );
+        Ok(())
+    }
+
+    // Helper applying in-memory csv data with the given config.
+    fn apply_csv(data: &str, config: &EngineConfig) -> Result<ProcessingStats, Box<dyn Error>> {
+        apply_csv_to_dbs(data, config).map(|(stats, _)| stats)
+    }
+
+    // Applies the csv to empty databases, returning the processing stats along with the resulting clients.
+    fn apply_csv_to_dbs(
+        data: &str,
+        config: &EngineConfig,
+    ) -> Result<(ProcessingStats, client::ClientDb), Box<dyn Error>> {
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats =
+            apply_transactions(vec![rdr], &mut transaction_db, &mut client_db, config, None)?;
+        Ok((stats, client_db))
+    }
+
+    // Config aborting the run on the first malformed row.
+    fn strict_config() -> EngineConfig {
+        EngineConfig {
+            strict: true,
+            ..EngineConfig::default()
+        }
+    }
+
+    #[test]
+    fn empty_client_column_reports_missing_client_id() {
+        // Make sure an empty client column names the problem and the line it occurred on.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, , 2, 10.0";
+        let err =
+            apply_csv(data, &strict_config()).expect_err("empty client id should be reported");
+        assert_eq!(err.to_string(), "missing client id on line 3");
+    }
+
+    #[test]
+    fn empty_tx_column_reports_missing_tx_id() {
+        // Make sure an empty tx column names the problem and the line it occurred on.
+        let data = "type, client, tx, amount
+                    deposit, 1, , 10.0";
+        let err = apply_csv(data, &strict_config()).expect_err("empty tx id should be reported");
+        assert_eq!(err.to_string(), "missing tx id on line 2");
+    }
+
+    #[test]
+    fn malformed_row_skipped_by_default() -> Result<(), Box<dyn Error>> {
+        // A garbage row between two valid deposits is skipped and recorded, and both deposits still applied.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    garbage, not, a, row
+                    deposit, 1, 2, 5.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanation = Vec::new();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanation),
+        )?;
+
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().total, dec!(15.0));
+        assert_eq!(stats.rows_read, 3);
+        assert_eq!(stats.malformed_rows.len(), 1);
+        assert!(String::from_utf8(explanation)?.starts_with("skipped malformed row: "));
+        assert_eq!(
+            stats.malformed_warning().as_deref(),
+            Some("warning: 1 malformed rows were skipped")
+        );
+
+        // Under --strict the same row aborts the run.
+        assert!(apply_csv(data, &strict_config()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_references_reports_dangling_dispute() -> Result<(), Box<dyn Error>> {
+        // A dispute before its deposit is fine, but one referencing a tx that never appears is reported.
+        let data = "type, client, tx, amount
+                    dispute, 1, 1,
+                    deposit, 1, 1, 10.0
+                    dispute, 1, 7,
+                    resolve, 1, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let dangling =
+            check_references(vec![rdr], &TransactionDb::init(), &EngineConfig::default())?;
+        assert_eq!(
+            dangling,
+            vec![DanglingReference {
+                line: 4,
+                transaction_type: TransactionType::Dispute,
+                transaction_id: 7,
+            }]
+        );
+        assert_eq!(
+            dangling[0].to_string(),
+            "line 4: dispute references unknown tx 7"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_references_ignores_clock_and_unlock_rows() -> Result<(), Box<dyn Error>> {
+        // Clock and unlock rows reference no transaction, so their tx ids are never reported as dangling.
+        let data = "type, client, tx, amount, timestamp
+                    deposit, 1, 1, 10.0, 100
+                    clock, 0, 0, , 200
+                    unlock, 1, 9, , 300";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        assert_eq!(
+            check_references(vec![rdr], &TransactionDb::init(), &EngineConfig::default())?,
+            vec![]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_references_skips_malformed_rows() -> Result<(), Box<dyn Error>> {
+        // Malformed rows are skipped as they are when applied, so the dangling dispute after them is still
+        // reported. A strict check aborts on the first, as a strict run does.
+        let data = "type, client, tx, amount
+                    bogus, 1, 1, 10.0
+                    deposit, x, 2, 10.0
+                    dispute, 1, 7,";
+        let reader = || {
+            ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(data.as_bytes())
+        };
+        let dangling = check_references(
+            vec![reader()],
+            &TransactionDb::init(),
+            &EngineConfig::default(),
+        )?;
+        assert_eq!(
+            dangling,
+            vec![DanglingReference {
+                line: 4,
+                transaction_type: TransactionType::Dispute,
+                transaction_id: 7,
+            }]
+        );
+        assert!(
+            check_references(vec![reader()], &TransactionDb::init(), &strict_config()).is_err()
+        );
+        Ok(())
+    }
+
+    // Reader returning its first chunk immediately and the rest only after a delay, like a stalled upstream.
+    struct SlowReader {
+        chunks: Vec<&'static [u8]>,
+        delay: std::time::Duration,
+    }
+
+    impl io::Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.chunks.is_empty() {
+                return Ok(0);
+            }
+            if self.chunks.len() == 1 {
+                std::thread::sleep(self.delay);
+            }
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn max_runtime_truncates_with_partial_output() -> Result<(), Box<dyn Error>> {
+        // The first two rows arrive before the deadline, the last only after it, so only the first two are applied.
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(SlowReader {
+                chunks: vec![
+                    b"type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n",
+                    b"deposit,3,3,1.0\n",
+                ],
+                delay: std::time::Duration::from_millis(100),
+            });
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            max_runtime: Some(std::time::Duration::from_millis(50)),
+            ..EngineConfig::default()
+        };
+
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+        assert!(stats.truncated);
+        assert_eq!(stats.rows_read, 2);
+        assert!(client_db.get_client_record(&2).is_some());
+        assert!(client_db.get_client_record(&3).is_none());
+        assert_eq!(
+            stats.truncation_warning().as_deref(),
+            Some(
+                "warning: maximum runtime exceeded after 2 rows, output reflects a truncated input"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_fields_rejected_when_strict() {
+        // Make sure a row with a trailing extra column is reported with its line number.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0, extra";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let result = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &strict_config(),
+            None,
+        );
+        let err = result.expect_err("extra field should be reported");
+        assert_eq!(err.to_string(), "too many fields on line 2");
+    }
+
+    #[test]
+    fn too_many_fields_ignored_when_configured() -> Result<(), Box<dyn Error>> {
+        // Make sure extra trailing columns are dropped and the row applied under --ignore-extra-fields.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0, extra, columns
+                    deposit, 1, 2, 5.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            ignore_extra_fields: true,
+            ..EngineConfig::default()
+        };
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().total, dec!(15.0));
+        Ok(())
+    }
+
+    // Apply a sub-precision deposit followed by a normal one, returning the client db and any explanations.
+    fn apply_subprecision_deposit(
+        reject_subprecision: bool,
+    ) -> Result<(client::ClientDb, String), Box<dyn Error>> {
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 0.00001
+                    deposit, 1, 2, 2.5";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            reject_subprecision,
+            ..EngineConfig::default()
+        };
+        let mut explanations = Vec::new();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            Some(&mut explanations),
+        )?;
+        Ok((client_db, String::from_utf8(explanations)?))
+    }
+
+    #[test]
+    fn subprecision_deposit_accepted_as_zero_by_default() -> Result<(), Box<dyn Error>> {
+        // By default the deposit rounds to zero and is applied without changing the balance.
+        let (mut client_db, explanations) = apply_subprecision_deposit(false)?;
+        assert_eq!(explanations, "");
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(
+            client_db.get_client_record(&1).unwrap().available,
+            dec!(2.5)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subprecision_deposit_rejected_when_configured() -> Result<(), Box<dyn Error>> {
+        // Under --reject-subprecision the deposit is rejected and explained, and later rows still apply.
+        let (mut client_db, explanations) = apply_subprecision_deposit(true)?;
+        assert_eq!(
+            explanations,
+            "line 2: deposit for client 1 rejected, amount is below 4 decimal place precision\n"
+        );
+        // The precision named is the one amounts are read with.
+        let err = TransactionError::SubprecisionAmount {
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            decimal_places: 8,
+        };
+        assert_eq!(
+            err.to_string(),
+            "deposit for client 1 rejected, amount is below 8 decimal place precision"
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(
+            client_db.get_client_record(&1).unwrap().available,
+            dec!(2.5)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_with_too_many_significant_digits_rejected() -> Result<(), Box<dyn Error>> {
+        // With a maximum of 12 significant digits, a deposit written with 18 is rejected however it would
+        // round, while one with exactly 12 is applied.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 12345678901234.5678
+                    deposit, 1, 2, 12345678.9012";
+        let config = EngineConfig {
+            max_significant_digits: Some(12),
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+        assert_eq!(
+            stats
+                .rejections
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "line 2: deposit for client 1 rejected, amount 12345678901234.5678 has more than 12 \
+                 significant digits"
+            ]
+        );
+        assert_eq!(stats.summary.total_available, Some(dec!(12345678.9012)));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_chargeback_leaves_remainder_disputable() -> Result<(), Box<dyn Error>> {
+        // Charging back a dispute of 40 of a deposit of 100 leaves the other 60 available and, when only
+        // full chargebacks lock the account, still disputable. Charging that back too locks the account.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 100.0
+                    dispute, 1, 1, 40.0
+                    chargeback, 1, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            chargeback_locks: ChargebackLocks::Full,
+            ..EngineConfig::default()
+        };
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(
+            (client_record.available, client_record.total),
+            (dec!(60.0), dec!(60.0))
+        );
+        assert!(!client_record.locked);
+        assert_eq!(
+            transaction_db.disputable_amount(1),
+            Amount::new(dec!(60)).ok()
+        );
+
+        let data = "type, client, tx, amount
+                    dispute, 1, 1,
+                    chargeback, 1, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut explanations = Vec::new();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            Some(&mut explanations),
+        )?;
+        assert_eq!(String::from_utf8(explanations)?, "");
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(
+            (
+                client_record.available,
+                client_record.held,
+                client_record.total
+            ),
+            (dec!(0.0), dec!(0.0), dec!(0.0))
+        );
+        assert!(client_record.locked);
+        assert_eq!(transaction_db.disputable_amount(1), Some(Amount::ZERO));
+        Ok(())
+    }
+
+    #[test]
+    fn negative_amounts_rejected() -> Result<(), Box<dyn Error>> {
+        // Negative amounts are not valid amounts, so a negative deposit cannot be used to debit the account.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, -4.0
+                    withdrawal, 1, 3, -1.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanations = Vec::new();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanations),
+        )?;
+        assert_eq!(
+            String::from_utf8(explanations)?,
+            "line 3: deposit for client 1 rejected, amount -4 is negative\n\
+             line 4: withdrawal for client 1 rejected, amount -1 is negative\n"
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().total, dec!(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn non_finite_amounts_rejected() -> Result<(), Box<dyn Error>> {
+        // A NaN or infinite amount is rejected as malformed, leaving the account unchanged.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, NaN
+                    withdrawal, 1, 3, inf";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanations = Vec::new();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanations),
+        )?;
+        assert_eq!(
+            String::from_utf8(explanations)?,
+            "line 3: deposit for client 1 rejected, \"NaN\" is not a decimal amount\n\
+             line 4: withdrawal for client 1 rejected, \"inf\" is not a decimal amount\n"
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(10.0));
+        assert_eq!(client_record.total, dec!(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_deposit_amount_is_one_rejection() -> Result<(), Box<dyn Error>> {
+        // A deposit with an empty amount column is recorded as exactly one MissingAmount rejection and changes
+        // nothing, while a dispute with no amount is applied as normal.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2,
+                    dispute, 1, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(stats.rejections.len(), 1);
+        assert_eq!(stats.rejections[0].line, 3);
+        assert_eq!(
+            stats.rejections[0].error,
+            TransactionError::MissingAmount {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+            }
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.total, dec!(10.0));
+        assert_eq!(client_record.held, dec!(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn degenerate_amounts_rejected_as_malformed() -> Result<(), Box<dyn Error>> {
+        // Amounts of a lone `.`, `-` or `+`, or with digit separators, are reported as malformed rather than as
+        // missing or as the digits alone, while a genuinely empty amount is still reported as missing.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, .
+                    withdrawal, 1, 3, -
+                    deposit, 1, 4, +
+                    deposit, 1, 5,
+                    deposit, 1, 6, 1_000";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanations = Vec::new();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanations),
+        )?;
+        assert_eq!(
+            String::from_utf8(explanations)?,
+            "line 3: deposit for client 1 rejected, \".\" is not a decimal amount\n\
+             line 4: withdrawal for client 1 rejected, \"-\" is not a decimal amount\n\
+             line 5: deposit for client 1 rejected, \"+\" is not a decimal amount\n\
+             line 6: deposit for client 1 rejected, no valid amount supplied\n\
+             line 7: deposit for client 1 rejected, \"1_000\" is not a decimal amount\n"
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().total, dec!(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn only_disputes_applied_against_loaded_history() -> Result<(), Box<dyn Error>> {
+        // Disputes-only run against history loaded from an earlier run: deposits in the filtered file are
+        // skipped and not recorded, so only disputes of the loaded deposits take effect.
+        let history = "type, client, tx, amount
+                       deposit, 1, 1, 10.0
+                       deposit, 2, 2, 20.0";
+        let data = "type, client, tx, amount
+                    deposit, 1, 3, 5.0
+                    dispute, 1, 1,
+                    dispute, 1, 3,
+                    withdrawal, 2, 4, 20.0
+                    dispute, 2, 2,";
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        apply_transactions(
+            vec![ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(history.as_bytes())],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        let config = EngineConfig {
+            only_types: vec![TransactionType::Dispute],
+            ..EngineConfig::default()
+        };
+        let mut explanation = Vec::new();
+        apply_transactions(
+            vec![ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(data.as_bytes())],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            Some(&mut explanation),
+        )?;
+        assert_eq!(
+            String::from_utf8(explanation)?,
+            "line 4: dispute rejected, tx 3 does not exist\n"
+        );
+        assert!(transaction_db.retrieve_transaction_data(&3).is_none());
+        assert!(transaction_db.retrieve_transaction_data(&4).is_none());
+        // Unwrap used here as we can say for certainty that the client records with id=1_u16 and 2_u16 exist
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(
+            (client_record.available, client_record.held),
+            (dec!(0.0), dec!(10.0))
+        );
+        let client_record = client_db.get_client_record(&2).unwrap();
+        assert_eq!(
+            (client_record.available, client_record.held),
+            (dec!(0.0), dec!(20.0))
+        );
+        Ok(())
+    }
+
+    // Apply a run with rejections across several clients to fresh databases, returning the explanations
+    // and the written client csv.
+    #[cfg(feature = "deterministic")]
+    fn explained_run() -> Result<(String, String), Box<dyn Error>> {
+        let data = "type, client, tx, amount
+                    deposit, 7, 1, 5.0
+                    deposit, 3, 2, 1.0
+                    withdrawal, 9, 3, 2.0
+                    deposit, 1, 4, 4.0
+                    withdrawal, 3, 5, 8.0
+                    resolve, 7, 1,
+                    chargeback, 1, 4,
+                    dispute, 5, 99,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanation = Vec::new();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            Some(&mut explanation),
+        )?;
+        let mut written = Vec::new();
+        client_db.write_csv(&mut written, &crate::config::OutputConfig::default())?;
+        Ok((String::from_utf8(explanation)?, String::from_utf8(written)?))
+    }
+
+    #[test]
+    #[cfg(feature = "deterministic")]
+    fn reports_stable_across_runs_with_ordered_backend() -> Result<(), Box<dyn Error>> {
+        // With the ordered backend repeated runs report rejections and write clients in exactly the same
+        // order, with clients ordered by id.
+        let (explanation, written) = explained_run()?;
+        for _ in 0..10 {
+            assert_eq!(explained_run()?, (explanation.clone(), written.clone()));
+        }
+        assert_eq!(explanation.lines().count(), 5);
+        let clients: Vec<&str> = written
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split(',').next())
+            .collect();
+        assert_eq!(clients, ["1", "3", "5", "7", "9"]);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_reason_appears_in_dispute_report() -> Result<(), Box<dyn Error>> {
+        // Make sure the reason code on an open dispute is reported and resolved disputes are not.
+        let data = "type, client, tx, amount, reason
+                    deposit, 1, 1, 10.0,
+                    deposit, 2, 2, 5.0,
+                    deposit, 2, 3, 7.0,
+                    dispute, 1, 1, , fraud
+                    dispute, 2, 2, ,
+                    dispute, 2, 3, , error
+                    resolve, 2, 3, ,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        let report: Vec<String> = transaction_db
+            .open_disputes()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                "tx 1: client 1 amount 10 reason fraud",
+                "tx 2: client 2 amount 5 reason none",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sharded_files_match_sequential_processing() -> Result<(), Box<dyn Error>> {
+        // Apply two files touching overlapping clients both sequentially and sharded across workers,
+        // and make sure every client ends up with the same balances.
+        let first = "type, client, tx, amount
+                     deposit, 1, 1, 10.0
+                     deposit, 2, 2, 20.0
+                     deposit, 3, 3, 30.0
+                     withdrawal, 1, 4, 4.0
+                     dispute, 2, 2,";
+        let second = "type, client, tx, amount
+                      withdrawal, 1, 5, 5.0
+                      chargeback, 2, 2,
+                      deposit, 2, 6, 1.0
+                      dispute, 3, 3,
+                      resolve, 3, 3,
+                      withdrawal, 3, 7, 25.0
+                      deposit, 4, 8, 2.0";
+        let readers = || {
+            [first, second]
+                .iter()
+                .map(|data| {
+                    ReaderBuilder::new()
+                        .trim(Trim::All)
+                        .from_reader(data.as_bytes())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut sequential_transaction_db = TransactionDb::init();
+        let mut sequential_client_db = client::ClientDb::init();
+        let sequential_stats = apply_transactions(
+            readers(),
+            &mut sequential_transaction_db,
+            &mut sequential_client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+        let mut sharded_transaction_db = TransactionDb::init();
+        let mut sharded_client_db = client::ClientDb::init();
+        let stats = apply_transactions_sharded(
+            readers(),
+            3,
+            &mut sharded_transaction_db,
+            &mut sharded_client_db,
+            &EngineConfig::default(),
+        )?;
+
+        assert_eq!(stats.rows_read, 12);
+        assert_eq!(sharded_client_db.client_records().count(), 4);
+        for client_id in 1..=4 {
+            // Unwrap used here as we can say for certainty that client records with ids 1 to 4 exist
+            let sequential = sequential_client_db.get_client_record(&client_id).unwrap();
+            let (available, held, total, locked) = (
+                sequential.available,
+                sequential.held,
+                sequential.total,
+                sequential.locked,
+            );
+            let sharded = sharded_client_db.get_client_record(&client_id).unwrap();
+            assert_eq!(
+                (
+                    sharded.available,
+                    sharded.held,
+                    sharded.total,
+                    sharded.locked
+                ),
+                (available, held, total, locked)
+            );
+        }
+        assert_eq!(sharded_transaction_db.lineage(2).len(), 3);
+        assert_eq!(stats.rejections, sequential_stats.rejections);
+        Ok(())
+    }
+
+    #[test]
+    fn sharded_duplicate_ids_match_sequential_processing() -> Result<(), Box<dyn Error>> {
+        // Reuse tx ids across clients owned by different workers, including an id whose first use was a rejected
+        // withdrawal and so is free to be reused, and an id already stored before the run. Sharded processing
+        // must reject the same duplicates as sequential processing and end with the same balances.
+        let first = "type, client, tx, amount
+                     deposit, 1, 1, 10.0
+                     withdrawal, 2, 2, 5.0
+                     deposit, 3, 2, 7.0
+                     deposit, 2, 1, 4.0
+                     deposit, 2, 100, 1.0";
+        let second = "type, client, tx, amount
+                      deposit, 3, 1, 1.0
+                      withdrawal, 1, 2, 1.0
+                      deposit, 2, 9, 3.0
+                      dispute, 2, 9,";
+        let readers = || {
+            [first, second]
+                .iter()
+                .map(|data| {
+                    ReaderBuilder::new()
+                        .trim(Trim::All)
+                        .from_reader(data.as_bytes())
+                })
+                .collect::<Vec<_>>()
+        };
+        let stored = || {
+            let mut transaction_db = TransactionDb::init();
+            transaction_db.insert_transaction(Transaction::new(
+                TransactionType::Deposit,
+                3,
+                100,
+                Amount::new(dec!(1)).ok(),
+            ));
+            transaction_db
+        };
+        let rejected_rows = |stats: &ProcessingStats| {
+            let mut rows: Vec<(u64, u16, u32)> = stats
+                .rejections
+                .iter()
+                .map(|rejection| {
+                    (
+                        rejection.line,
+                        rejection.client_id,
+                        rejection.transaction_id,
+                    )
+                })
+                .collect();
+            rows.sort_unstable();
+            rows
+        };
+
+        let mut sequential_transaction_db = stored();
+        let mut sequential_client_db = client::ClientDb::init();
+        let sequential_stats = apply_transactions(
+            readers(),
+            &mut sequential_transaction_db,
+            &mut sequential_client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+        let mut sharded_transaction_db = stored();
+        let mut sharded_client_db = client::ClientDb::init();
+        let stats = apply_transactions_sharded(
+            readers(),
+            3,
+            &mut sharded_transaction_db,
+            &mut sharded_client_db,
+            &EngineConfig::default(),
+        )?;
+
+        assert_eq!(
+            rejected_rows(&sequential_stats),
+            [(2, 3, 1), (3, 1, 2), (3, 2, 2), (5, 2, 1), (6, 2, 100)]
+        );
+        assert_eq!(rejected_rows(&stats), rejected_rows(&sequential_stats));
+        assert!(stats.rejections.iter().all(|rejection| matches!(
+            rejection.error,
+            TransactionError::DuplicateTransactionId { .. }
+                | TransactionError::InsufficientFunds { .. }
+        )));
+        for client_id in 1..=3 {
+            // Unwrap used here as we can say for certainty that client records with ids 1 to 3 exist
+            let sequential = sequential_client_db.get_client_record(&client_id).unwrap();
+            let (available, held, total) =
+                (sequential.available, sequential.held, sequential.total);
+            let sharded = sharded_client_db.get_client_record(&client_id).unwrap();
+            assert_eq!(
+                (sharded.available, sharded.held, sharded.total),
+                (available, held, total)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_reports_colliding_ids() {
+        // A transaction id stored by both databases is reported and keeps the original transaction, while the
+        // other transactions are moved across.
+        let deposit = |client_id, transaction_id, amount| {
+            Transaction::new(
+                TransactionType::Deposit,
+                client_id,
+                transaction_id,
+                Amount::new(amount).ok(),
+            )
+        };
+        let mut transaction_db = TransactionDb::init();
+        transaction_db.insert_transaction(deposit(1, 1, dec!(10)));
+        let mut other = TransactionDb::init();
+        other.insert_transaction(deposit(2, 1, dec!(20)));
+        other.insert_transaction(deposit(2, 2, dec!(5)));
+        assert_eq!(transaction_db.merge(other), vec![1]);
+        // Unwrap used here as we can say for certainty that the transaction with id=1_u32 exists
+        assert_eq!(
+            transaction_db
+                .retrieve_transaction_data(&1)
+                .unwrap()
+                .client_id,
+            1
+        );
+        assert_eq!(transaction_db.iter_in_order().count(), 2);
+    }
+
+    #[test]
+    fn deposit_on_unlocked_account_warns_when_configured() -> Result<(), Box<dyn Error>> {
+        // Make sure activity on an account that was charged back and then unlocked is flagged, and only then.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, 5.0
+                    dispute, 1, 1,
+                    chargeback, 1, 1,
+                    deposit, 1, 3, 1.0
+                    unlock, 1, 1,
+                    deposit, 1, 4, 2.0
+                    deposit, 2, 5, 2.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            warn_reactivated: true,
+            ..EngineConfig::default()
+        };
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+        assert_eq!(
+            stats
+                .alerts
+                .iter()
+                .map(|alert| alert.to_string())
+                .collect::<Vec<_>>(),
+            vec!["warning: line 8: deposit applied to client 1, which was previously locked"]
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert!(!client_record.locked);
+        assert_eq!(client_record.total, dec!(7.0));
+        Ok(())
+    }
+
+    #[test]
+    fn future_dated_deposit_applies_once_clock_advances() -> Result<(), Box<dyn Error>> {
+        // A deposit dated after the processing clock is held until a clock row reaches its timestamp,
+        // and one still held at the end of the run is left unapplied.
+        let config = EngineConfig {
+            defer_future_dated: true,
+            processing_clock: Some(1_000),
+            ..EngineConfig::default()
+        };
+        let data = "type, client, tx, amount, timestamp
+                    deposit, 1, 1, 10.0, 900
+                    deposit, 1, 2, 5.0, 2000
+                    withdrawal, 1, 3, 12.0, 1000
+                    deposit, 1, 4, 1.0, 9000
+                    clock, 0, 0, , 2500
+                    withdrawal, 1, 5, 12.0, 2500";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let mut explanation = Vec::new();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            Some(&mut explanation),
+        )?;
+
+        // The first withdrawal is rejected as the future-dated deposit was not yet effective.
+        assert_eq!(
+            String::from_utf8(explanation)?,
+            "line 4: withdrawal of 12 rejected, client 1 has only 10 available\n"
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        assert_eq!(client_db.get_client_record(&1).unwrap().total, dec!(3.0));
+        assert!(client_db.get_client_record(&0).is_none());
+        assert_eq!(stats.deferred, 1);
+        assert_eq!(
+            stats.deferred_warning().as_deref(),
+            Some("warning: 1 future-dated transactions were not yet effective and were left unapplied")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_on_locked_account_rejected() -> Result<(), Box<dyn Error>> {
+        // Once a chargeback locks the account, a later deposit is recorded as a single AccountLocked rejection
+        // with its line and tx id, rather than silently dropped, and the balances are left as they were.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, 5.0
+                    dispute, 1, 2,
+                    chargeback, 1, 2,
+                    deposit, 1, 3, 7.0";
+        let stats = apply_csv(data, &EngineConfig::default())?;
+
+        assert_eq!(
+            stats.rejections,
+            vec![Rejection {
+                line: 6,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                transaction_id: 3,
+                error: TransactionError::AccountLocked {
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                },
+            }]
+        );
+        assert_eq!(stats.summary.deposits, 2);
+        assert_eq!(stats.summary.total_available, Some(dec!(10)));
+        assert_eq!(stats.summary.total_held, Some(dec!(0)));
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_transactions_collected() -> Result<(), Box<dyn Error>> {
+        // A withdrawal over the available balance is recorded as a single InsufficientFunds rejection,
+        // with its line and tx id, and leaves the account untouched.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    withdrawal, 1, 2, 15.0";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(
+            stats.rejections,
+            vec![Rejection {
+                line: 3,
                 transaction_type: TransactionType::Withdrawal,
                 client_id: 1,
                 transaction_id: 2,
-                amount: Some(5.0),
+                error: TransactionError::InsufficientFunds {
+                    client_id: 1,
+                    amount: dec!(15),
+                    available: dec!(10),
+                },
+            }]
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(10.0));
+        assert_eq!(client_record.total, dec!(10.0));
+        assert_eq!(
+            stats.rejection_summary().as_deref(),
+            Some("warning: 1 transactions were rejected and left unapplied")
+        );
+        let mut rejects = Vec::new();
+        stats.write_rejections(&mut rejects)?;
+        assert_eq!(
+            String::from_utf8(rejects)?,
+            "line,type,client,tx,reason\n\
+             3,withdrawal,1,2,\"withdrawal of 15 rejected, client 1 has only 10 available\"\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tracked_transactions_bounded() -> Result<(), Box<dyn Error>> {
+        // Feed more deposits than the maximum tracked: memory stays bounded, the oldest deposits can no
+        // longer be disputed, recent ones still can, and a deposit under dispute is kept until resolved.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 1.0
+                    deposit, 1, 2, 2.0
+                    dispute, 1, 2,
+                    deposit, 1, 3, 3.0
+                    deposit, 1, 4, 4.0
+                    deposit, 1, 5, 5.0
+                    deposit, 1, 6, 6.0
+                    dispute, 1, 1,
+                    dispute, 1, 3,
+                    dispute, 1, 6,
+                    resolve, 1, 2,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::with_max_tracked(3);
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(
+            stats
+                .rejections
+                .iter()
+                .map(|rejection| rejection.transaction_id)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert!(transaction_db.tracked_transactions() <= 3);
+        assert!(transaction_db.retrieve_transaction_data(&6).is_some());
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.held, dec!(6.0));
+        assert_eq!(client_record.total, dec!(21.0));
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawal_disputes_limited_to_window() -> Result<(), Box<dyn Error>> {
+        // Under a one hour window, a withdrawal disputed exactly an hour after it was made is held, while one
+        // disputed a second later is rejected and its funds are not held.
+        let data = "type, client, tx, amount, timestamp
+                    deposit, 1, 1, 100.0, 0
+                    withdrawal, 1, 2, 10.0, 1000
+                    withdrawal, 1, 3, 20.0, 1000
+                    dispute, 1, 2, , 4600
+                    dispute, 1, 3, , 4601";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let config = EngineConfig {
+            withdrawal_dispute_window: Some(Duration::from_secs(3600)),
+            ..EngineConfig::default()
+        };
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &config,
+            None,
+        )?;
+
+        assert_eq!(
+            transaction_db.dispute_state(2),
+            Some(DisputeState::Disputed)
+        );
+        assert_eq!(transaction_db.dispute_state(3), None);
+        assert_eq!(stats.rejections.len(), 1);
+        assert_eq!(
+            stats.rejections[0].to_string(),
+            "line 6: dispute rejected, withdrawal tx 3 was made 3601s earlier, outside the dispute window of 3600s"
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(70.0));
+        assert_eq!(client_record.held, dec!(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn fractional_dispute_window_not_truncated() -> Result<(), Box<dyn Error>> {
+        // A window of 1.5s allows a dispute 1s after the withdrawal and rejects one 2s after it, explaining the
+        // rejection with the window as given rather than truncated to whole seconds.
+        let data = "type, client, tx, amount, timestamp
+                    deposit, 1, 1, 100.0, 0
+                    withdrawal, 1, 2, 10.0, 1000
+                    withdrawal, 1, 3, 20.0, 1000
+                    dispute, 1, 2, , 1001
+                    dispute, 1, 3, , 1002";
+        let config = EngineConfig {
+            withdrawal_dispute_window: Some(Duration::from_secs_f64(1.5)),
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+        assert_eq!(stats.rejections.len(), 1);
+        assert_eq!(
+            stats.rejections[0].to_string(),
+            "line 6: dispute rejected, withdrawal tx 3 was made 2s earlier, outside the dispute window of 1.5s"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cross_client_claims_touch_neither_client() -> Result<(), Box<dyn Error>> {
+        // While client 1's deposit is under dispute, client 2 tries to resolve and charge it back, and client 3
+        // (otherwise unknown) tries to dispute client 2's deposit. Each is rejected as a client mismatch, both
+        // existing balances are unchanged, and no record is created for client 3.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, 20.0
+                    dispute, 1, 1,
+                    resolve, 2, 1,
+                    chargeback, 2, 1,
+                    dispute, 3, 2,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        let errors: Vec<&TransactionError> = stats
+            .rejections
+            .iter()
+            .map(|rejection| &rejection.error)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                &TransactionError::ClientMismatch {
+                    transaction_type: TransactionType::Resolve,
+                    transaction_id: 1,
+                    client_id: 2,
+                    owner_id: 1,
+                },
+                &TransactionError::ClientMismatch {
+                    transaction_type: TransactionType::Chargeback,
+                    transaction_id: 1,
+                    client_id: 2,
+                    owner_id: 1,
+                },
+                &TransactionError::ClientMismatch {
+                    transaction_type: TransactionType::Dispute,
+                    transaction_id: 2,
+                    client_id: 3,
+                    owner_id: 2,
+                },
+            ]
+        );
+        // Unwrap used here as we can say for certainty that client records with ids 1 and 2 exist
+        for (client_id, available, held) in [(1, dec!(0.0), dec!(10.0)), (2, dec!(20.0), dec!(0.0))]
+        {
+            let client_record = client_db.get_client_record(&client_id).unwrap();
+            assert_eq!(client_record.available, available);
+            assert_eq!(client_record.held, held);
+            assert!(!client_record.locked);
+        }
+        assert!(client_db.get_client_record(&3).is_none());
+        assert_eq!(
+            transaction_db.dispute_state(1),
+            Some(DisputeState::Disputed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_of_another_clients_transaction_rejected() -> Result<(), Box<dyn Error>> {
+        // Client 2 disputes, resolves and charges back client 1's deposit: each is rejected and neither
+        // account changes, and client 1 can still dispute its own deposit afterwards.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, 20.0
+                    dispute, 2, 1,
+                    resolve, 2, 1,
+                    chargeback, 2, 1,";
+        let rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let mut transaction_db = TransactionDb::init();
+        let mut client_db = client::ClientDb::init();
+        let stats = apply_transactions(
+            vec![rdr],
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+            None,
+        )?;
+
+        assert_eq!(stats.rejections.len(), 3);
+        assert_eq!(
+            stats.rejections[0].error,
+            TransactionError::ClientMismatch {
+                transaction_type: TransactionType::Dispute,
+                transaction_id: 1,
+                client_id: 2,
+                owner_id: 1,
+            }
+        );
+        assert_eq!(
+            stats.rejections[0].to_string(),
+            "line 4: dispute of tx 1 by client 2 rejected, tx belongs to client 1"
+        );
+        // Unwrap used here as we can say for certainty that client records with ids 1 and 2 exist
+        for (client_id, total) in [(1, dec!(10.0)), (2, dec!(20.0))] {
+            let client_record = client_db.get_client_record(&client_id).unwrap();
+            assert_eq!(client_record.available, total);
+            assert_eq!(client_record.held, dec!(0.0));
+            assert!(!client_record.locked);
+        }
+        assert_eq!(transaction_db.lineage(1).len(), 1);
+
+        Transaction::new(TransactionType::Dispute, 1, 1, None).handle_transaction(
+            &mut transaction_db,
+            &mut client_db,
+            &EngineConfig::default(),
+        )?;
+        assert_eq!(client_db.get_client_record(&1).unwrap().held, dec!(10.0));
+        Ok(())
+    }
+
+    // Describe every row of in-memory csv data parsed either by hand or by deserialising, for comparison.
+    fn describe_rows(data: &str, config: &EngineConfig, fast_parse: bool) -> Vec<String> {
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let headers = RowHeaders::new(rdr.headers().unwrap().clone());
+        let mut record = StringRecord::new();
+        let mut rows = Vec::new();
+        while rdr.read_record(&mut record).unwrap() {
+            let parsed = match fast_parse {
+                true => headers.parse_fields(&record, 0, config),
+                false => headers.parse_record(record.clone(), 0, config),
+            };
+            rows.push(match parsed {
+                Ok(transaction) => format!(
+                    "{:?}",
+                    (
+                        transaction.transaction_type,
+                        transaction.client_id,
+                        transaction.transaction_id,
+                        transaction.amount,
+                        transaction.seq,
+                        transaction.timestamp,
+                        transaction.reason,
+                        transaction.subprecision,
+                        transaction.invalid_amount,
+                        transaction.rounding_residue,
+                    )
+                ),
+                Err(err) => err.to_string(),
+            });
+        }
+        rows
+    }
+
+    #[test]
+    fn fast_parse_matches_deserialising() {
+        // Make sure parsing fields by hand reads every row, valid or malformed, exactly as deserialising does.
+        let data = "type, client, tx, amount, seq, timestamp, reason
+                    deposit, 1, 1, 10.12345, 1, 100,
+                    Withdrawal, 1, 2, 0.00001, 2, , 
+                    dispute, 1, 1, , , , fraud
+                    correction, 1, 1, 12345678901234.5678, , , typo
+                    deposit, 0x10, 3, 1.0
+                    deposit, 1, 4, .
+                    deposit, 1, 5, -1.0, x
+                    deposit, , 6, 1.0
+                    deposit, 1, , 1.0
+                    deposit, 70000, 7, 1.0
+                    transfer, 1, 8, 1.0
+                    deposit, 1, 9, 1.0, 3, 200, , extra
+                    deposit, 1, 10";
+        let configs = [
+            EngineConfig::default(),
+            EngineConfig {
+                rounding: RoundingMode::Truncate,
+                reject_subprecision: true,
+                max_significant_digits: Some(12),
+                suspense_account: Some(0),
+                ignore_extra_fields: true,
+                ..EngineConfig::default()
             },
         ];
-        let number_of_transactions_to_be_inserted = test_transactions.len();
-        for transaction in test_transactions {
-            transaction_db.insert_transaction(transaction);
+        // Valid rows are read by hand rather than falling back to deserialising.
+        let headers = RowHeaders::new(StringRecord::from(vec!["type", "client", "tx", "amount"]));
+        assert!(headers
+            .read_fields(&StringRecord::from(vec!["deposit", "1", "1", "10.0"]))
+            .is_some());
+        for config in &configs {
+            assert_eq!(
+                describe_rows(data, config, true),
+                describe_rows(data, config, false)
+            );
         }
-        assert!(transaction_db.db.len() == number_of_transactions_to_be_inserted)
+    }
+
+    #[test]
+    fn fast_parse_run_matches_deserialising_run() -> Result<(), Box<dyn Error>> {
+        // Make sure a run parsing fields by hand reports the same rejections, skipped rows and summary.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 2, 2, abc
+                    withdrawal, 1, 3, 20.0
+                    deposit, 2, 4, 5.0, extra
+                    dispute, 1, 1,
+                    chargeback, 1, 1,";
+        let fast = apply_csv(
+            data,
+            &EngineConfig {
+                fast_parse: true,
+                ..EngineConfig::default()
+            },
+        )?;
+        let deserialised = apply_csv(data, &EngineConfig::default())?;
+        assert_eq!(fast.rows_read, deserialised.rows_read);
+        assert_eq!(fast.malformed_rows, deserialised.malformed_rows);
+        assert_eq!(fast.rejections, deserialised.rejections);
+        assert_eq!(fast.summary.to_string(), deserialised.summary.to_string());
+        Ok(())
+    }
+
+    // Applies the csv with and without parsing fields by hand, returning the explanation of each rejection,
+    // which must be the same either way.
+    fn rejections_both_ways(data: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let explain = |fast_parse| -> Result<Vec<String>, Box<dyn Error>> {
+            let config = EngineConfig {
+                fast_parse,
+                ..EngineConfig::default()
+            };
+            let stats = apply_csv(data, &config)?;
+            Ok(stats.rejections.iter().map(Rejection::to_string).collect())
+        };
+        let deserialised = explain(false)?;
+        assert_eq!(explain(true)?, deserialised);
+        Ok(deserialised)
+    }
+
+    #[test]
+    fn amount_with_two_decimal_points_rejected() -> Result<(), Box<dyn Error>> {
+        // `1.2.3` is rejected on a deposit, and on a resolve even though a resolve has no use for an amount.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 1.2.3
+                    deposit, 1, 2, 5.0
+                    dispute, 1, 2,
+                    resolve, 1, 2, 1.2.3";
+        assert_eq!(
+            rejections_both_ways(data)?,
+            [
+                "line 2: deposit for client 1 rejected, \"1.2.3\" is not a decimal amount",
+                "line 5: resolve for client 1 rejected, \"1.2.3\" is not a decimal amount",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn amount_with_dangling_exponent_rejected() -> Result<(), Box<dyn Error>> {
+        // `1e` is rejected on a withdrawal, and on a chargeback, which then neither reverses the deposit nor
+        // locks the account.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 5.0
+                    withdrawal, 1, 2, 1e
+                    dispute, 1, 1,
+                    chargeback, 1, 1, 1e";
+        assert_eq!(
+            rejections_both_ways(data)?,
+            [
+                "line 3: withdrawal for client 1 rejected, \"1e\" is not a decimal amount",
+                "line 5: chargeback for client 1 rejected, \"1e\" is not a decimal amount",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty_amount_not_malformed() -> Result<(), Box<dyn Error>> {
+        // An empty amount is still no amount: a deposit without one is rejected as such rather than as
+        // malformed, and disputes, resolves and chargebacks without one are applied.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1,
+                    deposit, 1, 2, 5.0
+                    deposit, 1, 3, 4.0
+                    dispute, 1, 2,
+                    resolve, 1, 2,
+                    dispute, 1, 3,
+                    chargeback, 1, 3,";
+        assert_eq!(
+            rejections_both_ways(data)?,
+            ["line 2: deposit for client 1 rejected, no valid amount supplied"]
+        );
+        Ok(())
+    }
+
+    // Applies a csv of two deposits to client 1, the second disputed, with and without parsing fields by hand,
+    // checking every row is applied whatever the order of the columns or any extra columns present.
+    fn assert_layout_applied(data: &str) -> Result<(), Box<dyn Error>> {
+        for fast_parse in [false, true] {
+            let stats = apply_csv(
+                data,
+                &EngineConfig {
+                    fast_parse,
+                    ..EngineConfig::default()
+                },
+            )?;
+            assert!(stats.malformed_rows.is_empty());
+            assert!(stats.rejections.is_empty());
+            assert_eq!(
+                stats.summary.to_string(),
+                "applied 2 deposits, 0 withdrawals, 1 disputes, 0 resolves, 0 chargebacks, 0 releases, \
+                 0 unlocks and 0 corrections; 1 clients with 5 available and 2 held"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reordered_columns_parsed_by_name() -> Result<(), Box<dyn Error>> {
+        // Columns are matched by header name, not position.
+        assert_layout_applied(
+            "tx, client, amount, type
+             1, 1, 5.0, deposit
+             2, 1, 2.0, deposit
+             2, 1, , dispute",
+        )
+    }
+
+    #[test]
+    fn unknown_extra_column_ignored() -> Result<(), Box<dyn Error>> {
+        // A column the engine does not know is ignored.
+        assert_layout_applied(
+            "type, client, tx, amount, memo
+             deposit, 1, 1, 5.0, salary
+             deposit, 1, 2, 2.0, refund
+             dispute, 1, 2, , not mine",
+        )
+    }
+
+    #[test]
+    fn timestamp_column_accepted() -> Result<(), Box<dyn Error>> {
+        // A timestamp column is read, but without any timestamp based option it does not change the outcome.
+        assert_layout_applied(
+            "type, client, tx, amount, timestamp
+             deposit, 1, 1, 5.0, 100
+             deposit, 1, 2, 2.0, 200
+             dispute, 1, 2, , 300",
+        )
+    }
+
+    #[test]
+    fn reordered_columns_with_extra_columns_parsed() -> Result<(), Box<dyn Error>> {
+        // Reordered columns interleaved with unknown ones, including before the first known column.
+        assert_layout_applied(
+            "memo, amount, tx, timestamp, type, source, client
+             salary, 5.0, 1, 100, deposit, bank, 1
+             refund, 2.0, 2, 200, deposit, card, 1
+             not mine, , 2, 300, dispute, card, 1",
+        )
+    }
+
+    #[test]
+    fn ignored_resolve_after_chargeback_not_reported() -> Result<(), Box<dyn Error>> {
+        // A resolve after a chargeback is explained as a rejection by default, and skipped silently when
+        // configured to be ignored. Neither counts it as applied.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    dispute, 1, 1,
+                    chargeback, 1, 1,
+                    resolve, 1, 1,";
+        for (resolve_after_chargeback, rejections) in [
+            (ResolveAfterChargeback::Reject, 1),
+            (ResolveAfterChargeback::Ignore, 0),
+        ] {
+            let config = EngineConfig {
+                locked_allows: LockedAllows::Disputes,
+                resolve_after_chargeback,
+                ..EngineConfig::default()
+            };
+            let stats = apply_csv(data, &config)?;
+            assert_eq!(stats.rejections.len(), rejections);
+            assert_eq!(stats.summary.resolves, 0);
+        }
+        let stats = apply_csv(
+            data,
+            &EngineConfig {
+                locked_allows: LockedAllows::Disputes,
+                ..EngineConfig::default()
+            },
+        )?;
+        assert_eq!(
+            stats.rejections[0].to_string(),
+            "line 5: resolve rejected, tx 1 has already been charged back so there is nothing to release"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn transactions_iterated_in_arrival_order() {
+        // Make sure transactions are iterated in the order they arrived in rather than by id, with claims
+        // against them not counted as transactions.
+        let mut transaction_db = TransactionDb::init();
+        for transaction_id in [5, 2, 9] {
+            transaction_db.insert_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                transaction_id,
+                Some(Amount::new(dec!(1.0)).unwrap()),
+            ));
+        }
+        transaction_db.insert_transaction(Transaction::new(TransactionType::Dispute, 1, 5, None));
+        let order: Vec<u32> = transaction_db
+            .iter_in_order()
+            .map(|transaction| transaction.transaction_id)
+            .collect();
+        assert_eq!(order, vec![5, 2, 9]);
+    }
+
+    // Sink capturing every alert it is sent, as a consumer routing alerts elsewhere would receive them.
+    #[derive(Default)]
+    struct CapturingSink {
+        alerts: Vec<Alert>,
+    }
+
+    impl AlertSink for CapturingSink {
+        fn alert(&mut self, alert: &Alert) {
+            self.alerts.push(alert.clone());
+        }
+    }
+
+    #[test]
+    fn alerts_sent_to_sink_in_row_order() -> Result<(), Box<dyn Error>> {
+        // Make sure a run triggering both a burst and activity on a reactivated account sends each alert,
+        // with its structured details, to the sink in the order of the rows that raised them.
+        let data = "type, client, tx, amount, timestamp
+                    deposit, 1, 1, 10.0, 100
+                    dispute, 1, 1, , 110
+                    chargeback, 1, 1, , 120
+                    unlock, 1, 1, , 130
+                    deposit, 1, 2, 5.0, 200
+                    deposit, 2, 3, 1.0, 300";
+        let config = EngineConfig {
+            warn_reactivated: true,
+            burst_limit: Some(BurstLimit {
+                threshold: 3,
+                window: Duration::from_secs(60),
+            }),
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+        let mut sink = CapturingSink::default();
+        stats.send_alerts(&mut sink);
+        assert_eq!(
+            sink.alerts,
+            vec![
+                Alert {
+                    client_id: 1,
+                    line: 5,
+                    kind: AlertKind::Burst {
+                        transactions: 4,
+                        window: Duration::from_secs(60),
+                        threshold: 3,
+                    },
+                },
+                Alert {
+                    client_id: 1,
+                    line: 6,
+                    kind: AlertKind::Reactivated {
+                        transaction_type: TransactionType::Deposit,
+                    },
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_over_maximum_rejected() -> Result<(), Box<dyn Error>> {
+        // Deposits and withdrawals over the maximum are rejected, while those equal to or under it are applied,
+        // and without a maximum any amount is applied.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 1000.0001
+                    deposit, 1, 2, 1000.0
+                    deposit, 1, 3, 999.9999
+                    withdrawal, 1, 4, 1500.0
+                    withdrawal, 1, 5, 1000";
+        let config = EngineConfig {
+            max_amount: Some(dec!(1000)),
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+        let rejected: Vec<(u64, String)> = stats
+            .rejections
+            .iter()
+            .map(|rejection| (rejection.line, rejection.error.to_string()))
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (
+                    2,
+                    "deposit of 1000.0001 for client 1 rejected, amount is over the maximum of 1000"
+                        .to_string()
+                ),
+                (
+                    5,
+                    "withdrawal of 1500 for client 1 rejected, amount is over the maximum of 1000"
+                        .to_string()
+                ),
+            ]
+        );
+        assert!(matches!(
+            stats.rejections[0].error,
+            TransactionError::AmountTooLarge { .. }
+        ));
+        assert_eq!(stats.summary.deposits, 2);
+        assert_eq!(stats.summary.withdrawals, 1);
+
+        let stats = apply_csv(data, &EngineConfig::default())?;
+        assert!(stats.rejections.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn corrections_over_maximum_rejected() -> Result<(), Box<dyn Error>> {
+        // A correction cannot raise a deposit over the maximum, which would apply an amount the deposit itself
+        // could not have, while one correcting it to an amount under the maximum is applied.
+        let data = "type, client, tx, amount, reason
+                    deposit, 1, 1, 500.0,
+                    correction, 1, 1, 5000.0, keying error
+                    correction, 1, 1, 900.0, keying error";
+        let config = EngineConfig {
+            max_amount: Some(dec!(1000)),
+            ..EngineConfig::default()
+        };
+        let stats = apply_csv(data, &config)?;
+        assert_eq!(stats.rejections.len(), 1);
+        assert_eq!(
+            stats.rejections[0].to_string(),
+            "line 3: correction of 5000 for client 1 rejected, amount is over the maximum of 1000"
+        );
+        assert_eq!(stats.summary.corrections, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_withdrawal_cannot_be_disputed() -> Result<(), Box<dyn Error>> {
+        // A withdrawal rejected for insufficient funds is never stored, so disputing and charging it back is
+        // rejected rather than crediting money that never left the account.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    withdrawal, 1, 2, 50.0
+                    dispute, 1, 2,
+                    chargeback, 1, 2,";
+        let (stats, mut client_db) = apply_csv_to_dbs(data, &EngineConfig::default())?;
+        assert_eq!(stats.rejections.len(), 3);
+        assert_eq!(
+            stats.rejections[1].error,
+            TransactionError::UnknownTransaction {
+                transaction_type: TransactionType::Dispute,
+                transaction_id: 2,
+            }
+        );
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(10.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert!(!client_record.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn over_limit_deposit_cannot_be_disputed() -> Result<(), Box<dyn Error>> {
+        // A deposit rejected as over the maximum is never stored, so disputing and charging it back is rejected
+        // rather than taking its amount out of the account.
+        let data = "type, client, tx, amount
+                    deposit, 1, 1, 10.0
+                    deposit, 1, 2, 1000.0
+                    dispute, 1, 2,
+                    chargeback, 1, 2,";
+        let config = EngineConfig {
+            max_amount: Some(dec!(500)),
+            ..EngineConfig::default()
+        };
+        let (stats, mut client_db) = apply_csv_to_dbs(data, &config)?;
+        assert!(matches!(
+            stats.rejections[0].error,
+            TransactionError::AmountTooLarge { .. }
+        ));
+        assert_eq!(
+            stats.rejections[1].error,
+            TransactionError::UnknownTransaction {
+                transaction_type: TransactionType::Dispute,
+                transaction_id: 2,
+            }
+        );
+        assert_eq!(stats.rejections.len(), 3);
+        // Unwrap used here as we can say for certainty that the client record with id=1_u16 exists
+        let client_record = client_db.get_client_record(&1).unwrap();
+        assert_eq!(client_record.available, dec!(10.0));
+        assert_eq!(client_record.held, dec!(0.0));
+        assert!(!client_record.locked);
+        Ok(())
     }
 }
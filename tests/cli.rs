@@ -0,0 +1,178 @@
+use assert_cmd::Command;
+
+#[test]
+fn nonexistent_path_exits_with_invalid_path_code() -> Result<(), Box<dyn std::error::Error>> {
+    // A transaction file that does not exist is reported with its own exit code, distinct from a bad file
+    // or a failed write, and no client output is written.
+    Command::cargo_bin("transaction_engine")?
+        .arg("not_a_valid_path.csv")
+        .assert()
+        .code(4)
+        .stdout(
+            "Error opening transaction input: not_a_valid_path.csv: No such file or directory (os error 2)\n",
+        );
+    Ok(())
+}
+
+#[test]
+fn malformed_file_exits_with_input_error_code() -> Result<(), Box<dyn std::error::Error>> {
+    // Under --strict a row that cannot be parsed aborts the run as an input error.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("transactions.csv");
+    std::fs::write(&path, "type,client,tx,amount\nbogus,1,1,1.0\n")?;
+    Command::cargo_bin("transaction_engine")?
+        .arg(&path)
+        .arg("--strict")
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+#[test]
+fn threaded_run_matches_single_threaded_run() -> Result<(), Box<dyn std::error::Error>> {
+    // Clients sharded across worker threads end up with the same balances as a single-threaded run,
+    // including those whose deposits were disputed and charged back.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("transactions.csv");
+    let mut data = String::from("type,client,tx,amount\n");
+    for tx in 1..=200 {
+        data.push_str(&format!("deposit,{},{},{}.5\n", tx % 17, tx, tx));
+        if tx % 5 == 0 {
+            data.push_str(&format!("withdrawal,{},{},3.25\n", tx % 17, tx + 1_000));
+        }
+        if tx % 7 == 0 {
+            data.push_str(&format!("dispute,{},{},\n", tx % 17, tx));
+        }
+        if tx % 14 == 0 {
+            data.push_str(&format!("chargeback,{},{},\n", tx % 17, tx));
+        }
+    }
+    std::fs::write(&path, data)?;
+    let run = |args: &[&str]| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let output = Command::cargo_bin("transaction_engine")?
+            .arg(&path)
+            .args(args)
+            .output()?;
+        assert!(output.status.success());
+        Ok(output.stdout)
+    };
+    let single_threaded = run(&[])?;
+    assert_eq!(run(&["--threads", "4"])?, single_threaded);
+    assert_eq!(run(&["--threads", "1"])?, single_threaded);
+    Ok(())
+}
+
+#[test]
+fn precision_applies_to_amounts_read_and_balances_written() -> Result<(), Box<dyn std::error::Error>>
+{
+    // A deposit is read and written with 8 decimals under --precision 8, and with 2 under --precision 2.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("transactions.csv");
+    std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.123456789\n")?;
+    for (precision, expected) in [
+        (
+            "8",
+            "client,available,held,total,locked\n1,1.12345679,0.00000000,1.12345679,false\n",
+        ),
+        (
+            "2",
+            "client,available,held,total,locked\n1,1.12,0.00,1.12,false\n",
+        ),
+    ] {
+        Command::cargo_bin("transaction_engine")?
+            .arg(&path)
+            .args([
+                "--precision",
+                precision,
+                "--canonical-amount-format",
+                "fixed",
+            ])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn scenarios_write_labeled_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    // The withdrawal is only applied in the scenario without a minimum balance, so the labeled balances differ.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("transactions.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,8.0\n",
+    )?;
+    Command::cargo_bin("transaction_engine")?
+        .arg(&path)
+        .args([
+            "--scenario",
+            "default=",
+            "--scenario",
+            "floor=--minimum-balance 5",
+        ])
+        .assert()
+        .success()
+        .stdout(
+            "# scenario: default\nclient,available,held,total,locked\n1,2.0,0.0,2.0,false\n\
+             # scenario: floor\nclient,available,held,total,locked\n1,10.0,0.0,10.0,false\n",
+        );
+    Ok(())
+}
+
+#[test]
+fn dry_run_reports_errors_without_output() -> Result<(), Box<dyn std::error::Error>> {
+    // A file with an unknown transaction type and a non-numeric amount reports both, and writes no balances.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("transactions.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,5.0\nbogus,1,2,1.0\ndeposit,1,3,abc\n",
+    )?;
+    let output = Command::cargo_bin("transaction_engine")?
+        .arg(&path)
+        .arg("--dry-run")
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(stderr.lines().count(), 3);
+    assert!(stderr.ends_with("dry run: 3 rows validated, 2 errors found\n"));
+    Ok(())
+}
+
+#[test]
+fn checked_stdin_applied_after_check() -> Result<(), Box<dyn std::error::Error>> {
+    // Stdin can only be read once, so checking its references first must not leave nothing to apply.
+    Command::cargo_bin("transaction_engine")?
+        .args(["-", "--check-references"])
+        .write_stdin("type,client,tx,amount\ndeposit,1,1,10.0\ndispute,1,1,\n")
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,0.0,10.0,10.0,false\n");
+    Ok(())
+}
+
+#[test]
+fn overflowing_totals_reported_without_panic() -> Result<(), Box<dyn std::error::Error>> {
+    // Two clients near the largest representable balance each fit, but their summed funds do not; the run still
+    // succeeds, and the summary and metadata flag the totals instead of aborting.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("transactions.csv");
+    let meta = dir.path().join("meta.json");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,50000000000000000000000000000\ndeposit,2,2,50000000000000000000000000000\n",
+    )?;
+    let output = Command::cargo_bin("transaction_engine")?
+        .arg(&path)
+        .arg("--meta")
+        .arg(&meta)
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .ends_with("2 clients with overflowed available and 0 held\n"));
+    let meta = std::fs::read_to_string(&meta)?;
+    assert!(meta.contains("\"total_available\": null"));
+    Ok(())
+}